@@ -0,0 +1,24 @@
+use actix_web::http::StatusCode;
+
+pub use lanther_common::error::{ErrorCode, JsonError};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApiError {
+    #[error("Payload exceeds the maximum size of {0} bytes")]
+    PayloadTooLarge(usize),
+    #[error("IPFS operation failed: {0}")]
+    IpfsError(String),
+}
+
+impl From<ApiError> for JsonError {
+    fn from(err: ApiError) -> Self {
+        let (status, code) = match err {
+            ApiError::PayloadTooLarge(_) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, ErrorCode::PayloadTooLarge)
+            }
+            ApiError::IpfsError(_) => (StatusCode::BAD_GATEWAY, ErrorCode::UpstreamUnavailable),
+        };
+
+        JsonError::new(status, code, err.to_string())
+    }
+}
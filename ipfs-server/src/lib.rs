@@ -0,0 +1,52 @@
+//! The ipfs-server, which uploads files to IPFS on behalf of the proxy-server
+//!
+//! The server is a library so it can also be started in-process, like the end-to-end tests do.
+use std::io;
+use std::net::TcpListener;
+
+use actix_web::dev::Server;
+use actix_web::{self, web, HttpServer};
+use lanther_common::handlers;
+use metrics::Metrics;
+use settings::Settings;
+
+mod error;
+mod metrics;
+mod middlewares;
+mod routes;
+pub mod settings;
+
+/// Start the server on a listener, uploading files to the IPFS API in the settings
+///
+/// The settings must have been validated.
+pub fn serve(settings: &Settings, listener: TcpListener) -> io::Result<Server> {
+    log::info!("Starting IPFS Server on: {}", listener.local_addr()?);
+
+    let ipfs = settings.ipfs.clone();
+    let metrics = Metrics::new();
+    let server = HttpServer::new(move || {
+        actix_web::App::new()
+            .wrap(middlewares::Traced)
+            .wrap(middlewares::Metered::new(
+                metrics.http_requests.clone(),
+                metrics.http_request_duration.clone(),
+                "unmatched",
+            ))
+            .data(ipfs.client())
+            .default_service(web::route().to(handlers::not_found))
+            .service(routes::healthz)
+            .service(routes::readyz)
+            .data(metrics.clone())
+            .service(routes::get_metrics)
+            .service(
+                web::scope("/")
+                    .service(routes::index)
+                    .service(routes::upload),
+            )
+    })
+    .listen(listener)?
+    // On SIGTERM or SIGINT, stop accepting connections and let in-flight uploads finish
+    .shutdown_timeout(settings.server.shutdown_timeout)
+    .run();
+    Ok(server)
+}
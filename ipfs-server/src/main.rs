@@ -1,8 +1,14 @@
-use actix_web::{self, web, HttpServer};
+use std::net::TcpListener;
+
 use clap::{self, Arg};
-use ipfs_api::IpfsClient;
+use ipfs_server::serve;
+use ipfs_server::settings::Settings;
 
-mod routes;
+/// Report an error that prevents the server from starting and exit
+fn exit_with(e: impl std::fmt::Display) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1)
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -16,25 +22,28 @@ async fn main() -> std::io::Result<()> {
             Arg::with_name("ADDRESS")
                 .help("The address the IPFS server will be listening to")
                 .takes_value(true)
-                .index(1)
-                .required(true),
+                .index(1),
+        )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .help("A TOML or YAML configuration file, also read from LANTHER_CONFIG")
+                .takes_value(true),
         )
         .get_matches();
 
-    let address = matches
-        .value_of("ADDRESS")
-        .expect("ADDRESS is a required argument");
-
-    log::info!("Starting IPFS Server on: {}", address);
+    let settings = Settings::load(&matches).unwrap_or_else(|e| exit_with(e));
+    settings.validate().unwrap_or_else(|e| exit_with(e));
+    let address = settings
+        .server
+        .address
+        .as_deref()
+        .expect("server.address is validated");
+    let listener = TcpListener::bind(address)
+        .unwrap_or_else(|e| exit_with(format!("Could not listen on {}: {}", address, e)));
+    serve(&settings, listener)?.await?;
 
-    HttpServer::new(|| {
-        actix_web::App::new().data(IpfsClient::default()).service(
-            web::scope("/")
-                .service(routes::index)
-                .service(routes::upload),
-        )
-    })
-    .bind(address)?
-    .run()
-    .await
+    log::info!("IPFS Server stopped");
+    log::logger().flush();
+    Ok(())
 }
@@ -0,0 +1,67 @@
+use lanther_common::middlewares::HTTP_LABELS;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+/// Prometheus metrics of the ipfs-server
+///
+/// Metrics are kept in their own registry, which is cheap to clone and share across workers.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub uploaded_bytes: IntCounter,
+    pub ipfs_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &HTTP_LABELS,
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests",
+            ),
+            &HTTP_LABELS,
+        )
+        .unwrap();
+        let uploaded_bytes =
+            IntCounter::new("ipfs_uploaded_bytes_total", "Bytes uploaded to IPFS").unwrap();
+        let ipfs_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "ipfs_request_duration_seconds",
+                "Time taken by the IPFS daemon to respond, by operation",
+            ),
+            &["operation"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(uploaded_bytes.clone())).unwrap();
+        registry.register(Box::new(ipfs_duration.clone())).unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            uploaded_bytes,
+            ipfs_duration,
+        }
+    }
+
+    /// Render all metrics in the Prometheus text format
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
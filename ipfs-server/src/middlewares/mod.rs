@@ -0,0 +1 @@
+pub use lanther_common::middlewares::{Metered, Traced};
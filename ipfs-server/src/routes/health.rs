@@ -0,0 +1,39 @@
+//! Liveness and readiness probes
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use actix_web::{get, web, HttpResponse};
+use ipfs_api::IpfsClient;
+use lanther_common::{health, Envelope};
+
+use crate::metrics::Metrics;
+use serde_json::json;
+
+/// How long a dependency may take to answer before it is considered down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The server is alive as long as it can answer, regardless of its dependencies
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(Envelope::ok(json!({ "status": "ok" })))
+}
+
+/// The server is ready to take uploads when the IPFS daemon answers with its version
+#[get("/readyz")]
+pub async fn readyz(client: web::Data<IpfsClient>, metrics: web::Data<Metrics>) -> HttpResponse {
+    let mut checks = BTreeMap::new();
+    checks.insert(
+        "ipfs".to_string(),
+        health::check(CHECK_TIMEOUT, async {
+            let _timer = metrics
+                .ipfs_duration
+                .with_label_values(&["version"])
+                .start_timer();
+            client.version().await?;
+            Ok::<_, ipfs_api::response::Error>(())
+        })
+        .await,
+    );
+
+    health::respond(checks)
+}
@@ -1,11 +1,18 @@
 use std::boxed::Box;
 use std::io::Cursor;
 
-use actix_web::{error, get, post, web, Error, HttpResponse};
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, Error, HttpRequest, HttpResponse};
 use futures::StreamExt;
 use ipfs_api::IpfsClient;
 use serde::Serialize;
 
+use super::error::{ApiError, ErrorCode, JsonError};
+use super::metrics::Metrics;
+
+mod health;
+pub use health::{healthz, readyz};
+
 #[get("")]
 async fn index(_client: web::Data<IpfsClient>) -> HttpResponse {
     HttpResponse::Ok().body("Listening")
@@ -21,32 +28,71 @@ struct IpfsResponse {
 
 const MAX_SIZE: usize = 262_144; // max payload size is 256k
 
+/// Header set by the proxy with the largest body the API key of a request may upload
+///
+/// The ipfs-server is only meant to be reached through the proxy, which sets this header itself.
+const MAX_PAYLOAD_SIZE: &str = "x-lanther-max-payload-size";
+
+/// The payload size allowed for a request, which may only lower the server's own maximum
+fn max_size(req: &HttpRequest) -> usize {
+    req.headers()
+        .get(MAX_PAYLOAD_SIZE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<usize>().ok())
+        .map_or(MAX_SIZE, |max| max.min(MAX_SIZE))
+}
+
 #[post("")]
 async fn upload(
+    req: HttpRequest,
     mut payload: web::Payload,
     client: web::Data<IpfsClient>,
+    metrics: web::Data<Metrics>,
 ) -> Result<HttpResponse, Error> {
+    let max_size = max_size(&req);
     let mut body = web::BytesMut::new();
     while let Some(chunk) = payload.next().await {
         let chunk = chunk?;
         // limit max size of in-memory payload
-        if (body.len() + chunk.len()) > MAX_SIZE {
-            return Err(error::ErrorBadRequest("overflow"));
+        if (body.len() + chunk.len()) > max_size {
+            return Err(JsonError::from(ApiError::PayloadTooLarge(max_size)).into());
         }
         body.extend_from_slice(&chunk);
     }
+    let size = body.len();
     let data = Cursor::new(body);
     // heap allocation required otherwise cursor does not live long enough
     let boxed = Box::new(data);
-    match client.add(*boxed).await {
-        Ok(res) => Ok(HttpResponse::Ok().json(IpfsResponse {
-            hash: res.hash,
-            name: res.name,
-            size: res.size,
-        })),
-        Err(e) => Err(error::ErrorInternalServerError(format!(
-            "Something went horribly wrong: {:?}",
-            e
-        ))),
+    let timer = metrics
+        .ipfs_duration
+        .with_label_values(&["add"])
+        .start_timer();
+    let result = client.add(*boxed).await;
+    timer.observe_duration();
+    match result {
+        Ok(res) => {
+            metrics.uploaded_bytes.inc_by(size as u64);
+            Ok(HttpResponse::Ok().json(IpfsResponse {
+                hash: res.hash,
+                name: res.name,
+                size: res.size,
+            }))
+        }
+        Err(e) => Err(JsonError::from(ApiError::IpfsError(e.to_string())).into()),
     }
 }
+
+/// Expose metrics in the Prometheus text format
+#[get("/metrics")]
+pub async fn get_metrics(metrics: web::Data<Metrics>) -> Result<HttpResponse, JsonError> {
+    let body = metrics.render().map_err(|e| {
+        JsonError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::InternalError,
+            e.to_string(),
+        )
+    })?;
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
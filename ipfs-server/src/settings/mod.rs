@@ -0,0 +1,107 @@
+use std::env;
+use std::net::ToSocketAddrs;
+use std::path::PathBuf;
+
+use clap::ArgMatches;
+use config::{Config, Environment, File};
+use ipfs_api::{IpfsClient, TryFromUri};
+use serde::Deserialize;
+
+/// Prefix of the environment variables that override the configuration file
+pub const ENV_PREFIX: &str = "LANTHER";
+
+#[derive(thiserror::Error, Debug)]
+pub enum SettingsError {
+    #[error("Could not load configuration: {0}")]
+    Load(#[from] config::ConfigError),
+    #[error("Invalid configuration:\n  - {}", .0.join("\n  - "))]
+    Invalid(Vec<String>),
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ServerSettings {
+    pub address: Option<String>,
+    /// Seconds to let in-flight requests finish after a shutdown signal
+    pub shutdown_timeout: u64,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct IpfsSettings {
+    /// The URL of the IPFS daemon's API, defaults to http://localhost:5001
+    pub url: Option<String>,
+}
+
+impl IpfsSettings {
+    pub fn client(&self) -> IpfsClient {
+        match &self.url {
+            Some(url) => IpfsClient::from_str(url).expect("ipfs.url is validated"),
+            None => IpfsClient::default(),
+        }
+    }
+}
+
+/// Configuration of the ipfs-server
+///
+/// Settings are layered, each layer overriding the ones before it: defaults, a TOML or YAML
+/// configuration file, environment variables prefixed with `LANTHER_`, and command line
+/// arguments. Nested settings are separated by a double underscore in environment variables, so
+/// `ipfs.url` is set with `LANTHER_IPFS__URL`.
+#[derive(Debug, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub server: ServerSettings,
+    #[serde(default)]
+    pub ipfs: IpfsSettings,
+}
+
+impl Settings {
+    pub fn load(matches: &ArgMatches) -> Result<Self, SettingsError> {
+        let mut builder = Config::builder().set_default("server.shutdown_timeout", 30)?;
+
+        let path = matches
+            .value_of("config")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os(format!("{}_CONFIG", ENV_PREFIX)).map(PathBuf::from));
+        if let Some(path) = path {
+            builder = builder.add_source(File::from(path.as_path()).required(true));
+        }
+
+        builder = builder.add_source(
+            Environment::with_prefix(ENV_PREFIX)
+                .prefix_separator("_")
+                .separator("__"),
+        );
+
+        if let Some(address) = matches.value_of("ADDRESS") {
+            builder = builder.set_override("server.address", address)?;
+        }
+
+        Ok(builder.build()?.try_deserialize()?)
+    }
+
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let mut errors = Vec::new();
+        match &self.server.address {
+            None => errors.push(format!(
+                "server.address is required: set it in the configuration file, with {}_SERVER__ADDRESS, or with the ADDRESS argument",
+                ENV_PREFIX
+            )),
+            Some(address) if address.to_socket_addrs().is_err() => errors.push(format!(
+                "server.address `{}` is not a valid address to listen on, like 127.0.0.1:8082",
+                address
+            )),
+            Some(_) => {}
+        }
+        if let Some(url) = &self.ipfs.url {
+            if IpfsClient::from_str(url).is_err() {
+                errors.push(format!("ipfs.url `{}` is not a valid URL", url));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(SettingsError::Invalid(errors))
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use actix_web::http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+/// The envelope of every successful response, wrapping its payload
+///
+/// Error responses are enveloped by `JsonError` instead, with an `error` in place of a `payload`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Envelope<T> {
+    pub status: u16,
+    pub success: bool,
+    pub payload: T,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(status: StatusCode, payload: T) -> Self {
+        Envelope {
+            status: status.as_u16(),
+            success: status.is_success(),
+            payload,
+        }
+    }
+
+    pub fn ok(payload: T) -> Self {
+        Envelope::new(StatusCode::OK, payload)
+    }
+
+    pub fn created(payload: T) -> Self {
+        Envelope::new(StatusCode::CREATED, payload)
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Header identifying a request in both requests and responses
+pub const REQUEST_ID: &str = "x-request-id";
+
+/// Validation messages keyed by the path of the offending field
+pub type FieldErrors = BTreeMap<String, String>;
+
+/// Stable, machine-readable error codes that clients can branch on
+///
+/// Codes are shared by all lanther servers, and must never be renamed once released.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    NotFound,
+    MethodNotAllowed,
+    InternalError,
+    DatabaseError,
+    InvalidJson,
+    ValidationFailed,
+    PayloadTooLarge,
+    ApikeyRequired,
+    ApikeyNotFound,
+    ApikeyDisabled,
+    ApikeyExpired,
+    ApikeyRevoked,
+    OutsideAllowedWindow,
+    ScopeNotAllowed,
+    LockedDown,
+    RateLimited,
+    QuotaExceeded,
+    ChildKeyNotAllowed,
+    ReporterUnauthorized,
+    AdminUnauthorized,
+    VersionMismatch,
+    IdempotencyKeyInvalid,
+    IdempotencyKeyInProgress,
+    IdempotencyKeyMismatch,
+    PlanNotFound,
+    PlanExists,
+    PlanInUse,
+    PrincipalNotFound,
+    PrincipalExists,
+    PrincipalInUse,
+    KeyQuotaExceeded,
+    LockdownNotFound,
+    SignupTokenInvalid,
+    AuthUnavailable,
+    UpstreamUnavailable,
+    SandboxUnavailable,
+    EmailUnavailable,
+}
+
+impl ErrorCode {
+    /// Fallback code for errors not raised by lanther itself, like actix's routing errors
+    pub fn from_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::METHOD_NOT_ALLOWED => ErrorCode::MethodNotAllowed,
+            StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::PayloadTooLarge,
+            s if s.is_client_error() => ErrorCode::BadRequest,
+            _ => ErrorCode::InternalError,
+        }
+    }
+}
+
+/// Describes an error in the body of an error response
+#[derive(Debug, Serialize, Clone)]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+/// The envelope of every error response
+#[derive(Debug, Serialize, Clone)]
+pub struct JsonError {
+    pub status: u16,
+    pub success: bool,
+    pub error: ErrorBody,
+    /// Seconds after which the request may be retried, sent in the Retry-After header
+    #[serde(skip)]
+    pub retry_after: Option<u64>,
+}
+
+impl JsonError {
+    pub fn new(status: StatusCode, code: ErrorCode, message: impl Into<String>) -> Self {
+        JsonError {
+            status: status.as_u16(),
+            success: false,
+            error: ErrorBody {
+                code,
+                message: message.into(),
+                request_id: None,
+                details: None,
+            },
+            retry_after: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.error.details = Some(details);
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: &str) -> Self {
+        self.error.request_id = Some(request_id.to_string());
+        self
+    }
+
+    pub fn with_retry_after(mut self, retry_after: Option<u64>) -> Self {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Convert any error raised while handling a request, whether by us or by actix
+    pub fn from_actix(err: &actix_web::Error) -> Self {
+        match err.as_error::<JsonError>() {
+            Some(err) => err.clone(),
+            None => {
+                let status = err.as_response_error().status_code();
+                JsonError::new(status, ErrorCode::from_status(status), err.to_string())
+            }
+        }
+    }
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let err_json = serde_json::to_string(self).unwrap();
+        write!(f, "{}", err_json)
+    }
+}
+
+impl ResponseError for JsonError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Some(request_id) = &self.error.request_id {
+            response.header(REQUEST_ID, request_id.as_str());
+        }
+        if let Some(retry_after) = self.retry_after {
+            response.header(header::RETRY_AFTER, retry_after);
+        }
+        response.json2(self)
+    }
+}
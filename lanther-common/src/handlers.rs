@@ -0,0 +1,26 @@
+//! Handlers every lanther server registers, to keep errors raised outside of its routes within
+//! the `JsonError` envelope
+use actix_web::error::JsonPayloadError;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+
+use crate::error::{ErrorCode, JsonError};
+
+/// Default service for requests matching no route
+pub async fn not_found() -> Result<HttpResponse, JsonError> {
+    Err(JsonError::new(
+        StatusCode::NOT_FOUND,
+        ErrorCode::NotFound,
+        "No route matches the request",
+    ))
+}
+
+/// Reject request bodies that cannot be deserialized, for use in a `web::JsonConfig`
+pub fn json_error_handler(err: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    JsonError::new(
+        StatusCode::BAD_REQUEST,
+        ErrorCode::InvalidJson,
+        err.to_string(),
+    )
+    .into()
+}
@@ -0,0 +1,48 @@
+//! Helpers for the readiness probes of every lanther server
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use actix_web::http::StatusCode;
+use actix_web::rt::time::timeout;
+use actix_web::HttpResponse;
+
+use crate::envelope::Envelope;
+use crate::models::{Check, Readiness};
+
+/// Check a single dependency, which is considered down if it takes longer than `limit`
+pub async fn check<F, E>(limit: Duration, fut: F) -> Check
+where
+    F: Future<Output = Result<(), E>>,
+    E: Display,
+{
+    let start = Instant::now();
+    let error = match timeout(limit, fut).await {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(e.to_string()),
+        Err(_) => Some(format!("timed out after {:?}", limit)),
+    };
+    Check {
+        status: if error.is_none() { "ok" } else { "error" }.to_string(),
+        latency_ms: start.elapsed().as_millis() as u64,
+        error,
+    }
+}
+
+/// Answer a readiness probe, with a `503 Service Unavailable` unless every check passed
+pub fn respond(checks: BTreeMap<String, Check>) -> HttpResponse {
+    let ready = checks.values().all(|c| c.error.is_none());
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    HttpResponse::build(status).json(Envelope::new(
+        status,
+        Readiness {
+            status: if ready { "ok" } else { "degraded" }.to_string(),
+            checks,
+        },
+    ))
+}
@@ -0,0 +1,17 @@
+//! Building blocks shared by every lanther server, so they all behave the same way
+//!
+//! This covers the envelope of successful responses, the `JsonError` envelope of error
+//! responses along with its stable error codes, the models exchanged with the
+//! simpleapikeys-server, and actix middlewares and handlers every server registers.
+//!
+//! Each server keeps its own `ApiError`, converted into a `JsonError` with the status and code
+//! that fit it.
+pub mod envelope;
+pub mod error;
+pub mod handlers;
+pub mod health;
+pub mod middlewares;
+pub mod models;
+
+pub use envelope::Envelope;
+pub use error::{ErrorBody, ErrorCode, FieldErrors, JsonError, REQUEST_ID};
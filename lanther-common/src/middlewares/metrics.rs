@@ -0,0 +1,111 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::Error;
+use futures::future::{ok, Ready};
+use futures::Future;
+use prometheus::{HistogramVec, IntCounterVec};
+
+/// Labels of the HTTP request metrics, which `Metered` gives values to in this order
+pub const HTTP_LABELS: [&str; 3] = ["method", "route", "status"];
+
+/// Middleware to count and time every request, labelled by the route pattern it matched
+///
+/// Routes are labelled by their pattern, like `/apikeys/{key}`, rather than their path, to keep
+/// the number of series bounded. Requests matching no route are all given the same label, like
+/// `unmatched`, or `forwarded` for the proxy-server, which forwards them upstream.
+pub struct Metered {
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    unmatched: &'static str,
+}
+
+impl Metered {
+    /// Count and time requests in metrics labelled with `HTTP_LABELS`, labelling the route of
+    /// requests matching no route as `unmatched`
+    pub fn new(
+        http_requests: IntCounterVec,
+        http_request_duration: HistogramVec,
+        unmatched: &'static str,
+    ) -> Self {
+        Metered {
+            http_requests,
+            http_request_duration,
+            unmatched,
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Metered
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MeteredMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MeteredMiddleware {
+            service,
+            http_requests: self.http_requests.clone(),
+            http_request_duration: self.http_request_duration.clone(),
+            unmatched: self.unmatched,
+        })
+    }
+}
+
+pub struct MeteredMiddleware<S> {
+    service: S,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    unmatched: &'static str,
+}
+
+impl<S, B> Service for MeteredMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let method = req.method().to_string();
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| self.unmatched.to_string());
+        let http_requests = self.http_requests.clone();
+        let http_request_duration = self.http_request_duration.clone();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await;
+            let status = match &res {
+                Ok(res) => res.status(),
+                Err(e) => e.as_response_error().status_code(),
+            };
+            let labels = [method.as_str(), route.as_str(), status.as_str()];
+            http_requests.with_label_values(&labels).inc();
+            http_request_duration
+                .with_label_values(&labels)
+                .observe(start.elapsed().as_secs_f64());
+            res
+        })
+    }
+}
@@ -0,0 +1,5 @@
+pub mod metrics;
+pub mod trace;
+
+pub use metrics::{Metered, HTTP_LABELS};
+pub use trace::{RequestId, Traced};
@@ -0,0 +1,122 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::body::{Body, MessageBody, ResponseBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use actix_web::{Error, HttpMessage, ResponseError};
+use futures::future::{ok, Ready};
+use futures::Future;
+use uuid::Uuid;
+
+use crate::error::{JsonError, REQUEST_ID};
+
+/// Maximum length of a request ID sent by a client
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// The ID of the request being handled, available in the request extensions
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+/// Middleware to identify every request, and render every error in the JsonError envelope
+///
+/// The request ID is taken from the X-Request-Id header when the client sends a valid one, and
+/// generated otherwise. It is returned in the X-Request-Id header of every response, and in the
+/// body of error responses, including those for errors raised by actix itself.
+pub struct Traced;
+
+impl<S, B> Transform<S> for Traced
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TracedMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TracedMiddleware { service })
+    }
+}
+
+pub struct TracedMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for TracedMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID)
+            .and_then(|h| h.to_str().ok())
+            .filter(|id| is_valid_request_id(id))
+            .map(|id| id.to_string())
+            .unwrap_or_else(|| Uuid::new_v4().to_hyphenated().to_string());
+        req.extensions_mut().insert(RequestId(request_id.clone()));
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = match fut.await {
+                Ok(res) => res.map_body(|_, body| ResponseBody::Other(Body::from_message(body))),
+                // Without the request there is no response to amend, so the error is rendered
+                // further up, headers included, from the JsonError itself
+                Err(e) => {
+                    return Err(JsonError::from_actix(&e)
+                        .with_request_id(&request_id)
+                        .into())
+                }
+            };
+
+            let mut res = match res.response().error() {
+                Some(err) => {
+                    let error = JsonError::from_actix(err).with_request_id(&request_id);
+                    let mut error_response = error.error_response();
+                    // Keep any headers the original error set, like WWW-Authenticate
+                    for (name, value) in res.headers() {
+                        if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+                            error_response
+                                .headers_mut()
+                                .insert(name.clone(), value.clone());
+                        }
+                    }
+                    res.into_response(error_response)
+                }
+                None => res,
+            };
+
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID), value);
+            }
+            Ok(res)
+        })
+    }
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
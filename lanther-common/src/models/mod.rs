@@ -0,0 +1,468 @@
+//! Types exchanged with the simpleapikeys-server
+//!
+//! The server responds with these very types, so they cannot drift from what clients expect.
+use std::collections::BTreeMap;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+mod schedule;
+
+pub use schedule::{minutes, Schedule, Window, WEEKDAYS};
+
+/// Represents an API key as defined by a UUID
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKey {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub key: Uuid,
+    #[serde(default)]
+    pub environment: Environment,
+    /// The key this one was delegated from, if it is a child key
+    pub parent: Option<Uuid>,
+    pub owner: Option<String>,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub schedule: Option<Schedule>,
+    #[serde(default)]
+    pub limits: Limits,
+    /// The name of the plan the key is on, if any
+    pub plan: Option<String>,
+    /// The name of the principal the key acts for, if any
+    #[serde(default)]
+    pub principal: Option<String>,
+    pub disabled: bool,
+    /// When the key was revoked, after which it can never be enabled again
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub revocation_reason: Option<String>,
+    /// The version is bumped on every write and is exposed to clients as an ETag
+    pub version: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    /// Resolve the scopes and limits that apply to the key, given its plan
+    ///
+    /// Limits set on the key itself take precedence over the plan's. A plan's scopes bound those
+    /// of its keys, and a key with no scopes of its own is granted all of its plan's. A key with
+    /// no scopes on a plan with none is not restricted to any.
+    pub fn verify(&self, plan: Option<&Plan>) -> Verification {
+        let plan_scopes = plan.map(|plan| plan.scopes.as_slice()).unwrap_or_default();
+        let scopes = if self.scopes.is_empty() && plan_scopes.is_empty() {
+            None
+        } else if self.scopes.is_empty() {
+            Some(plan_scopes.to_vec())
+        } else if plan_scopes.is_empty() {
+            Some(self.scopes.clone())
+        } else {
+            Some(
+                self.scopes
+                    .iter()
+                    .filter(|scope| plan_scopes.contains(scope))
+                    .cloned()
+                    .collect(),
+            )
+        };
+
+        Verification {
+            key: self.key,
+            environment: self.environment,
+            parent: self.parent,
+            disabled: self.disabled,
+            expired: self.expires_at.is_some_and(|at| at <= Utc::now()),
+            locked_down: false,
+            principal: None,
+            plan: self.plan.clone(),
+            scopes,
+            schedules: self.schedule.iter().cloned().collect(),
+            limits: match plan {
+                Some(plan) => self.limits.or(plan.limits),
+                None => self.limits,
+            },
+        }
+    }
+}
+
+/// Everything needed to authorize a request made with an API key
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Verification {
+    pub key: Uuid,
+    #[serde(default)]
+    pub environment: Environment,
+    pub parent: Option<Uuid>,
+    pub disabled: bool,
+    pub expired: bool,
+    /// Whether a lockdown, of everyone or of the key's owner, stops the key from being used
+    #[serde(default)]
+    pub locked_down: bool,
+    /// Who the key acts for, which is disabled along with the key when the principal is
+    #[serde(default)]
+    pub principal: Option<VerifiedPrincipal>,
+    pub plan: Option<String>,
+    /// The scopes requests may be made in, or none when the key is not restricted to any
+    ///
+    /// A key may end up with no scopes at all, when those of its plan or parent leave it none.
+    #[serde(default)]
+    pub scopes: Option<Vec<String>>,
+    /// Schedules that must all allow a request, those of the key and of its parent
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+    pub limits: Limits,
+}
+
+impl Verification {
+    /// The key this one descends from, which is itself unless it is a child key
+    ///
+    /// Child keys cannot have children of their own, so this is the parent of a child key.
+    pub fn root(&self) -> Uuid {
+        self.parent.unwrap_or(self.key)
+    }
+
+    /// Bound the verification of a child key by that of its parent
+    ///
+    /// A child key is disabled, expired or locked down along with its parent, acts for the same
+    /// principal, and never gets scopes or limits its parent does not have, even if the parent's
+    /// were narrowed after the child was created. It may only be used when the schedules of both
+    /// allow it.
+    pub fn bounded_by(mut self, parent: &Verification) -> Verification {
+        self.disabled |= parent.disabled;
+        self.expired |= parent.expired;
+        self.locked_down |= parent.locked_down;
+        // Children act for whoever their parent acts for, even after the parent is moved
+        self.principal = parent.principal.clone();
+        if let Some(parent_scopes) = &parent.scopes {
+            match &mut self.scopes {
+                Some(scopes) => scopes.retain(|scope| parent_scopes.contains(scope)),
+                None => self.scopes = Some(parent_scopes.clone()),
+            }
+        }
+        self.limits = self.limits.min(parent.limits);
+        self.schedules.extend(parent.schedules.iter().cloned());
+        self
+    }
+}
+
+/// Usage limits of an API key, where a missing limit means unlimited
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct Limits {
+    pub requests_per_minute: Option<i64>,
+    pub monthly_quota: Option<i64>,
+    pub max_payload_size: Option<i64>,
+}
+
+impl Limits {
+    pub const FIELDS: [&'static str; 3] =
+        ["requests_per_minute", "monthly_quota", "max_payload_size"];
+
+    /// Take the lowest of each limit, where a missing limit is higher than any other
+    pub fn min(self, other: Limits) -> Limits {
+        let min = |a: Option<i64>, b: Option<i64>| match (a, b) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        Limits {
+            requests_per_minute: min(self.requests_per_minute, other.requests_per_minute),
+            monthly_quota: min(self.monthly_quota, other.monthly_quota),
+            max_payload_size: min(self.max_payload_size, other.max_payload_size),
+        }
+    }
+
+    /// Fill in the limits missing from these with those of `other`
+    pub fn or(self, other: Limits) -> Limits {
+        Limits {
+            requests_per_minute: self.requests_per_minute.or(other.requests_per_minute),
+            monthly_quota: self.monthly_quota.or(other.monthly_quota),
+            max_payload_size: self.max_payload_size.or(other.max_payload_size),
+        }
+    }
+}
+
+/// Whether an API key is for real traffic, or for testing against a sandbox
+///
+/// API keys created before environments were introduced are live.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Live,
+    Test,
+}
+
+impl Environment {
+    pub fn parse(environment: &str) -> Option<Self> {
+        match environment {
+            "live" => Some(Environment::Live),
+            "test" => Some(Environment::Test),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Environment::Live => "live",
+            Environment::Test => "test",
+        }
+    }
+}
+
+/// What a principal is: a human, or a machine identity like a CI pipeline
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrincipalKind {
+    User,
+    ServiceAccount,
+}
+
+impl PrincipalKind {
+    pub fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "user" => Some(PrincipalKind::User),
+            "service_account" => Some(PrincipalKind::ServiceAccount),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PrincipalKind::User => "user",
+            PrincipalKind::ServiceAccount => "service_account",
+        }
+    }
+}
+
+/// A user or a service account API keys act for, which disables all of them while disabled
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Principal {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub name: String,
+    pub kind: PrincipalKind,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    pub disabled: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The principal a verified API key acts for
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct VerifiedPrincipal {
+    pub name: String,
+    pub kind: PrincipalKind,
+}
+
+/// A usage plan, whose limits and allowed scopes apply to every API key on it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Plan {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub name: String,
+    pub limits: Limits,
+    /// Scopes API keys on the plan may be granted, where none means any scope
+    pub scopes: Vec<String>,
+    /// Active API keys there may be on the plan, where none means unlimited
+    #[serde(default)]
+    pub max_active_keys: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// States an API key may be in, each key being in exactly one of them
+///
+/// Revoked keys are also disabled, and are only counted as revoked. Expired keys are those
+/// past their expiry that are neither disabled nor revoked.
+pub const STATES: [&str; 4] = ["active", "disabled", "expired", "revoked"];
+
+/// Counts of API keys across the whole inventory
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ApiKeyStats {
+    pub total: i64,
+    /// Every state in `STATES`, including those no key is in
+    pub by_state: Vec<Group>,
+    /// Keys with no owner or no plan are counted under a `null` name
+    pub by_owner: Vec<Group>,
+    pub by_plan: Vec<Group>,
+    /// Months are given as `YYYY-MM`, in UTC
+    pub by_month: Vec<Group>,
+}
+
+/// How many API keys share a value, like an owner or a plan
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Group {
+    pub name: Option<String>,
+    pub count: i64,
+}
+
+/// How many active API keys an owner has, against how many it may have
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OwnerUsage {
+    pub owner: String,
+    pub active: i64,
+    /// Where none means unlimited
+    pub limit: Option<i64>,
+}
+
+/// A page of the API keys matching a search, best matches first
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ApiKeySearch {
+    pub query: String,
+    /// How many API keys match, across every page
+    pub total: i64,
+    /// Starting from 1
+    pub page: i64,
+    pub per_page: i64,
+    pub results: Vec<SearchHit>,
+}
+
+/// An API key matching a search, along with how well it matches
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchHit {
+    /// Higher is better, and only compares to other hits of the same search
+    pub score: i64,
+    pub apikey: ApiKey,
+}
+
+/// How a batch of leaked candidates was handled
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct LeakReportResult {
+    /// Candidates that are API keys, whether revoked by this report or before
+    pub matched: Vec<LeakedKey>,
+    pub unmatched: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LeakedKey {
+    pub key: String,
+    /// Whether the key was revoked by this report, rather than already revoked
+    pub revoked: bool,
+}
+
+/// How many API keys, plans, principals or lockdowns a delete removed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Deleted {
+    pub count: i64,
+}
+
+/// A signup waiting for its email to be verified, which activates its API key
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Signup {
+    pub email: String,
+    /// When the verification link sent to the email stops working
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A lockdown stopping every API key of an owner, or with no owner, of everyone
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Lockdown {
+    /// The owner locked down, or none for a global lockdown
+    pub owner: Option<String>,
+    /// Owners a global lockdown does not apply to
+    pub allow: Vec<String>,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Enables or disables an API key, replacing its `disabled` flag
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct UpdateApiKey {
+    pub key: Uuid,
+    pub disabled: bool,
+}
+
+/// The optional body of a request to create an API key
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct ApiKeyRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Environment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub principal: Option<String>,
+}
+
+/// The body of a request to delegate a child key, where anything not given is inherited
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct ChildKeyRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Schedule>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limits: Option<Limits>,
+}
+
+/// The body of a request to create or replace a plan
+#[derive(Serialize, Debug, Clone)]
+pub struct PlanRequest {
+    pub name: String,
+    pub limits: Limits,
+    pub scopes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_active_keys: Option<i64>,
+}
+
+/// The body of a request to create a principal
+#[derive(Serialize, Debug, Clone)]
+pub struct PrincipalRequest {
+    pub name: String,
+    pub kind: PrincipalKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// The body of a report of leaked API keys
+#[derive(Serialize, Debug, Clone)]
+pub struct LeakReportRequest {
+    pub keys: Vec<String>,
+    /// Where the keys were found, like a public repository
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+}
+
+/// The body of a request to sign up for an API key
+#[derive(Serialize, Debug, Clone)]
+pub struct SignupRequest {
+    pub email: String,
+}
+
+/// The body of a request to lock down everyone, or an owner, where only a global lockdown may
+/// `allow` some owners
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct LockdownRequest {
+    pub reason: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+}
+
+/// Whether the server is ready to take requests, with the outcome of each of its checks
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Readiness {
+    /// Either `ok` or `degraded`
+    pub status: String,
+    pub checks: BTreeMap<String, Check>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Check {
+    pub status: String,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
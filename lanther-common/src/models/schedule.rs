@@ -0,0 +1,106 @@
+use chrono::prelude::*;
+use chrono::Duration;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// Days of the week, as they are given in the windows of a schedule
+pub const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// When an API key may be used, as windows of time on some days of the week in a timezone
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Schedule {
+    /// An IANA timezone, like `Europe/Madrid`
+    pub timezone: String,
+    pub windows: Vec<Window>,
+}
+
+/// A range of time on some days of the week, from `start` up to but excluding `end`
+///
+/// Times are given as `HH:MM`, and `end` may be `24:00` for a window lasting until midnight.
+/// Windows going past midnight are given as two windows, one on each day.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Window {
+    pub days: Vec<String>,
+    pub start: String,
+    pub end: String,
+}
+
+impl Schedule {
+    /// The earliest instant, at or after `now`, at which the schedule allows requests
+    ///
+    /// Returns `now` itself when requests are allowed right away, and `None` when the schedule
+    /// cannot be read or does not open within the next week.
+    pub fn opens_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let tz: Tz = self.timezone.parse().ok()?;
+        let local = now.with_timezone(&tz);
+        let minute = local.hour() * 60 + local.minute();
+        let open = self.windows.iter().any(|window| {
+            window.on(local.weekday())
+                && window
+                    .range()
+                    .is_some_and(|(start, end)| start <= minute && minute < end)
+        });
+        if open {
+            return Some(now);
+        }
+
+        let mut opens_at: Option<DateTime<Utc>> = None;
+        for days in 0..=7 {
+            let date = local.date_naive() + Duration::days(days);
+            for window in self
+                .windows
+                .iter()
+                .filter(|window| window.on(date.weekday()))
+            {
+                let (start, _) = match window.range() {
+                    Some(range) => range,
+                    None => continue,
+                };
+                let start = date.and_hms_opt(start / 60, start % 60, 0)?;
+                // A window starting in a daylight saving gap opens when the clocks go forward
+                let start = tz
+                    .from_local_datetime(&start)
+                    .earliest()
+                    .or_else(|| {
+                        tz.from_local_datetime(&(start + Duration::hours(1)))
+                            .earliest()
+                    })
+                    .map(|start| start.with_timezone(&Utc));
+                if let Some(start) = start.filter(|start| *start > now) {
+                    opens_at = Some(opens_at.map_or(start, |at| at.min(start)));
+                }
+            }
+            if opens_at.is_some() {
+                break;
+            }
+        }
+        opens_at
+    }
+}
+
+impl Window {
+    fn on(&self, weekday: Weekday) -> bool {
+        let day = WEEKDAYS[weekday.num_days_from_monday() as usize];
+        self.days.iter().any(|d| d == day)
+    }
+
+    /// The window as minutes since midnight
+    fn range(&self) -> Option<(u32, u32)> {
+        Some((minutes(&self.start)?, minutes(&self.end)?))
+    }
+}
+
+/// Parse a time of day given as `HH:MM` into minutes since midnight, allowing `24:00`
+pub fn minutes(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.split_once(':')?;
+    if hours.len() != 2 || minutes.len() != 2 {
+        return None;
+    }
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    match (hours, minutes) {
+        (24, 0) => Some(24 * 60),
+        (0..=23, 0..=59) => Some(hours * 60 + minutes),
+        _ => None,
+    }
+}
@@ -0,0 +1,308 @@
+//! An in-process harness to test lanther's full request path, from the proxy-server to IPFS
+//!
+//! `Lanther::start` boots the simpleapikeys-server, the ipfs-server and the proxy-server on
+//! ephemeral ports, with in-memory storage in place of Mongo DB, a fake IPFS API in place of an
+//! IPFS daemon and a fake SMTP server in place of a mail relay, so the whole path can be tested
+//! without Docker. It must be used within an actix
+//! runtime, like the one `#[actix_rt::test]` starts.
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use actix_web::dev::Server;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use ipfs_server::settings as ipfs_settings;
+use proxy_server::settings as proxy_settings;
+use serde_json::json;
+use simpleapikeys_server::settings as apikeys_settings;
+use url::Url;
+
+/// A master key for the simpleapikeys-server, as any 32 byte key will do in tests
+const MASTER_KEY: &str = "NDlk7+dlolk+N6F0+3RvpZIIsRBCYZUxSAZuQR7AN30=";
+
+/// The admin token of the simpleapikeys-server, which must be at least 32 characters long
+pub const ADMIN_TOKEN: &str = "e2e-admin-token-that-is-long-enough";
+
+/// Bind a listener to an ephemeral port on the loopback interface
+fn ephemeral() -> io::Result<(TcpListener, Url)> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let url = Url::parse(&format!("http://{}", listener.local_addr()?))
+        .expect("a socket address makes a valid URL");
+    Ok((listener, url))
+}
+
+/// A stand-in for the API of an IPFS daemon, recording every upload made to it
+#[derive(Clone, Default)]
+pub struct FakeIpfs {
+    uploads: Arc<Mutex<Vec<web::Bytes>>>,
+}
+
+impl FakeIpfs {
+    /// The raw bodies of the uploads made so far, which wrap each file in a multipart form
+    pub fn uploads(&self) -> Vec<web::Bytes> {
+        self.uploads
+            .lock()
+            .expect("uploads lock is poisoned")
+            .clone()
+    }
+
+    fn serve(&self, listener: TcpListener) -> io::Result<Server> {
+        let ipfs = self.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .data(ipfs.clone())
+                .route("/api/v0/add", web::post().to(add))
+                .route("/api/v0/version", web::post().to(version))
+        })
+        .listen(listener)?
+        .workers(1)
+        .run();
+        Ok(server)
+    }
+}
+
+async fn add(ipfs: web::Data<FakeIpfs>, body: web::Bytes) -> HttpResponse {
+    let mut uploads = ipfs.uploads.lock().expect("uploads lock is poisoned");
+    uploads.push(body.clone());
+    HttpResponse::Ok().json(json!({
+        "Name": "file",
+        "Hash": format!("QmFake{}", uploads.len()),
+        "Size": body.len().to_string(),
+    }))
+}
+
+async fn version() -> HttpResponse {
+    HttpResponse::Ok().json(json!({
+        "Version": "0.0.0-fake",
+        "Commit": "",
+        "Repo": "0",
+        "System": "",
+        "Golang": "",
+    }))
+}
+
+/// A stand-in for an SMTP server, accepting every email sent through it
+#[derive(Clone, Default)]
+pub struct FakeSmtp {
+    emails: Arc<Mutex<Vec<String>>>,
+}
+
+impl FakeSmtp {
+    /// The raw emails received so far, headers included
+    pub fn emails(&self) -> Vec<String> {
+        self.emails.lock().expect("emails lock is poisoned").clone()
+    }
+
+    /// Accept connections on a thread of its own, as the SMTP client blocks while sending
+    fn serve(&self, listener: TcpListener) {
+        let smtp = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // A broken session only fails the email sent with it
+                let _ = smtp.session(stream);
+            }
+        });
+    }
+
+    /// Answer a single SMTP session, keeping the data of every email sent in it
+    fn session(&self, stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        writer.write_all(b"220 localhost fake SMTP\r\n")?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let command = line.trim_end().to_ascii_uppercase();
+            if command.starts_with("DATA") {
+                writer.write_all(b"354 end data with <CR><LF>.<CR><LF>\r\n")?;
+                let mut email = String::new();
+                loop {
+                    line.clear();
+                    if reader.read_line(&mut line)? == 0 || line == ".\r\n" {
+                        break;
+                    }
+                    email.push_str(line.strip_prefix('.').unwrap_or(&line));
+                }
+                self.emails
+                    .lock()
+                    .expect("emails lock is poisoned")
+                    .push(email);
+                writer.write_all(b"250 queued\r\n")?;
+            } else if command.starts_with("QUIT") {
+                return writer.write_all(b"221 bye\r\n");
+            } else {
+                // EHLO, MAIL, RCPT, RSET and NOOP are all simply accepted
+                writer.write_all(b"250 ok\r\n")?;
+            }
+        }
+    }
+}
+
+/// Every lanther server, running in-process
+pub struct Lanther {
+    /// Where the proxy-server listens, which is where clients send their requests
+    pub proxy: Url,
+    /// Where the simpleapikeys-server listens
+    pub simpleapikeys: Url,
+    /// The database of the simpleapikeys-server, to look at what it stores
+    pub simpleapikeys_db: lanther_store::Database,
+    /// Where the ipfs-server listens, behind the proxy-server
+    pub ipfs_server: Url,
+    /// The IPFS API the ipfs-server uploads to
+    pub ipfs: FakeIpfs,
+    /// The SMTP server the simpleapikeys-server sends signup emails through
+    pub smtp: FakeSmtp,
+    servers: Vec<Server>,
+}
+
+impl Lanther {
+    /// Start every server, each with its own in-memory database
+    pub async fn start() -> io::Result<Lanther> {
+        Lanther::start_with(|_| {}).await
+    }
+
+    /// Start every server, letting `configure` change the settings of the simpleapikeys-server
+    /// before it starts, like to give it quotas
+    pub async fn start_with(
+        configure: impl FnOnce(&mut apikeys_settings::Settings),
+    ) -> io::Result<Lanther> {
+        let ipfs = FakeIpfs::default();
+        let (listener, ipfs_url) = ephemeral()?;
+        let fake_ipfs = ipfs.serve(listener)?;
+
+        let (listener, ipfs_server_url) = ephemeral()?;
+        let settings = ipfs_settings::Settings {
+            server: ipfs_settings::ServerSettings {
+                address: Some(listener.local_addr()?.to_string()),
+                shutdown_timeout: 0,
+            },
+            ipfs: ipfs_settings::IpfsSettings {
+                url: Some(ipfs_url.as_str().trim_end_matches('/').to_string()),
+            },
+        };
+        let ipfs_server = ipfs_server::serve(&settings, listener)?;
+
+        let smtp = FakeSmtp::default();
+        let (listener, smtp_url) = ephemeral()?;
+        smtp.serve(listener);
+
+        // The simpleapikeys-server reads the request log of the proxy-server, which starts after it
+        let (proxy_listener, proxy_url) = ephemeral()?;
+        let (listener, simpleapikeys_url) = ephemeral()?;
+        let mut settings = apikeys_settings::Settings {
+            server: apikeys_settings::ServerSettings {
+                address: Some(listener.local_addr()?.to_string()),
+                shutdown_timeout: 0,
+            },
+            mongo: apikeys_settings::MongoSettings::default(),
+            migrations: apikeys_settings::MigrationSettings { on_startup: false },
+            idempotency: apikeys_settings::IdempotencySettings {
+                window: 86400,
+                lease: 60,
+            },
+            encryption: apikeys_settings::EncryptionSettings {
+                master_key: Some(MASTER_KEY.to_string()),
+                key_version: 1,
+                ..Default::default()
+            },
+            leaks: apikeys_settings::LeakSettings::default(),
+            notifications: apikeys_settings::NotificationSettings::default(),
+            quotas: apikeys_settings::QuotaSettings::default(),
+            admin: apikeys_settings::AdminSettings {
+                token: Some(ADMIN_TOKEN.to_string()),
+                token_file: None,
+                proxy_url: Some(proxy_url.to_string()),
+            },
+            smtp: apikeys_settings::SmtpSettings {
+                host: smtp_url.host_str().map(str::to_string),
+                port: smtp_url.port().expect("ephemeral URLs have a port"),
+                security: "none".to_string(),
+                from: Some("Lanther <noreply@lanther.test>".to_string()),
+                timeout: 5,
+                ..Default::default()
+            },
+            signup: apikeys_settings::SignupSettings {
+                anonymous_creation: true,
+                base_url: Some(simpleapikeys_url.to_string()),
+                token_ttl: 24,
+                window: 3600,
+                per_ip: 5,
+                per_domain: 20,
+            },
+        };
+        configure(&mut settings);
+        let simpleapikeys_db =
+            lanther_store::Database::Memory(lanther_store::memory::Database::new());
+        let simpleapikeys =
+            simpleapikeys_server::serve(&settings, simpleapikeys_db.clone(), listener)?;
+
+        let listener = proxy_listener;
+        let settings = proxy_settings::Settings {
+            server: proxy_settings::ServerSettings {
+                address: listener.local_addr()?.ip().to_string(),
+                port: listener.local_addr()?.port(),
+                shutdown_timeout: 0,
+            },
+            upstream: proxy_settings::ServiceSettings {
+                url: Some(ipfs_server_url.to_string()),
+            },
+            sandbox: proxy_settings::ServiceSettings::default(),
+            auth: proxy_settings::AuthSettings {
+                url: Some(simpleapikeys_url.to_string()),
+                timeout: 5,
+                retries: 0,
+            },
+            mongo: proxy_settings::MongoSettings::default(),
+            migrations: proxy_settings::MigrationSettings { on_startup: false },
+        };
+        let db = lanther_store::Database::Memory(lanther_store::memory::Database::new());
+        let proxy = proxy_server::serve(&settings, db, listener)?;
+
+        Ok(Lanther {
+            proxy: proxy_url,
+            simpleapikeys: simpleapikeys_url,
+            simpleapikeys_db,
+            ipfs_server: ipfs_server_url,
+            ipfs,
+            smtp,
+            servers: vec![proxy, simpleapikeys, ipfs_server, fake_ipfs],
+        })
+    }
+
+    /// A client for the simpleapikeys-server, to manage API keys with
+    pub fn apikeys(&self) -> simpleapikeys_client::Client {
+        simpleapikeys_client::Client::builder(self.simpleapikeys.clone())
+            .timeout(Duration::from_secs(5))
+            .retries(0)
+            .build()
+    }
+
+    /// A client for the simpleapikeys-server authenticated as the admin, for admin-only routes
+    pub fn admin(&self) -> simpleapikeys_client::Client {
+        simpleapikeys_client::Client::builder(self.simpleapikeys.clone())
+            .timeout(Duration::from_secs(5))
+            .retries(0)
+            .bearer_token(ADMIN_TOKEN)
+            .build()
+    }
+
+    /// The URL of a path on the proxy-server
+    pub fn proxy_url(&self, path: &str) -> String {
+        self.proxy
+            .join(path)
+            .expect("paths make valid URLs")
+            .to_string()
+    }
+
+    /// Stop every server, letting none of their in-flight requests finish
+    pub async fn stop(self) {
+        for server in self.servers {
+            server.stop(false).await;
+        }
+    }
+}
@@ -0,0 +1,149 @@
+//! The admin dashboard: managing API keys from a browser, behind the admin token
+use actix_web::client::{Client, ClientRequest, ClientResponse};
+use actix_web::dev::{Decompress, Payload};
+use actix_web::http::{header, StatusCode};
+use lanther_e2e::{Lanther, ADMIN_TOKEN};
+use simpleapikeys_client::models::{ApiKey, ApiKeyRequest};
+
+type Response = ClientResponse<Decompress<Payload>>;
+
+fn admin(lanther: &Lanther, method: &str, path: &str) -> ClientRequest {
+    let url = lanther
+        .simpleapikeys
+        .join(path)
+        .expect("paths make valid URLs");
+    Client::default()
+        .request(method.parse().expect("a valid method"), url.as_str())
+        .basic_auth("admin", Some(ADMIN_TOKEN))
+}
+
+async fn page(lanther: &Lanther, path: &str) -> String {
+    let mut res = admin(lanther, "GET", path).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    String::from_utf8(res.body().await.unwrap().to_vec()).unwrap()
+}
+
+/// The token forms are submitted with, as written into every page with a form
+fn csrf(page: &str) -> String {
+    let start = page
+        .find("name=\"csrf\" value=\"")
+        .expect("page has a form")
+        + 19;
+    let end = start + page[start..].find('"').unwrap();
+    page[start..end].to_string()
+}
+
+async fn submit(lanther: &Lanther, path: &str, form: &[(&str, &str)]) -> Response {
+    admin(lanther, "POST", path).send_form(&form).await.unwrap()
+}
+
+fn location(res: &Response) -> String {
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    res.headers()
+        .get(header::LOCATION)
+        .expect("redirects have a location")
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+async fn create_apikey(lanther: &Lanther, owner: &str) -> ApiKey {
+    let request = ApiKeyRequest {
+        owner: Some(owner.to_string()),
+        ..Default::default()
+    };
+    lanther.admin().create_apikey(&request).await.unwrap()
+}
+
+#[actix_rt::test]
+async fn the_dashboard_requires_the_admin_token() {
+    let lanther = Lanther::start().await.unwrap();
+    let url = lanther.simpleapikeys.join("/admin").unwrap();
+
+    let res = Client::default().get(url.as_str()).send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert!(res.headers().contains_key(header::WWW_AUTHENTICATE));
+    let res = Client::default()
+        .get(url.as_str())
+        .basic_auth("admin", Some("not-the-admin-token"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    // Forms submitted from anywhere but the dashboard are refused, even with the credentials
+    let apikey = create_apikey(&lanther, "acme").await;
+    let path = format!("/admin/apikeys/{}/disabled", apikey.key);
+    let res = submit(&lanther, &path, &[("csrf", "forged"), ("disabled", "true")]).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    let apikey = lanther.apikeys().get_apikey(&apikey.key.to_string()).await;
+    assert!(!apikey.unwrap().disabled);
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn keys_are_searched_and_shown_with_their_recent_requests() {
+    let lanther = Lanther::start().await.unwrap();
+    let acme = create_apikey(&lanther, "acme").await;
+    let globex = create_apikey(&lanther, "globex").await;
+    let res = Client::default()
+        .post(lanther.proxy_url("/"))
+        .header("Authorization", acme.key.to_string())
+        .send_body("file")
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let list = page(&lanther, "/admin?q=ACM").await;
+    assert!(list.contains(&acme.key.to_string()));
+    assert!(!list.contains(&globex.key.to_string()));
+
+    let detail = page(&lanther, &format!("/admin/apikeys/{}", acme.key)).await;
+    assert!(detail.contains("<td>POST</td><td><code>/</code></td>"));
+    let detail = page(&lanther, &format!("/admin/apikeys/{}", globex.key)).await;
+    assert!(!detail.contains("<td>POST</td>"));
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn keys_are_created_disabled_and_rotated() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+    let token = csrf(&page(&lanther, "/admin").await);
+
+    let form = [("csrf", token.as_str()), ("owner", "acme"), ("plan", "")];
+    let res = submit(&lanther, "/admin/apikeys", &form).await;
+    let key = location(&res)
+        .trim_start_matches("/admin/apikeys/")
+        .to_string();
+    let apikey = client.get_apikey(&key).await.unwrap();
+    assert_eq!(apikey.owner.as_deref(), Some("acme"));
+
+    // Invalid fields are shown along with the form
+    let form = [("csrf", token.as_str()), ("plan", "missing")];
+    let res = submit(&lanther, "/admin/apikeys", &form).await;
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let path = format!("/admin/apikeys/{}/disabled", key);
+    let res = submit(&lanther, &path, &[("csrf", &token), ("disabled", "true")]).await;
+    location(&res);
+    assert!(client.get_apikey(&key).await.unwrap().disabled);
+    submit(&lanther, &path, &[("csrf", &token), ("disabled", "false")]).await;
+    assert!(!client.get_apikey(&key).await.unwrap().disabled);
+
+    let path = format!("/admin/apikeys/{}/rotate", key);
+    let res = submit(&lanther, &path, &[("csrf", &token)]).await;
+    let replacement = location(&res)
+        .trim_start_matches("/admin/apikeys/")
+        .to_string();
+    assert_ne!(replacement, key);
+    let rotated = client.get_apikey(&key).await.unwrap();
+    assert_eq!(rotated.revocation_reason.as_deref(), Some("rotated"));
+    let replacement = client.get_apikey(&replacement).await.unwrap();
+    assert_eq!(replacement.owner.as_deref(), Some("acme"));
+    assert!(!replacement.disabled);
+
+    lanther.stop().await;
+}
@@ -0,0 +1,69 @@
+//! Names and labels of API keys are encrypted at rest, and nowhere to be read from the database
+use bson::{doc, Bson, Document};
+use lanther_e2e::Lanther;
+use serde_json::json;
+use simpleapikeys_client::models::ApiKeyRequest;
+
+const NAME: &str = "Nightly backups of the billing database";
+const LABEL: &str = "finance-platform-team";
+
+/// Whether a stored document holds a value anywhere, in the clear
+fn contains_plaintext(document: &Document, value: &str) -> bool {
+    let mut bytes = Vec::new();
+    document.to_writer(&mut bytes).unwrap();
+    bytes
+        .windows(value.len())
+        .any(|window| window == value.as_bytes())
+}
+
+#[actix_rt::test]
+async fn names_and_labels_are_only_stored_encrypted() {
+    let lanther = Lanther::start().await.unwrap();
+    let admin = lanther.admin();
+    let apikey = admin
+        .create_apikey(&ApiKeyRequest::default())
+        .await
+        .unwrap();
+    let key = apikey.key.to_string();
+    let patch = json!({ "name": NAME, "labels": { "team": LABEL } });
+    admin.patch_apikey(&key, &patch, None).await.unwrap();
+
+    // They round-trip through encryption
+    let fetched = admin.get_apikey(&key).await.unwrap();
+    assert_eq!(fetched.name.as_deref(), Some(NAME));
+    assert_eq!(fetched.labels.get("team").map(String::as_str), Some(LABEL));
+
+    let stored = lanther
+        .simpleapikeys_db
+        .collection("apikeys")
+        .find_one(doc! { "key": key.clone() })
+        .await
+        .unwrap()
+        .expect("API key is stored");
+    assert!(matches!(stored.get("name"), Some(Bson::Binary(_))));
+    let labels = stored.get_document("labels").unwrap();
+    assert!(matches!(labels.get("team"), Some(Bson::Binary(_))));
+    assert!(stored.contains_key("data_key"));
+    assert!(!contains_plaintext(&stored, NAME));
+    assert!(!contains_plaintext(&stored, LABEL));
+
+    // Nor are they in the responses recorded for the admin's retries, which hold them decrypted
+    let recorded = lanther
+        .simpleapikeys_db
+        .collection("idempotency_keys")
+        .find_one(doc! { "response": { "$exists": true } })
+        .await
+        .unwrap()
+        .expect("responses are recorded");
+    assert!(!contains_plaintext(&recorded, NAME));
+    assert!(!contains_plaintext(&recorded, LABEL));
+
+    // Which are still replayed as they were sent
+    let retried = admin.with_idempotency_key("rename-backups");
+    let renamed = retried.patch_apikey(&key, &patch, None).await.unwrap();
+    let replayed = retried.patch_apikey(&key, &patch, None).await.unwrap();
+    assert_eq!(replayed.name.as_deref(), Some(NAME));
+    assert_eq!(replayed.version, renamed.version);
+
+    lanther.stop().await;
+}
@@ -0,0 +1,103 @@
+//! Idempotency-Key: retries get the first response replayed, but only to whoever it was sent to
+use std::time::Duration;
+
+use lanther_e2e::Lanther;
+use simpleapikeys_client::models::ApiKeyRequest;
+use simpleapikeys_client::{Client, Error};
+use simpleapikeys_server::services::{Claim, IdempotencyService, Keyring, MasterKey, KEY_LEN};
+
+async fn admin_only() -> Lanther {
+    Lanther::start_with(|settings| settings.signup.anonymous_creation = false)
+        .await
+        .unwrap()
+}
+
+/// A client for the simpleapikeys-server with credentials other than the admin's
+fn impostor(lanther: &Lanther) -> Client {
+    Client::builder(lanther.simpleapikeys.clone())
+        .timeout(Duration::from_secs(5))
+        .retries(0)
+        .bearer_token("not-the-admin-token-but-just-as-long")
+        .build()
+}
+
+fn error_code<T>(result: Result<T, Error>) -> Option<(u16, String)> {
+    match result {
+        Err(Error::Api { status, error }) => Some((status, error.code)),
+        _ => None,
+    }
+}
+
+#[actix_rt::test]
+async fn responses_are_only_replayed_to_the_same_credentials() {
+    let lanther = admin_only().await;
+    let request = ApiKeyRequest::default();
+
+    let admin = lanther.admin().with_idempotency_key("create-ci-key");
+    let created = admin.create_apikey(&request).await.unwrap();
+    let replayed = admin.create_apikey(&request).await.unwrap();
+    assert_eq!(replayed.key, created.key);
+
+    // Anyone else reusing the key is processed, and refused, rather than handed the admin's key
+    let other = impostor(&lanther).with_idempotency_key("create-ci-key");
+    let result = other.create_apikey(&request).await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn refusals_are_not_replayed() {
+    let lanther = admin_only().await;
+    let request = ApiKeyRequest::default();
+
+    let other = impostor(&lanther).with_idempotency_key("create-ci-key");
+    let result = other.create_apikey(&request).await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+    // Not even to the same credentials, which may be given access meanwhile
+    let result = other.create_apikey(&request).await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+
+    let admin = lanther.admin().with_idempotency_key("create-ci-key");
+    admin.create_apikey(&request).await.unwrap();
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn anonymous_requests_cannot_send_an_idempotency_key() {
+    let lanther = Lanther::start().await.unwrap();
+    let request = ApiKeyRequest::default();
+
+    // Anonymous clients all look alike, so one could be replayed another's API key
+    let anonymous = lanther.apikeys().with_idempotency_key("create-ci-key");
+    let result = anonymous.create_apikey(&request).await;
+    let invalid = Some((400, "idempotency_key_invalid".to_string()));
+    assert_eq!(error_code(result), invalid);
+    assert!(lanther.admin().list_apikeys().await.unwrap().is_empty());
+
+    lanther.apikeys().create_apikey(&request).await.unwrap();
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn abandoned_requests_only_hold_their_key_for_the_lease() {
+    let db = lanther_store::Database::Memory(lanther_store::memory::Database::new());
+    let idempotency = IdempotencyService::new(
+        db.collection("idempotency_keys"),
+        Keyring::new(MasterKey::new(1, &[7; KEY_LEN]), None),
+        chrono::Duration::hours(24),
+        chrono::Duration::seconds(1),
+    );
+
+    // Claimed by a request whose client disconnected, so it was neither completed nor released
+    let claim = idempotency.claim("caller:create-ci-key", "request").await;
+    assert!(matches!(claim.unwrap(), Claim::Claimed));
+    let claim = idempotency.claim("caller:create-ci-key", "request").await;
+    assert!(matches!(claim.unwrap(), Claim::InProgress));
+
+    actix_rt::time::delay_for(Duration::from_millis(1100)).await;
+    let claim = idempotency.claim("caller:create-ci-key", "request").await;
+    assert!(matches!(claim.unwrap(), Claim::Claimed));
+}
@@ -0,0 +1,206 @@
+//! Lockdowns: stopping the API keys of an owner, or of everyone but some, as soon as they are put
+//! in place
+use actix_web::client::{Client, ClientResponse};
+use actix_web::dev::{Decompress, Payload};
+use actix_web::http::StatusCode;
+use lanther_e2e::Lanther;
+use serde_json::{json, Value};
+use simpleapikeys_client::models::{ApiKey, ApiKeyRequest, ChildKeyRequest, LockdownRequest};
+
+type Response = ClientResponse<Decompress<Payload>>;
+
+async fn create_apikey(lanther: &Lanther, owner: &str) -> ApiKey {
+    let request = ApiKeyRequest {
+        owner: Some(owner.to_string()),
+        ..Default::default()
+    };
+    lanther.admin().create_apikey(&request).await.unwrap()
+}
+
+async fn upload(lanther: &Lanther, apikey: &ApiKey) -> Response {
+    Client::default()
+        .post(lanther.proxy_url("/"))
+        .header("Authorization", apikey.key.to_string())
+        .send_body("file")
+        .await
+        .expect("proxy answers")
+}
+
+async fn error_code(mut res: Response) -> String {
+    let body: Value = res.json().await.expect("error is JSON");
+    body["error"]["code"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn lockdown(reason: &str, allow: &[&str]) -> LockdownRequest {
+    LockdownRequest {
+        reason: reason.to_string(),
+        allow: allow.iter().map(|owner| owner.to_string()).collect(),
+    }
+}
+
+#[actix_rt::test]
+async fn owner_lockdowns_stop_only_their_keys() {
+    let lanther = Lanther::start().await.unwrap();
+    let acme = create_apikey(&lanther, "acme").await;
+    let globex = create_apikey(&lanther, "globex").await;
+    let admin = lanther.admin();
+
+    let locked = admin
+        .lock_down_owner("acme", &lockdown("compromised CI", &[]))
+        .await
+        .unwrap();
+    assert_eq!(locked.owner.as_deref(), Some("acme"));
+
+    // Proxies verify every request, so the lockdown applies to the very next one
+    let res = upload(&lanther, &acme).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(error_code(res).await, "locked_down");
+    assert_eq!(upload(&lanther, &globex).await.status(), StatusCode::OK);
+
+    admin.lift_owner_lockdown("acme").await.unwrap();
+    assert_eq!(upload(&lanther, &acme).await.status(), StatusCode::OK);
+    assert!(admin.list_lockdowns().await.unwrap().is_empty());
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn child_keys_are_locked_down_along_with_their_parent() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+    let parent = create_apikey(&lanther, "acme").await;
+    let child = client
+        .create_child(&parent.key.to_string(), &ChildKeyRequest::default())
+        .await
+        .unwrap();
+    assert_eq!(child.owner.as_deref(), Some("acme"));
+
+    // The child keeps the owner it was created with, but not the parent
+    lanther
+        .admin()
+        .patch_apikey(&parent.key.to_string(), &json!({ "owner": "globex" }), None)
+        .await
+        .unwrap();
+    lanther
+        .admin()
+        .lock_down_owner("globex", &lockdown("compromised CI", &[]))
+        .await
+        .unwrap();
+    let res = upload(&lanther, &child).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(error_code(res).await, "locked_down");
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn global_lockdowns_stop_everyone_not_allowed() {
+    let lanther = Lanther::start().await.unwrap();
+    let acme = create_apikey(&lanther, "acme").await;
+    let globex = create_apikey(&lanther, "globex").await;
+    let anonymous = lanther
+        .apikeys()
+        .create_apikey(&ApiKeyRequest::default())
+        .await
+        .unwrap();
+    let admin = lanther.admin();
+
+    admin
+        .lock_down(&lockdown("incident", &["globex"]))
+        .await
+        .unwrap();
+    assert_eq!(
+        upload(&lanther, &acme).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        upload(&lanther, &anonymous).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(upload(&lanther, &globex).await.status(), StatusCode::OK);
+
+    // An allowed owner is still stopped by a lockdown of its own
+    admin
+        .lock_down_owner("globex", &lockdown("abuse", &[]))
+        .await
+        .unwrap();
+    assert_eq!(
+        upload(&lanther, &globex).await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(admin.list_lockdowns().await.unwrap().len(), 2);
+
+    admin.lift_lockdown().await.unwrap();
+    assert_eq!(upload(&lanther, &acme).await.status(), StatusCode::OK);
+    assert_eq!(upload(&lanther, &anonymous).await.status(), StatusCode::OK);
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn lockdowns_are_only_for_the_admin() {
+    let lanther = Lanther::start().await.unwrap();
+    let acme = create_apikey(&lanther, "acme").await;
+
+    let result = lanther
+        .apikeys()
+        .lock_down(&lockdown("not mine to", &[]))
+        .await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+    assert_eq!(upload(&lanther, &acme).await.status(), StatusCode::OK);
+
+    let result = lanther.admin().lift_owner_lockdown("acme").await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(404));
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn only_the_admin_moves_keys_between_owners() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+    let acme = create_apikey(&lanther, "acme").await;
+    let anonymous = client
+        .create_apikey(&ApiKeyRequest::default())
+        .await
+        .unwrap();
+    let admin = lanther.admin();
+    admin
+        .lock_down_owner("acme", &lockdown("compromised CI", &[]))
+        .await
+        .unwrap();
+    admin
+        .lock_down(&lockdown("incident", &["globex"]))
+        .await
+        .unwrap();
+
+    // Neither into an owner allowed through the global lockdown
+    let request = ApiKeyRequest {
+        owner: Some("globex".to_string()),
+        ..Default::default()
+    };
+    let result = client.create_apikey(&request).await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+    let globex = json!({ "owner": "globex" });
+    let result = client
+        .patch_apikey(&anonymous.key.to_string(), &globex, None)
+        .await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+    let res = upload(&lanther, &anonymous).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // Nor out from under the lockdown of their owner
+    let result = client
+        .patch_apikey(&acme.key.to_string(), &json!({ "owner": null }), None)
+        .await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+    admin.lift_lockdown().await.unwrap();
+    let res = upload(&lanther, &acme).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(error_code(res).await, "locked_down");
+
+    lanther.stop().await;
+}
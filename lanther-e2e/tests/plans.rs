@@ -0,0 +1,39 @@
+//! Plans, which only the admin may create, change or delete as they set the limits of their keys
+use lanther_e2e::Lanther;
+use simpleapikeys_client::models::{Limits, PlanRequest};
+
+fn free(requests_per_minute: i64) -> PlanRequest {
+    PlanRequest {
+        name: "free".to_string(),
+        limits: Limits {
+            requests_per_minute: Some(requests_per_minute),
+            ..Default::default()
+        },
+        scopes: Vec::new(),
+        max_active_keys: Some(1),
+    }
+}
+
+#[actix_rt::test]
+async fn only_the_admin_writes_plans() {
+    let lanther = Lanther::start().await.unwrap();
+    let anonymous = lanther.apikeys();
+    let admin = lanther.admin();
+
+    let result = anonymous.create_plan(&free(60)).await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+    admin.create_plan(&free(60)).await.unwrap();
+
+    // Key holders cannot raise the limits of the plan their keys are on
+    let result = anonymous.replace_plan(&free(6000)).await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+    let result = anonymous.delete_plan("free").await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+    let plan = anonymous.get_plan("free").await.unwrap();
+    assert_eq!(plan.limits.requests_per_minute, Some(60));
+
+    admin.replace_plan(&free(6000)).await.unwrap();
+    admin.delete_plan("free").await.unwrap();
+
+    lanther.stop().await;
+}
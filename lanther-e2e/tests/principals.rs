@@ -0,0 +1,157 @@
+//! Principals: the users and service accounts API keys act for, whose keys are disabled along
+//! with them
+use std::collections::BTreeMap;
+
+use actix_web::client::Client;
+use actix_web::http::StatusCode;
+use lanther_e2e::Lanther;
+use serde_json::json;
+use simpleapikeys_client::models::{
+    ApiKey, ApiKeyRequest, ChildKeyRequest, PrincipalKind, PrincipalRequest,
+};
+use simpleapikeys_client::Error;
+
+async fn upload(lanther: &Lanther, apikey: &ApiKey) -> StatusCode {
+    Client::default()
+        .post(lanther.proxy_url("/"))
+        .header("Authorization", apikey.key.to_string())
+        .send_body("file")
+        .await
+        .expect("proxy answers")
+        .status()
+}
+
+fn service_account(name: &str) -> PrincipalRequest {
+    PrincipalRequest {
+        name: name.to_string(),
+        kind: PrincipalKind::ServiceAccount,
+        description: Some("Deploys from CI".to_string()),
+        metadata: BTreeMap::new(),
+    }
+}
+
+#[actix_rt::test]
+async fn disabling_a_principal_disables_its_keys() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+    let admin = lanther.admin();
+    admin
+        .create_principal(&service_account("ci"))
+        .await
+        .unwrap();
+    let request = ApiKeyRequest {
+        principal: Some("ci".to_string()),
+        ..Default::default()
+    };
+    let apikey = client.create_apikey(&request).await.unwrap();
+    assert_eq!(apikey.principal.as_deref(), Some("ci"));
+    let child = client
+        .create_child(&apikey.key.to_string(), &ChildKeyRequest::default())
+        .await
+        .unwrap();
+
+    // Children act for the principal of their parent
+    let verification = client.verify(&child.key.to_string()).await.unwrap();
+    let principal = verification.principal.expect("child acts for ci");
+    assert_eq!(principal.name, "ci");
+    assert_eq!(principal.kind, PrincipalKind::ServiceAccount);
+    assert_eq!(upload(&lanther, &apikey).await, StatusCode::OK);
+
+    let disabled = admin
+        .patch_principal("ci", &json!({ "disabled": true }))
+        .await
+        .unwrap();
+    assert!(disabled.disabled);
+    assert_eq!(upload(&lanther, &apikey).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(upload(&lanther, &child).await, StatusCode::UNAUTHORIZED);
+    // The keys themselves are left untouched, so enabling the principal brings them all back
+    let stored = client.get_apikey(&apikey.key.to_string()).await.unwrap();
+    assert!(!stored.disabled);
+
+    admin
+        .patch_principal("ci", &json!({ "disabled": false }))
+        .await
+        .unwrap();
+    assert_eq!(upload(&lanther, &apikey).await, StatusCode::OK);
+    assert_eq!(upload(&lanther, &child).await, StatusCode::OK);
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn principals_must_exist_and_cannot_be_deleted_while_in_use() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+    let admin = lanther.admin();
+    let request = ApiKeyRequest {
+        principal: Some("ci".to_string()),
+        ..Default::default()
+    };
+    match client.create_apikey(&request).await {
+        Err(Error::Api { status, error }) => {
+            assert_eq!((status, error.code.as_str()), (422, "validation_failed"))
+        }
+        other => panic!(
+            "expected a validation error, got {:?}",
+            other.map(|k| k.key)
+        ),
+    }
+
+    admin
+        .create_principal(&service_account("ci"))
+        .await
+        .unwrap();
+    match admin.create_principal(&service_account("ci")).await {
+        Err(Error::Api { status, error }) => {
+            assert_eq!((status, error.code.as_str()), (409, "principal_exists"))
+        }
+        other => panic!("expected a conflict, got {:?}", other.map(|p| p.name)),
+    }
+    let apikey = client.create_apikey(&request).await.unwrap();
+    match admin.delete_principal("ci").await {
+        Err(Error::Api { status, error }) => {
+            assert_eq!((status, error.code.as_str()), (409, "principal_in_use"))
+        }
+        other => panic!("expected a conflict, got {:?}", other),
+    }
+
+    client
+        .patch_apikey(&apikey.key.to_string(), &json!({ "principal": null }), None)
+        .await
+        .unwrap();
+    admin.delete_principal("ci").await.unwrap();
+    assert!(client.list_principals().await.unwrap().is_empty());
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn only_the_admin_writes_principals() {
+    let lanther = Lanther::start().await.unwrap();
+    let anonymous = lanther.apikeys();
+    let admin = lanther.admin();
+
+    let result = anonymous.create_principal(&service_account("ci")).await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+    admin
+        .create_principal(&service_account("ci"))
+        .await
+        .unwrap();
+    let request = ApiKeyRequest {
+        principal: Some("ci".to_string()),
+        ..Default::default()
+    };
+    let apikey = anonymous.create_apikey(&request).await.unwrap();
+
+    // Nobody but the admin can switch off every key of a service account
+    let result = anonymous
+        .patch_principal("ci", &json!({ "disabled": true }))
+        .await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+    assert_eq!(upload(&lanther, &apikey).await, StatusCode::OK);
+    let result = anonymous.delete_principal("ci").await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(401));
+    assert!(!anonymous.get_principal("ci").await.unwrap().disabled);
+
+    lanther.stop().await;
+}
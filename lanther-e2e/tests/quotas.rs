@@ -0,0 +1,199 @@
+//! Quotas on how many active API keys an owner, or a plan, may have
+use lanther_e2e::Lanther;
+use serde_json::json;
+use simpleapikeys_client::models::{
+    ApiKey, ApiKeyRequest, ChildKeyRequest, Limits, PlanRequest, UpdateApiKey,
+};
+use simpleapikeys_client::{Client, Error};
+
+async fn create_apikey(
+    client: &Client,
+    owner: Option<&str>,
+    plan: Option<&str>,
+) -> Result<ApiKey, Error> {
+    let request = ApiKeyRequest {
+        owner: owner.map(str::to_string),
+        plan: plan.map(str::to_string),
+        ..Default::default()
+    };
+    client.create_apikey(&request).await
+}
+
+fn error_code(result: Result<ApiKey, Error>) -> (u16, String) {
+    match result {
+        Err(Error::Api { status, error }) => (status, error.code),
+        other => panic!("expected an error, got {:?}", other.map(|k| k.key)),
+    }
+}
+
+#[actix_rt::test]
+async fn owners_cannot_exceed_their_quota() {
+    let lanther = Lanther::start_with(|settings| {
+        settings.quotas.max_active_keys_per_owner = Some(2);
+        settings.quotas.owners.insert("acme".to_string(), 1);
+    })
+    .await
+    .unwrap();
+    let client = lanther.admin();
+
+    let acme = create_apikey(&client, Some("acme"), None).await.unwrap();
+    let result = create_apikey(&client, Some("acme"), None).await;
+    assert_eq!(error_code(result), (409, "key_quota_exceeded".to_string()));
+    create_apikey(&client, Some("globex"), None).await.unwrap();
+    create_apikey(&client, Some("globex"), None).await.unwrap();
+    assert!(create_apikey(&client, Some("globex"), None).await.is_err());
+    // Keys with no owner are not limited by any owner's quota
+    create_apikey(&client, None, None).await.unwrap();
+
+    let usage = client.owner_usage("acme").await.unwrap();
+    assert_eq!((usage.active, usage.limit), (1, Some(1)));
+
+    // Disabled keys are not active, so they free a slot
+    let update = UpdateApiKey {
+        key: acme.key,
+        disabled: true,
+    };
+    client.update_apikey(&update, None).await.unwrap();
+    assert_eq!(client.owner_usage("acme").await.unwrap().active, 0);
+    create_apikey(&client, Some("acme"), None).await.unwrap();
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn plans_cannot_exceed_their_quota() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+    let plan = PlanRequest {
+        name: "free".to_string(),
+        limits: Limits::default(),
+        scopes: Vec::new(),
+        max_active_keys: Some(1),
+    };
+    lanther.admin().create_plan(&plan).await.unwrap();
+
+    let on_plan = create_apikey(&client, None, Some("free")).await.unwrap();
+    assert_eq!(on_plan.plan.as_deref(), Some("free"));
+    let result = create_apikey(&client, None, Some("free")).await;
+    assert_eq!(error_code(result), (409, "key_quota_exceeded".to_string()));
+
+    // Keys cannot be moved onto a full plan either, but may be patched while on it
+    let other = create_apikey(&client, None, None).await.unwrap();
+    let result = client
+        .patch_apikey(&other.key.to_string(), &json!({ "plan": "free" }), None)
+        .await;
+    assert_eq!(error_code(result), (409, "key_quota_exceeded".to_string()));
+    client
+        .patch_apikey(&on_plan.key.to_string(), &json!({ "plan": "free" }), None)
+        .await
+        .unwrap();
+
+    let result = create_apikey(&client, None, Some("missing")).await;
+    assert_eq!(error_code(result), (422, "validation_failed".to_string()));
+
+    lanther.stop().await;
+}
+
+fn plan(name: &str, max_active_keys: i64) -> PlanRequest {
+    PlanRequest {
+        name: name.to_string(),
+        limits: Limits::default(),
+        scopes: Vec::new(),
+        max_active_keys: Some(max_active_keys),
+    }
+}
+
+#[actix_rt::test]
+async fn keys_cannot_be_made_active_past_their_owners_quota() {
+    let lanther = Lanther::start_with(|settings| {
+        settings.quotas.max_active_keys_per_owner = Some(1);
+    })
+    .await
+    .unwrap();
+    let client = lanther.admin();
+    let quota_exceeded = (409, "key_quota_exceeded".to_string());
+
+    // A disabled key gives up its slot, which another key takes
+    let disabled = create_apikey(&client, Some("acme"), None).await.unwrap();
+    let key = disabled.key.to_string();
+    let disable = json!({ "disabled": true });
+    client.patch_apikey(&key, &disable, None).await.unwrap();
+    create_apikey(&client, Some("acme"), None).await.unwrap();
+    let enable = json!({ "disabled": false });
+    let result = client.patch_apikey(&key, &enable, None).await;
+    assert_eq!(error_code(result), quota_exceeded);
+    let update = UpdateApiKey {
+        key: disabled.key,
+        disabled: false,
+    };
+    let result = client.update_apikey(&update, None).await;
+    assert_eq!(error_code(result), quota_exceeded);
+
+    // So does an expired key, which cannot be extended while the owner is full
+    let expired = create_apikey(&client, Some("globex"), None).await.unwrap();
+    let key = expired.key.to_string();
+    let expire = json!({ "expires_at": "2020-01-01T00:00:00Z" });
+    client.patch_apikey(&key, &expire, None).await.unwrap();
+    create_apikey(&client, Some("globex"), None).await.unwrap();
+    let extend = json!({ "expires_at": null });
+    let result = client.patch_apikey(&key, &extend, None).await;
+    assert_eq!(error_code(result), quota_exceeded);
+
+    // Nor can an active key be moved to a full owner, though it may be patched as it is
+    let other = create_apikey(&client, Some("initech"), None).await.unwrap();
+    let key = other.key.to_string();
+    let result = client
+        .patch_apikey(&key, &json!({ "owner": "acme" }), None)
+        .await;
+    assert_eq!(error_code(result), quota_exceeded);
+    client
+        .patch_apikey(&key, &json!({ "owner": "initech", "name": "CI" }), None)
+        .await
+        .unwrap();
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn child_keys_take_up_slots_on_the_plan_of_their_parent() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+    let quota_exceeded = (409, "key_quota_exceeded".to_string());
+    lanther.admin().create_plan(&plan("free", 2)).await.unwrap();
+    lanther.admin().create_plan(&plan("pro", 2)).await.unwrap();
+
+    let parent = create_apikey(&client, None, Some("free")).await.unwrap();
+    let key = parent.key.to_string();
+    let child = client
+        .create_child(&key, &ChildKeyRequest::default())
+        .await
+        .unwrap();
+    let result = client.create_child(&key, &ChildKeyRequest::default()).await;
+    assert_eq!(error_code(result), quota_exceeded);
+
+    // Disabled children give up their slot until enabled again
+    let disable = json!({ "disabled": true });
+    let child_key = child.key.to_string();
+    client
+        .patch_apikey(&child_key, &disable, None)
+        .await
+        .unwrap();
+    create_apikey(&client, None, Some("free")).await.unwrap();
+    let enable = json!({ "disabled": false });
+    let result = client.patch_apikey(&child_key, &enable, None).await;
+    assert_eq!(error_code(result), quota_exceeded);
+
+    // Children move to another plan along with their parent, so both need a slot there
+    create_apikey(&client, None, Some("pro")).await.unwrap();
+    let second = create_apikey(&client, None, None).await.unwrap();
+    client
+        .create_child(&second.key.to_string(), &ChildKeyRequest::default())
+        .await
+        .unwrap();
+    let result = client
+        .patch_apikey(&second.key.to_string(), &json!({ "plan": "pro" }), None)
+        .await;
+    assert_eq!(error_code(result), quota_exceeded);
+
+    lanther.stop().await;
+}
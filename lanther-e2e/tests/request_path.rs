@@ -0,0 +1,298 @@
+//! The full request path: from a client, through the proxy-server and its authorization by the
+//! simpleapikeys-server, to the ipfs-server and IPFS
+use actix_web::client::{Client, ClientResponse};
+use actix_web::dev::{Decompress, Payload};
+use actix_web::http::StatusCode;
+use lanther_e2e::Lanther;
+use serde_json::{json, Value};
+use simpleapikeys_client::models::{
+    ApiKey, ApiKeyRequest, ChildKeyRequest, Limits, PlanRequest, UpdateApiKey,
+};
+
+type Response = ClientResponse<Decompress<Payload>>;
+
+async fn create_apikey(lanther: &Lanther) -> ApiKey {
+    lanther
+        .apikeys()
+        .create_apikey(&ApiKeyRequest::default())
+        .await
+        .expect("API key is created")
+}
+
+/// Upload a file through the proxy, with an API key if any
+async fn upload(lanther: &Lanther, apikey: Option<&ApiKey>, file: &'static str) -> Response {
+    let req = Client::default().post(lanther.proxy_url("/"));
+    let req = match apikey {
+        Some(apikey) => req.header("Authorization", apikey.key.to_string()),
+        None => req,
+    };
+    req.send_body(file).await.expect("proxy answers")
+}
+
+async fn error_code(mut res: Response) -> String {
+    let body: Value = res.json().await.expect("error is JSON");
+    body["error"]["code"]
+        .as_str()
+        .expect("error has a code")
+        .to_string()
+}
+
+async fn get_payload(lanther: &Lanther, path: &str) -> Value {
+    let mut res = Client::default()
+        .get(lanther.proxy_url(path))
+        .send()
+        .await
+        .expect("proxy answers");
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = res.json().await.expect("response is JSON");
+    body["payload"].clone()
+}
+
+#[actix_rt::test]
+async fn created_apikeys_can_be_verified() {
+    let lanther = Lanther::start().await.unwrap();
+    let apikey = create_apikey(&lanther).await;
+    assert!(!apikey.disabled);
+
+    let client = lanther.apikeys();
+    let fetched = client.get_apikey(&apikey.key.to_string()).await.unwrap();
+    assert_eq!(fetched.key, apikey.key);
+    let verification = client.verify(&apikey.key.to_string()).await.unwrap();
+    assert_eq!(verification.key, apikey.key);
+    assert!(!verification.disabled);
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn requests_without_an_apikey_are_rejected() {
+    let lanther = Lanther::start().await.unwrap();
+
+    let res = upload(&lanther, None, "anonymous").await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(error_code(res).await, "apikey_required");
+    assert!(lanther.ipfs.uploads().is_empty());
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn requests_with_an_unknown_apikey_are_rejected() {
+    let lanther = Lanther::start().await.unwrap();
+
+    let res = Client::default()
+        .post(lanther.proxy_url("/"))
+        .header("Authorization", "00000000-0000-0000-0000-000000000000")
+        .send_body("unknown")
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(error_code(res).await, "apikey_not_found");
+    assert!(lanther.ipfs.uploads().is_empty());
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn authorized_uploads_reach_ipfs() {
+    let lanther = Lanther::start().await.unwrap();
+    let apikey = create_apikey(&lanther).await;
+
+    let mut res = upload(&lanther, Some(&apikey), "hello lanther").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["hash"], "QmFake1");
+
+    let uploads = lanther.ipfs.uploads();
+    assert_eq!(uploads.len(), 1);
+    assert!(uploads[0]
+        .windows("hello lanther".len())
+        .any(|window| window == b"hello lanther"));
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn disabled_apikeys_are_rejected() {
+    let lanther = Lanther::start().await.unwrap();
+    let apikey = create_apikey(&lanther).await;
+    let res = upload(&lanther, Some(&apikey), "before").await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let update = UpdateApiKey {
+        key: apikey.key,
+        disabled: true,
+    };
+    let disabled = lanther
+        .apikeys()
+        .update_apikey(&update, Some(apikey.version))
+        .await
+        .unwrap();
+    assert!(disabled.disabled);
+
+    let res = upload(&lanther, Some(&apikey), "after").await;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(error_code(res).await, "apikey_disabled");
+    assert_eq!(lanther.ipfs.uploads().len(), 1);
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn proxied_requests_are_logged() {
+    let lanther = Lanther::start().await.unwrap();
+    let apikey = create_apikey(&lanther).await;
+    let other = create_apikey(&lanther).await;
+    upload(&lanther, Some(&apikey), "first").await;
+    upload(&lanther, Some(&apikey), "second").await;
+    upload(&lanther, Some(&other), "third").await;
+    // Rejected requests never reach the upstream, so they are not logged
+    upload(&lanther, None, "anonymous").await;
+
+    let all = get_payload(&lanther, "/requests").await;
+    assert_eq!(all.as_array().unwrap().len(), 3);
+
+    let by_key = get_payload(&lanther, &format!("/requests/{}", apikey.key)).await;
+    let by_key = by_key.as_array().unwrap();
+    assert_eq!(by_key.len(), 2);
+    for request in by_key {
+        assert_eq!(request["method"], "POST");
+        assert_eq!(request["path"], "/");
+        assert_eq!(request["authorization"], apikey.key.to_string());
+    }
+
+    let path = format!("/requests/{}?order=newest&limit=1", apikey.key);
+    let newest = get_payload(&lanther, &path).await;
+    let newest = newest.as_array().unwrap();
+    assert_eq!(newest.len(), 1);
+    let created_at = |request: &Value| request["created_at"].as_str().unwrap().to_string();
+    assert!(by_key
+        .iter()
+        .all(|request| created_at(request) <= created_at(&newest[0])));
+
+    let path = format!("/requests/{}?limit=0", apikey.key);
+    let res = Client::default().get(lanther.proxy_url(&path)).send().await;
+    assert_eq!(res.unwrap().status(), StatusCode::BAD_REQUEST);
+
+    lanther.stop().await;
+}
+
+/// Fetch from the proxy with an API key
+async fn read(lanther: &Lanther, apikey: &ApiKey) -> Response {
+    Client::default()
+        .get(lanther.proxy_url("/"))
+        .header("Authorization", apikey.key.to_string())
+        .send()
+        .await
+        .expect("proxy answers")
+}
+
+#[actix_rt::test]
+async fn requests_outside_the_scopes_of_a_key_are_rejected() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+    let plan = PlanRequest {
+        name: "readers".to_string(),
+        limits: Limits::default(),
+        scopes: vec!["read".to_string()],
+        max_active_keys: None,
+    };
+    lanther.admin().create_plan(&plan).await.unwrap();
+    let request = ApiKeyRequest {
+        plan: Some("readers".to_string()),
+        ..Default::default()
+    };
+    let reader = client.create_apikey(&request).await.unwrap();
+
+    assert_eq!(read(&lanther, &reader).await.status(), StatusCode::OK);
+    let res = upload(&lanther, Some(&reader), "not allowed").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(error_code(res).await, "scope_not_allowed");
+    assert!(lanther.ipfs.uploads().is_empty());
+
+    // Scopes the plan does not allow leave the key with none, rather than with every scope
+    let uploader = client
+        .patch_apikey(
+            &reader.key.to_string(),
+            &json!({ "scopes": ["upload"] }),
+            None,
+        )
+        .await
+        .unwrap();
+    let res = read(&lanther, &uploader).await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(error_code(res).await, "scope_not_allowed");
+    let res = upload(&lanther, Some(&uploader), "still not allowed").await;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert!(lanther.ipfs.uploads().is_empty());
+
+    // Keys with no scopes on a plan with none may make any request
+    let unrestricted = create_apikey(&lanther).await;
+    assert_eq!(read(&lanther, &unrestricted).await.status(), StatusCode::OK);
+    let res = upload(&lanther, Some(&unrestricted), "allowed").await;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn child_keys_share_the_limits_of_their_parent() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+    let plan = PlanRequest {
+        name: "metered".to_string(),
+        limits: Limits {
+            monthly_quota: Some(3),
+            ..Default::default()
+        },
+        scopes: Vec::new(),
+        max_active_keys: None,
+    };
+    lanther.admin().create_plan(&plan).await.unwrap();
+    let request = ApiKeyRequest {
+        plan: Some("metered".to_string()),
+        ..Default::default()
+    };
+    let parent = client.create_apikey(&request).await.unwrap();
+    let key = parent.key.to_string();
+    let child = client
+        .create_child(&key, &ChildKeyRequest::default())
+        .await
+        .unwrap();
+    let sibling = client
+        .create_child(&key, &ChildKeyRequest::default())
+        .await
+        .unwrap();
+
+    for apikey in [&parent, &child, &sibling] {
+        let res = upload(&lanther, Some(apikey), "counted once").await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+    // The quota is used up by the three of them together, rather than by each on its own
+    for apikey in [&parent, &child, &sibling] {
+        let res = upload(&lanther, Some(apikey), "over quota").await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(error_code(res).await, "quota_exceeded");
+    }
+
+    // And so is the rate limit
+    let limited = create_apikey(&lanther).await;
+    let limits = json!({ "limits": { "requests_per_minute": 2 } });
+    client
+        .patch_apikey(&limited.key.to_string(), &limits, None)
+        .await
+        .unwrap();
+    let child = client
+        .create_child(&limited.key.to_string(), &ChildKeyRequest::default())
+        .await
+        .unwrap();
+    let res = upload(&lanther, Some(&limited), "first").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = upload(&lanther, Some(&child), "second").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let res = upload(&lanther, Some(&child), "third").await;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(error_code(res).await, "rate_limited");
+
+    lanther.stop().await;
+}
@@ -0,0 +1,100 @@
+//! Searching API keys by their key, owner, name and labels, even though names and labels are
+//! encrypted at rest
+use lanther_e2e::Lanther;
+use serde_json::json;
+use simpleapikeys_client::models::{ApiKey, ApiKeyRequest, ApiKeySearch};
+use simpleapikeys_client::{Client, Error};
+
+async fn create_apikey(client: &Client, owner: &str, patch: serde_json::Value) -> ApiKey {
+    let request = ApiKeyRequest {
+        owner: Some(owner.to_string()),
+        ..Default::default()
+    };
+    let apikey = client.create_apikey(&request).await.unwrap();
+    client
+        .patch_apikey(&apikey.key.to_string(), &patch, None)
+        .await
+        .unwrap()
+}
+
+fn keys(search: &ApiKeySearch) -> Vec<String> {
+    search
+        .results
+        .iter()
+        .map(|hit| hit.apikey.key.to_string())
+        .collect()
+}
+
+#[actix_rt::test]
+async fn keys_are_found_by_key_owner_name_and_labels_best_first() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.admin();
+    let billing = create_apikey(
+        &client,
+        "acme",
+        json!({ "name": "Billing worker", "labels": { "team": "payments" } }),
+    )
+    .await;
+    let deploys = create_apikey(
+        &client,
+        "globex",
+        json!({ "name": "Deploys for acme", "labels": { "team": "platform" } }),
+    )
+    .await;
+    let other = create_apikey(&client, "initech", json!({ "name": "Reports" })).await;
+
+    // The owner is worth more than the name, whole words more than prefixes
+    let search = client.search_apikeys("ACME", 1, 20).await.unwrap();
+    assert_eq!(search.total, 2);
+    assert_eq!(
+        keys(&search),
+        vec![billing.key.to_string(), deploys.key.to_string()]
+    );
+    assert!(search.results[0].score > search.results[1].score);
+
+    let search = client.search_apikeys("paym", 1, 20).await.unwrap();
+    assert_eq!(keys(&search), vec![billing.key.to_string()]);
+    let prefix = &other.key.to_string()[..8];
+    let search = client.search_apikeys(prefix, 1, 20).await.unwrap();
+    assert_eq!(keys(&search), vec![other.key.to_string()]);
+    let search = client.search_apikeys("nothing", 1, 20).await.unwrap();
+    assert_eq!(search.total, 0);
+
+    // Pages hold the same ranking, split up
+    let search = client.search_apikeys("acme", 2, 1).await.unwrap();
+    assert_eq!((search.total, search.page), (2, 2));
+    assert_eq!(keys(&search), vec![deploys.key.to_string()]);
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn keys_are_found_by_what_they_are_patched_to() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.admin();
+    let apikey = create_apikey(&client, "acme", json!({ "name": "Billing" })).await;
+
+    client
+        .patch_apikey(
+            &apikey.key.to_string(),
+            &json!({ "name": "Invoices", "labels": { "region": "eu-west" } }),
+            None,
+        )
+        .await
+        .unwrap();
+    let search = client.search_apikeys("billing", 1, 20).await.unwrap();
+    assert_eq!(search.total, 0);
+    let search = client.search_apikeys("invoices west", 1, 20).await.unwrap();
+    assert_eq!(keys(&search), vec![apikey.key.to_string()]);
+
+    for (query, per_page) in [("", 20), ("a", 20), ("acme", 0), ("acme", 101)] {
+        match client.search_apikeys(query, 1, per_page).await {
+            Err(Error::Api { status, error }) => {
+                assert_eq!((status, error.code.as_str()), (422, "validation_failed"))
+            }
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    lanther.stop().await;
+}
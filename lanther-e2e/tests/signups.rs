@@ -0,0 +1,178 @@
+//! Self-service signups: an API key is only handed out once the email signed up with is verified
+use actix_web::client::Client;
+use actix_web::http::StatusCode;
+use lanther_e2e::Lanther;
+use serde_json::{json, Value};
+use simpleapikeys_client::models::{ApiKeyRequest, SignupRequest};
+use simpleapikeys_client::Error;
+
+/// The token in the verification link of an email, undoing any quoted-printable line breaks
+fn verification_token(email: &str) -> String {
+    let email = email.replace("=\r\n", "");
+    let start = email
+        .find("/signups/")
+        .expect("email has a verification link")
+        + "/signups/".len();
+    let end = start
+        + email[start..]
+            .find("/verify")
+            .expect("link ends in /verify");
+    email[start..end].to_string()
+}
+
+fn signup(email: &str) -> SignupRequest {
+    SignupRequest {
+        email: email.to_string(),
+    }
+}
+
+#[actix_rt::test]
+async fn verified_signups_get_an_apikey() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+
+    let pending = client.signup(&signup("ada@example.com")).await.unwrap();
+    assert_eq!(pending.email, "ada@example.com");
+    let emails = lanther.smtp.emails();
+    assert_eq!(emails.len(), 1);
+    assert!(emails[0].contains("ada@example.com"));
+
+    let token = verification_token(&emails[0]);
+    let apikey = client.verify_signup(&token).await.unwrap();
+    assert_eq!(apikey.owner.as_deref(), Some("ada@example.com"));
+    let verification = client.verify(&apikey.key.to_string()).await.unwrap();
+    assert!(!verification.disabled);
+
+    // A verification link only ever creates one API key
+    match client.verify_signup(&token).await {
+        Err(Error::Api { status, error }) => {
+            assert_eq!(status, 404);
+            assert_eq!(error.code, "signup_token_invalid");
+        }
+        other => panic!(
+            "expected signup_token_invalid, got {:?}",
+            other.map(|k| k.key)
+        ),
+    }
+    assert_eq!(client.list_apikeys().await.unwrap().len(), 1);
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn following_a_verification_link_only_asks_to_confirm_it() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+    client.signup(&signup("ada@example.com")).await.unwrap();
+    let token = verification_token(&lanther.smtp.emails()[0]);
+    let link = format!("{}signups/{}/verify", lanther.simpleapikeys, token);
+
+    // Email scanners fetch links, possibly more than once, before the recipient follows them
+    for _ in 0..2 {
+        let mut res = Client::default().get(&link).send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let page = String::from_utf8(res.body().await.unwrap().to_vec()).unwrap();
+        assert!(page.contains(&format!("method=\"post\" action=\"{}\"", link)));
+    }
+    assert!(client.list_apikeys().await.unwrap().is_empty());
+
+    // Submitting the form from a browser gets the API key as a page
+    let mut res = Client::default()
+        .post(&link)
+        .header("Accept", "text/html")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    let page = String::from_utf8(res.body().await.unwrap().to_vec()).unwrap();
+    let apikeys = client.list_apikeys().await.unwrap();
+    assert_eq!(apikeys.len(), 1);
+    assert!(page.contains(&apikeys[0].key.to_string()));
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn unknown_signup_tokens_are_rejected() {
+    let lanther = Lanther::start().await.unwrap();
+
+    let result = lanther.apikeys().verify_signup("not-a-token").await;
+    assert_eq!(result.err().and_then(|e| e.status()), Some(404));
+    assert!(lanther.apikeys().list_apikeys().await.unwrap().is_empty());
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn signups_are_limited_by_ip_address() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+
+    for i in 0..5 {
+        let email = format!("user{}@domain{}.example", i, i);
+        client.signup(&signup(&email)).await.unwrap();
+    }
+    let res = Client::default()
+        .post(format!("{}signups", lanther.simpleapikeys))
+        .send_json(&json!({ "email": "one-too-many@example.com" }))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(res.headers().contains_key("retry-after"));
+    assert_eq!(lanther.smtp.emails().len(), 5);
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn invalid_emails_are_rejected() {
+    let lanther = Lanther::start().await.unwrap();
+
+    let mut res = Client::default()
+        .post(format!("{}signups", lanther.simpleapikeys))
+        .send_json(&json!({ "email": "not an email" }))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(body["error"]["code"], "validation_failed");
+    assert!(lanther.smtp.emails().is_empty());
+
+    lanther.stop().await;
+}
+
+#[actix_rt::test]
+async fn only_the_admin_manages_keys_without_anonymous_creation() {
+    let lanther = Lanther::start_with(|settings| settings.signup.anonymous_creation = false)
+        .await
+        .unwrap();
+    let apikey = lanther
+        .admin()
+        .create_apikey(&ApiKeyRequest::default())
+        .await
+        .unwrap();
+    let key = apikey.key.to_string();
+
+    // Everyone else signs up for a key of their own, and cannot reach those of others
+    let anonymous = lanther.apikeys();
+    let refused = |result: Result<_, Error>| result.err().and_then(|e| e.status()) == Some(401);
+    assert!(refused(anonymous.list_apikeys().await.map(|_| ())));
+    assert!(refused(anonymous.get_apikey(&key).await.map(|_| ())));
+    assert!(refused(
+        anonymous.search_apikeys(&key, 1, 20).await.map(|_| ())
+    ));
+    let disable = json!({ "disabled": true });
+    assert!(refused(
+        anonymous
+            .patch_apikey(&key, &disable, None)
+            .await
+            .map(|_| ())
+    ));
+    assert!(refused(anonymous.delete_apikey(&key).await.map(|_| ())));
+    assert!(!lanther.admin().get_apikey(&key).await.unwrap().disabled);
+
+    // The key itself is still all it takes to be verified
+    assert!(anonymous.verify(&key).await.is_ok());
+
+    lanther.stop().await;
+}
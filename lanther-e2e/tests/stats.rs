@@ -0,0 +1,88 @@
+//! Counts of API keys: the stats, the usage of every owner and the gauges exported as metrics
+use chrono::Utc;
+use lanther_e2e::Lanther;
+use simpleapikeys_client::models::{ApiKey, ApiKeyRequest, Group, OwnerUsage, UpdateApiKey};
+
+async fn create_apikey(lanther: &Lanther, owner: Option<&str>) -> ApiKey {
+    let request = ApiKeyRequest {
+        owner: owner.map(|owner| owner.to_string()),
+        ..Default::default()
+    };
+    lanther.admin().create_apikey(&request).await.unwrap()
+}
+
+fn group(name: Option<&str>, count: i64) -> Group {
+    Group {
+        name: name.map(|name| name.to_string()),
+        count,
+    }
+}
+
+#[actix_rt::test]
+async fn keys_are_counted_by_state_owner_plan_and_month() {
+    let lanther = Lanther::start_with(|settings| {
+        settings.quotas.owners.insert("initech".to_string(), 3);
+    })
+    .await
+    .unwrap();
+    let client = lanther.apikeys();
+    let disabled = create_apikey(&lanther, Some("acme")).await;
+    create_apikey(&lanther, Some("acme")).await;
+    create_apikey(&lanther, Some("globex")).await;
+    create_apikey(&lanther, None).await;
+    let update = UpdateApiKey {
+        key: disabled.key,
+        disabled: true,
+    };
+    client
+        .update_apikey(&update, Some(disabled.version))
+        .await
+        .unwrap();
+
+    let stats = client.stats().await.unwrap();
+    assert_eq!(stats.total, 4);
+    let by_state = vec![
+        group(Some("active"), 3),
+        group(Some("disabled"), 1),
+        group(Some("expired"), 0),
+        group(Some("revoked"), 0),
+    ];
+    assert_eq!(stats.by_state, by_state);
+    // Most keys first, then by name, where no owner comes before any
+    let by_owner = vec![
+        group(Some("acme"), 2),
+        group(None, 1),
+        group(Some("globex"), 1),
+    ];
+    assert_eq!(stats.by_owner, by_owner);
+    assert_eq!(stats.by_plan, vec![group(None, 4)]);
+    let month = Utc::now().format("%Y-%m").to_string();
+    assert_eq!(stats.by_month, vec![group(Some(&month), 4)]);
+
+    let metrics = client.metrics().await.unwrap();
+    assert!(
+        metrics.contains("apikeys{state=\"active\"} 3"),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.contains("apikeys{state=\"disabled\"} 1"),
+        "{}",
+        metrics
+    );
+
+    // Disabled keys are not counted, and owners given a limit are listed even without keys
+    let usage = |owner: &str, active, limit| OwnerUsage {
+        owner: owner.to_string(),
+        active,
+        limit,
+    };
+    let expected = vec![
+        usage("acme", 1, None),
+        usage("globex", 1, None),
+        usage("initech", 0, Some(3)),
+    ];
+    assert_eq!(client.usage().await.unwrap(), expected);
+
+    lanther.stop().await;
+}
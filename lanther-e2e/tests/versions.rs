@@ -0,0 +1,43 @@
+//! Versions of API keys, which writes may be conditioned on, including keys stored before there
+//! were versions
+use bson::doc;
+use lanther_e2e::Lanther;
+use serde_json::json;
+use simpleapikeys_client::models::ApiKeyRequest;
+
+#[actix_rt::test]
+async fn keys_stored_without_a_version_are_at_version_0() {
+    let lanther = Lanther::start().await.unwrap();
+    let client = lanther.apikeys();
+    let apikey = client
+        .create_apikey(&ApiKeyRequest::default())
+        .await
+        .unwrap();
+    let key = apikey.key.to_string();
+    assert_eq!(apikey.version, 1);
+
+    // As if stored before versions, and started with --skip-migrations since
+    let apikeys = lanther.simpleapikeys_db.collection("apikeys");
+    let unset = doc! { "$unset": { "version": "" } };
+    apikeys
+        .update_one(doc! { "key": key.clone() }, unset)
+        .await
+        .unwrap();
+    assert_eq!(client.get_apikey(&key).await.unwrap().version, 0);
+    assert_eq!(client.list_apikeys().await.unwrap()[0].version, 0);
+
+    // Writes conditioned on the version read go through, and version the key from then on
+    let stale = client
+        .patch_apikey(&key, &json!({ "name": "CI" }), Some(1))
+        .await;
+    assert_eq!(stale.err().and_then(|e| e.status()), Some(412));
+    let patched = client
+        .patch_apikey(&key, &json!({ "name": "CI" }), Some(0))
+        .await
+        .unwrap();
+    assert_eq!(patched.version, 1);
+    let search = client.search_apikeys("ci", 1, 20).await.unwrap();
+    assert_eq!(search.total, 1);
+
+    lanther.stop().await;
+}
@@ -0,0 +1,371 @@
+//! Schema migrations and index management for the MongoDB database used by lanther's servers
+//!
+//! Every migration has a version, and the versions applied are recorded in the `migrations`
+//! collection so each migration runs once per database. Two servers sharing a database may start
+//! at the same time and race to apply the same migration, so migrations must be idempotent.
+use std::future::Future;
+use std::pin::Pin;
+
+use chrono::Utc;
+use futures::StreamExt;
+use mongodb::bson::{doc, Document};
+use mongodb::error::{Error, ErrorKind, WriteFailure};
+use mongodb::Database;
+
+/// The collection where applied migrations are recorded
+pub const MIGRATIONS_COLLECTION: &str = "migrations";
+
+/// The fields kept unique by an index, as `(collection, field)` pairs, besides every `_id`
+pub const UNIQUE_INDEXES: [(&str, &str); 3] = [
+    ("apikeys", "key"),
+    ("plans", "name"),
+    ("principals", "name"),
+];
+
+type MigrationFuture = Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>;
+
+/// A single migration over one of the collections in the database
+struct Migration {
+    version: i64,
+    collection: &'static str,
+    description: &'static str,
+    apply: fn(Database) -> MigrationFuture,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum MigrationError {
+    #[error("Migration {version} ({description}) failed: {source}")]
+    MigrationFailed {
+        version: i64,
+        description: &'static str,
+        source: Error,
+    },
+    #[error("MongoDB Operation failed: {source}")]
+    MongoDBOperationError {
+        #[from]
+        source: Error,
+    },
+}
+
+/// All known migrations, in the order they must be applied
+fn migrations() -> Vec<Migration> {
+    vec![
+        Migration {
+            version: 1,
+            collection: "apikeys",
+            description: "Create unique index on apikeys.key",
+            apply: |db| Box::pin(create_apikeys_key_index(db)),
+        },
+        Migration {
+            version: 2,
+            collection: "requests",
+            description: "Create indexes on requests.authorization and requests.created_at",
+            apply: |db| Box::pin(create_requests_indexes(db)),
+        },
+        Migration {
+            version: 3,
+            collection: "apikeys",
+            description: "Backfill apikeys.version",
+            apply: |db| Box::pin(backfill_apikeys_version(db)),
+        },
+        Migration {
+            version: 4,
+            collection: "idempotency_keys",
+            description: "Create TTL index on idempotency_keys.expires_at",
+            apply: |db| Box::pin(create_idempotency_keys_ttl_index(db)),
+        },
+        Migration {
+            version: 5,
+            collection: "plans",
+            description: "Create unique index on plans.name",
+            apply: |db| Box::pin(create_plans_name_index(db)),
+        },
+        Migration {
+            version: 6,
+            collection: "apikeys",
+            description: "Create index on apikeys.plan",
+            apply: |db| Box::pin(create_apikeys_plan_index(db)),
+        },
+        Migration {
+            version: 7,
+            collection: "audit_log",
+            description: "Create index on audit_log.key and audit_log.created_at",
+            apply: |db| Box::pin(create_audit_log_index(db)),
+        },
+        Migration {
+            version: 8,
+            collection: "apikeys",
+            description: "Create index on apikeys.parent",
+            apply: |db| Box::pin(create_apikeys_parent_index(db)),
+        },
+        Migration {
+            version: 9,
+            collection: "apikeys",
+            description: "Create index on apikeys.owner",
+            apply: |db| Box::pin(create_apikeys_owner_index(db)),
+        },
+        Migration {
+            version: 10,
+            collection: "signups",
+            description: "Create TTL index on signups.expires_at and indexes on signups.ip and signups.domain",
+            apply: |db| Box::pin(create_signups_indexes(db)),
+        },
+        Migration {
+            version: 11,
+            collection: "principals",
+            description: "Create unique index on principals.name",
+            apply: |db| Box::pin(create_principals_name_index(db)),
+        },
+        Migration {
+            version: 12,
+            collection: "apikeys",
+            description: "Create index on apikeys.principal",
+            apply: |db| Box::pin(create_apikeys_principal_index(db)),
+        },
+        Migration {
+            version: 13,
+            collection: "apikeys",
+            description: "Create index on apikeys.search_terms",
+            apply: |db| Box::pin(create_apikeys_search_terms_index(db)),
+        },
+        Migration {
+            version: 14,
+            collection: "requests",
+            description: "Create index on requests.root and requests.created_at",
+            apply: |db| Box::pin(create_requests_root_index(db)),
+        },
+        Migration {
+            version: 15,
+            collection: "apikeys",
+            description: "Create index on apikeys.created_at",
+            apply: |db| Box::pin(create_apikeys_created_at_index(db)),
+        },
+    ]
+}
+
+/// Apply any pending migrations over the given collections, returning the versions applied
+pub async fn run(db: &Database, collections: &[&str]) -> Result<Vec<i64>, MigrationError> {
+    let records = db.collection(MIGRATIONS_COLLECTION);
+    let mut applied: Vec<i64> = Vec::new();
+    let mut cursor = records.find(None, None).await?;
+    while let Some(doc) = cursor.next().await {
+        if let Ok(version) = doc?.get_i64("_id") {
+            applied.push(version);
+        }
+    }
+
+    let mut result = Vec::new();
+    for migration in migrations() {
+        if !collections.contains(&migration.collection) || applied.contains(&migration.version) {
+            continue;
+        }
+
+        log::info!(
+            "Applying migration {}: {}",
+            migration.version,
+            migration.description
+        );
+        (migration.apply)(db.clone())
+            .await
+            .map_err(|source| MigrationError::MigrationFailed {
+                version: migration.version,
+                description: migration.description,
+                source,
+            })?;
+
+        let record = doc! {
+            "_id": migration.version,
+            "collection": migration.collection,
+            "description": migration.description,
+            "applied_at": Utc::now(),
+        };
+        match records.insert_one(record, None).await {
+            Ok(_) => result.push(migration.version),
+            Err(e) if is_duplicate_key_error(&e) => {
+                log::info!(
+                    "Migration {} was concurrently applied elsewhere",
+                    migration.version
+                );
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(result)
+}
+
+/// Whether an error was caused by a write violating a unique index
+pub fn is_duplicate_key_error(err: &Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::WriteError(WriteFailure::WriteError(e)) if e.code == 11000
+    )
+}
+
+/// Create indexes on a collection. Creating an index that already exists is a no-op.
+async fn create_indexes(
+    db: &Database,
+    collection: &str,
+    indexes: Vec<Document>,
+) -> Result<(), Error> {
+    let command = doc! {
+        "createIndexes": collection,
+        "indexes": indexes,
+    };
+    db.run_command(command, None).await?;
+    Ok(())
+}
+
+async fn create_apikeys_key_index(db: Database) -> Result<(), Error> {
+    let indexes = vec![doc! {
+        "key": { "key": 1 },
+        "name": "key_unique",
+        "unique": true,
+    }];
+    create_indexes(&db, "apikeys", indexes).await
+}
+
+async fn create_requests_indexes(db: Database) -> Result<(), Error> {
+    let indexes = vec![
+        doc! {
+            "key": { "authorization": 1, "created_at": -1 },
+            "name": "authorization_created_at",
+        },
+        doc! {
+            "key": { "created_at": -1 },
+            "name": "created_at",
+        },
+    ];
+    create_indexes(&db, "requests", indexes).await
+}
+
+async fn backfill_apikeys_version(db: Database) -> Result<(), Error> {
+    let filter = doc! {
+        "version": { "$exists": false },
+    };
+    let update = doc! {
+        "$set": { "version": 1i64 },
+    };
+    db.collection("apikeys")
+        .update_many(filter, update, None)
+        .await?;
+    Ok(())
+}
+
+async fn create_idempotency_keys_ttl_index(db: Database) -> Result<(), Error> {
+    let indexes = vec![doc! {
+        "key": { "expires_at": 1 },
+        "name": "expires_at_ttl",
+        "expireAfterSeconds": 0,
+    }];
+    create_indexes(&db, "idempotency_keys", indexes).await
+}
+
+async fn create_plans_name_index(db: Database) -> Result<(), Error> {
+    let indexes = vec![doc! {
+        "key": { "name": 1 },
+        "name": "name_unique",
+        "unique": true,
+    }];
+    create_indexes(&db, "plans", indexes).await
+}
+
+async fn create_apikeys_plan_index(db: Database) -> Result<(), Error> {
+    let indexes = vec![doc! {
+        "key": { "plan": 1 },
+        "name": "plan",
+    }];
+    create_indexes(&db, "apikeys", indexes).await
+}
+
+async fn create_audit_log_index(db: Database) -> Result<(), Error> {
+    let indexes = vec![doc! {
+        "key": { "key": 1, "created_at": -1 },
+        "name": "key_created_at",
+    }];
+    create_indexes(&db, "audit_log", indexes).await
+}
+
+async fn create_apikeys_parent_index(db: Database) -> Result<(), Error> {
+    let indexes = vec![doc! {
+        "key": { "parent": 1 },
+        "name": "parent",
+        // Only child keys have a parent, so there is no point indexing the rest
+        "partialFilterExpression": { "parent": { "$exists": true } },
+    }];
+    create_indexes(&db, "apikeys", indexes).await
+}
+
+async fn create_apikeys_owner_index(db: Database) -> Result<(), Error> {
+    let indexes = vec![doc! {
+        "key": { "owner": 1 },
+        "name": "owner",
+    }];
+    create_indexes(&db, "apikeys", indexes).await
+}
+
+async fn create_signups_indexes(db: Database) -> Result<(), Error> {
+    let indexes = vec![
+        doc! {
+            "key": { "expires_at": 1 },
+            "name": "expires_at_ttl",
+            // Expired signups can no longer be verified, nor count against the limits
+            "expireAfterSeconds": 0,
+        },
+        doc! {
+            "key": { "ip": 1, "created_at": -1 },
+            "name": "ip_created_at",
+        },
+        doc! {
+            "key": { "domain": 1, "created_at": -1 },
+            "name": "domain_created_at",
+        },
+    ];
+    create_indexes(&db, "signups", indexes).await
+}
+
+async fn create_principals_name_index(db: Database) -> Result<(), Error> {
+    let indexes = vec![doc! {
+        "key": { "name": 1 },
+        "name": "name_unique",
+        "unique": true,
+    }];
+    create_indexes(&db, "principals", indexes).await
+}
+
+async fn create_apikeys_principal_index(db: Database) -> Result<(), Error> {
+    let indexes = vec![doc! {
+        "key": { "principal": 1 },
+        "name": "principal",
+        // Only keys acting for a principal have one, so there is no point indexing the rest
+        "partialFilterExpression": { "principal": { "$exists": true } },
+    }];
+    create_indexes(&db, "apikeys", indexes).await
+}
+
+async fn create_apikeys_search_terms_index(db: Database) -> Result<(), Error> {
+    // The terms are keyed hashes of words in encrypted fields, which a text index cannot look into
+    let indexes = vec![doc! {
+        "key": { "search_terms": 1 },
+        "name": "search_terms",
+    }];
+    create_indexes(&db, "apikeys", indexes).await
+}
+
+async fn create_requests_root_index(db: Database) -> Result<(), Error> {
+    // Monthly quotas are counted over a key and its children, by the key they descend from
+    let indexes = vec![doc! {
+        "key": { "root": 1, "created_at": -1 },
+        "name": "root_created_at",
+    }];
+    create_indexes(&db, "requests", indexes).await
+}
+
+async fn create_apikeys_created_at_index(db: Database) -> Result<(), Error> {
+    // The dashboard lists the most recently created API keys first
+    let indexes = vec![doc! {
+        "key": { "created_at": -1 },
+        "name": "created_at",
+    }];
+    create_indexes(&db, "apikeys", indexes).await
+}
@@ -0,0 +1,112 @@
+//! Connecting the servers to MongoDB, as configured under `mongo`
+use std::path::PathBuf;
+use std::time::Duration;
+
+use actix_rt::time::{delay_for, timeout};
+use mongodb::bson::doc;
+use mongodb::options::{ClientOptions, Credential};
+use mongodb::{Client, Database};
+use serde::Deserialize;
+
+/// How long a single connection attempt may take before it is retried
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest wait between connection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The `mongo` settings of a server, which each server reads and validates along with the rest
+#[derive(Debug, Default, Deserialize)]
+pub struct MongoSettings {
+    pub address: Option<String>,
+    pub database: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub password_file: Option<PathBuf>,
+    /// How many times to try reaching Mongo DB on startup
+    pub connect_attempts: u32,
+    /// Seconds to wait before the first retry, doubled after every failed attempt
+    pub connect_backoff: u64,
+}
+
+impl MongoSettings {
+    /// Build the options to connect to Mongo DB, with credentials kept out of the address
+    pub async fn client_options(&self) -> mongodb::error::Result<ClientOptions> {
+        let address = self.address.as_deref().unwrap_or_default();
+        let mut options = ClientOptions::parse(address).await?;
+        if let Some(username) = &self.username {
+            options.credential = Some(
+                Credential::builder()
+                    .username(Some(username.clone()))
+                    .password(self.password.clone())
+                    .build(),
+            );
+        }
+        Ok(options)
+    }
+
+    pub fn database(&self) -> &str {
+        self.database.as_deref().unwrap_or_default()
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConnectError {
+    #[error("Invalid Mongo DB options: {0}")]
+    InvalidOptions(#[source] mongodb::error::Error),
+    #[error("Could not reach Mongo DB at {address} after {attempts} attempts: {reason}")]
+    Unreachable {
+        address: String,
+        attempts: u32,
+        reason: String,
+    },
+}
+
+/// Connect to Mongo DB, retrying with an exponential backoff until it answers a ping
+///
+/// Mongo DB may still be starting up alongside the server, so failing to reach it is retried up
+/// to `mongo.connect_attempts` times. Invalid options are never retried.
+pub async fn connect(settings: &MongoSettings) -> Result<Database, ConnectError> {
+    let options = settings
+        .client_options()
+        .await
+        .map_err(ConnectError::InvalidOptions)?;
+    // The address may embed credentials, so only the hosts are ever reported
+    let address = options
+        .hosts
+        .iter()
+        .map(|host| host.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    let client = Client::with_options(options).map_err(ConnectError::InvalidOptions)?;
+    let db = client.database(settings.database());
+
+    let mut backoff = Duration::from_secs(settings.connect_backoff);
+    let mut attempt = 1;
+    loop {
+        let reason = match timeout(ATTEMPT_TIMEOUT, db.run_command(doc! { "ping": 1 }, None)).await
+        {
+            Ok(Ok(_)) => return Ok(db),
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("timed out after {:?}", ATTEMPT_TIMEOUT),
+        };
+        if attempt >= settings.connect_attempts {
+            return Err(ConnectError::Unreachable {
+                address,
+                attempts: attempt,
+                reason,
+            });
+        }
+
+        log::warn!(
+            "Could not reach Mongo DB at {} (attempt {} of {}), retrying in {:?}: {}",
+            address,
+            attempt,
+            settings.connect_attempts,
+            backoff,
+            reason
+        );
+        delay_for(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        attempt += 1;
+    }
+}
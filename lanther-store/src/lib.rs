@@ -0,0 +1,249 @@
+//! Storage for lanther's servers, either in MongoDB or in memory
+//!
+//! Services operate on a `Collection`, which mirrors the subset of MongoDB's API they use, so they
+//! run the same way against MongoDB in production and against an in-memory stand-in in tests.
+//! The stand-in understands the filters, updates and aggregations the services send, and enforces
+//! the unique indexes created by `lanther-migrations`.
+use futures::stream::{BoxStream, StreamExt};
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument};
+
+pub mod connect;
+pub mod memory;
+
+pub use connect::{connect, ConnectError, MongoSettings};
+
+/// Documents returned by a query, as they are read
+pub type Cursor = BoxStream<'static, Result<Document, Error>>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("{0}")]
+    Mongo(#[from] mongodb::error::Error),
+    #[error("Duplicate key: {collection}.{field} must be unique")]
+    DuplicateKey {
+        collection: String,
+        field: &'static str,
+    },
+    #[error("Not supported in memory: {0}")]
+    Unsupported(String),
+}
+
+impl Error {
+    /// Whether the error was caused by a write violating a unique index
+    pub fn is_duplicate_key(&self) -> bool {
+        match self {
+            Error::Mongo(e) => lanther_migrations::is_duplicate_key_error(e),
+            Error::DuplicateKey { .. } => true,
+            Error::Unsupported(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InsertOneResult {
+    pub inserted_id: Bson,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct UpdateResult {
+    pub matched_count: i64,
+    pub modified_count: i64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DeleteResult {
+    pub deleted_count: i64,
+}
+
+/// A database holding the collections of a server
+#[derive(Clone)]
+pub enum Database {
+    Mongo(mongodb::Database),
+    Memory(memory::Database),
+}
+
+impl Database {
+    pub fn collection(&self, name: &str) -> Collection {
+        match self {
+            Database::Mongo(db) => Collection::Mongo(db.collection(name)),
+            Database::Memory(db) => Collection::Memory(db.collection(name)),
+        }
+    }
+
+    /// Check the database answers, which the in-memory stand-in always does
+    pub async fn ping(&self) -> Result<(), Error> {
+        match self {
+            Database::Mongo(db) => {
+                db.run_command(doc! { "ping": 1 }, None).await?;
+                Ok(())
+            }
+            Database::Memory(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Collection {
+    Mongo(mongodb::Collection),
+    Memory(memory::Collection),
+}
+
+impl Collection {
+    pub async fn insert_one(&self, document: Document) -> Result<InsertOneResult, Error> {
+        match self {
+            Collection::Mongo(collection) => {
+                let result = collection.insert_one(document, None).await?;
+                Ok(InsertOneResult {
+                    inserted_id: result.inserted_id,
+                })
+            }
+            Collection::Memory(collection) => collection.insert_one(document),
+        }
+    }
+
+    pub async fn find_one(&self, filter: Document) -> Result<Option<Document>, Error> {
+        match self {
+            Collection::Mongo(collection) => Ok(collection.find_one(filter, None).await?),
+            Collection::Memory(collection) => collection.find_one(&filter),
+        }
+    }
+
+    /// Find the documents matching a filter, or every document without one
+    pub async fn find(&self, filter: impl Into<Option<Document>>) -> Result<Cursor, Error> {
+        let filter = filter.into();
+        match self {
+            Collection::Mongo(collection) => {
+                let cursor = collection.find(filter, None).await?;
+                Ok(cursor.map(|doc| doc.map_err(Error::from)).boxed())
+            }
+            Collection::Memory(collection) => {
+                let docs = collection.find(&filter.unwrap_or_default())?;
+                Ok(futures::stream::iter(docs.into_iter().map(Ok)).boxed())
+            }
+        }
+    }
+
+    /// Find the documents matching a filter in the order of a sort, like `{ "created_at": -1 }`,
+    /// up to `limit` of them when given
+    pub async fn find_sorted(
+        &self,
+        filter: Document,
+        sort: Document,
+        limit: Option<i64>,
+    ) -> Result<Cursor, Error> {
+        match self {
+            Collection::Mongo(collection) => {
+                let options = FindOptions::builder().sort(sort).limit(limit).build();
+                let cursor = collection.find(filter, options).await?;
+                Ok(cursor.map(|doc| doc.map_err(Error::from)).boxed())
+            }
+            Collection::Memory(collection) => {
+                let mut docs = collection.find(&filter)?;
+                memory::sort(&mut docs, &sort)?;
+                if let Some(limit) = limit {
+                    docs.truncate(limit.max(0) as usize);
+                }
+                Ok(futures::stream::iter(docs.into_iter().map(Ok)).boxed())
+            }
+        }
+    }
+
+    /// Update the first document matching a filter, returning it as written
+    pub async fn find_one_and_update(
+        &self,
+        filter: Document,
+        update: Document,
+    ) -> Result<Option<Document>, Error> {
+        match self {
+            Collection::Mongo(collection) => {
+                let options = FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build();
+                Ok(collection
+                    .find_one_and_update(filter, update, options)
+                    .await?)
+            }
+            Collection::Memory(collection) => collection.find_one_and_update(&filter, &update),
+        }
+    }
+
+    pub async fn update_one(
+        &self,
+        filter: Document,
+        update: Document,
+    ) -> Result<UpdateResult, Error> {
+        match self {
+            Collection::Mongo(collection) => {
+                let result = collection.update_one(filter, update, None).await?;
+                Ok(UpdateResult {
+                    matched_count: result.matched_count,
+                    modified_count: result.modified_count,
+                })
+            }
+            Collection::Memory(collection) => collection.update(&filter, &update, Some(1)),
+        }
+    }
+
+    pub async fn update_many(
+        &self,
+        filter: Document,
+        update: Document,
+    ) -> Result<UpdateResult, Error> {
+        match self {
+            Collection::Mongo(collection) => {
+                let result = collection.update_many(filter, update, None).await?;
+                Ok(UpdateResult {
+                    matched_count: result.matched_count,
+                    modified_count: result.modified_count,
+                })
+            }
+            Collection::Memory(collection) => collection.update(&filter, &update, None),
+        }
+    }
+
+    pub async fn delete_one(&self, filter: Document) -> Result<DeleteResult, Error> {
+        match self {
+            Collection::Mongo(collection) => {
+                let result = collection.delete_one(filter, None).await?;
+                Ok(DeleteResult {
+                    deleted_count: result.deleted_count,
+                })
+            }
+            Collection::Memory(collection) => collection.delete(&filter, Some(1)),
+        }
+    }
+
+    pub async fn delete_many(&self, filter: Document) -> Result<DeleteResult, Error> {
+        match self {
+            Collection::Mongo(collection) => {
+                let result = collection.delete_many(filter, None).await?;
+                Ok(DeleteResult {
+                    deleted_count: result.deleted_count,
+                })
+            }
+            Collection::Memory(collection) => collection.delete(&filter, None),
+        }
+    }
+
+    pub async fn count_documents(&self, filter: Document) -> Result<i64, Error> {
+        match self {
+            Collection::Mongo(collection) => Ok(collection.count_documents(filter, None).await?),
+            Collection::Memory(collection) => Ok(collection.find(&filter)?.len() as i64),
+        }
+    }
+
+    /// Run an aggregation pipeline
+    pub async fn aggregate(&self, pipeline: Vec<Document>) -> Result<Cursor, Error> {
+        match self {
+            Collection::Mongo(collection) => {
+                let cursor = collection.aggregate(pipeline, None).await?;
+                Ok(cursor.map(|doc| doc.map_err(Error::from)).boxed())
+            }
+            Collection::Memory(collection) => {
+                let docs = collection.aggregate(&pipeline)?;
+                Ok(futures::stream::iter(docs.into_iter().map(Ok)).boxed())
+            }
+        }
+    }
+}
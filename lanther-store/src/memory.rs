@@ -0,0 +1,701 @@
+//! An in-memory stand-in for MongoDB, to run servers without one, like in tests
+//!
+//! Filters may compare fields, including dotted paths into sub-documents, by equality or with
+//! `$eq`, `$ne`, `$in`, `$nin`, `$exists`, `$gt`, `$gte`, `$lt` and `$lte`, and combine them with
+//! `$and` and `$or`. Updates may use `$set`, `$unset` and `$inc`, and sorts may order by any
+//! fields, ascending or descending. Aggregations may use the `$match`, `$group`, `$sort`, `$limit`
+//! and `$facet` stages, grouping with `$sum` over expressions made of field paths, literals,
+//! `$eq`, `$ne`, `$gt`, `$gte`, `$lt`, `$lte`, `$and`, `$or`, `$type`, `$switch` and
+//! `$dateToString`. Anything else is rejected with `Error::Unsupported`, rather than silently
+//! matching or writing the wrong documents.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{Bson, Document};
+
+use crate::{DeleteResult, Error, InsertOneResult, UpdateResult};
+
+/// Collections kept in memory, shared by every clone of the database
+#[derive(Clone, Default)]
+pub struct Database {
+    collections: Arc<Mutex<HashMap<String, Collection>>>,
+}
+
+impl Database {
+    pub fn new() -> Self {
+        Database::default()
+    }
+
+    pub fn collection(&self, name: &str) -> Collection {
+        let mut collections = self.collections.lock().expect("database lock is poisoned");
+        collections
+            .entry(name.to_string())
+            .or_insert_with(|| Collection {
+                name: name.to_string(),
+                unique: lanther_migrations::UNIQUE_INDEXES
+                    .iter()
+                    .filter(|(collection, _)| *collection == name)
+                    .map(|(_, field)| *field)
+                    .chain(std::iter::once("_id"))
+                    .collect(),
+                documents: Arc::default(),
+            })
+            .clone()
+    }
+}
+
+/// Documents kept in the order they were inserted in, like MongoDB's natural order
+#[derive(Clone)]
+pub struct Collection {
+    name: String,
+    /// Fields no two documents may share a value of, as enforced by a unique index
+    unique: Vec<&'static str>,
+    documents: Arc<Mutex<Vec<Document>>>,
+}
+
+impl Collection {
+    fn documents(&self) -> MutexGuard<'_, Vec<Document>> {
+        self.documents.lock().expect("collection lock is poisoned")
+    }
+
+    pub fn insert_one(&self, mut document: Document) -> Result<InsertOneResult, Error> {
+        if !document.contains_key("_id") {
+            document.insert("_id", ObjectId::new());
+        }
+        let mut documents = self.documents();
+        self.check_unique(&documents, &document, None)?;
+        let inserted_id = document.get("_id").cloned().unwrap_or(Bson::Null);
+        documents.push(document);
+        Ok(InsertOneResult { inserted_id })
+    }
+
+    pub fn find_one(&self, filter: &Document) -> Result<Option<Document>, Error> {
+        for document in self.documents().iter() {
+            if matches(document, filter)? {
+                return Ok(Some(document.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn find(&self, filter: &Document) -> Result<Vec<Document>, Error> {
+        let mut found = Vec::new();
+        for document in self.documents().iter() {
+            if matches(document, filter)? {
+                found.push(document.clone());
+            }
+        }
+        Ok(found)
+    }
+
+    pub fn find_one_and_update(
+        &self,
+        filter: &Document,
+        update: &Document,
+    ) -> Result<Option<Document>, Error> {
+        let mut documents = self.documents();
+        for i in 0..documents.len() {
+            if matches(&documents[i], filter)? {
+                let updated = apply(&documents[i], update)?;
+                self.check_unique(&documents, &updated, Some(i))?;
+                documents[i] = updated.clone();
+                return Ok(Some(updated));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Update the documents matching a filter, up to `limit` of them when given
+    pub fn update(
+        &self,
+        filter: &Document,
+        update: &Document,
+        limit: Option<usize>,
+    ) -> Result<UpdateResult, Error> {
+        let mut documents = self.documents();
+        let mut result = UpdateResult {
+            matched_count: 0,
+            modified_count: 0,
+        };
+        for i in 0..documents.len() {
+            if limit.is_some_and(|limit| result.matched_count as usize >= limit) {
+                break;
+            }
+            if !matches(&documents[i], filter)? {
+                continue;
+            }
+            result.matched_count += 1;
+            let updated = apply(&documents[i], update)?;
+            if updated != documents[i] {
+                self.check_unique(&documents, &updated, Some(i))?;
+                documents[i] = updated;
+                result.modified_count += 1;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Delete the documents matching a filter, up to `limit` of them when given
+    pub fn delete(&self, filter: &Document, limit: Option<usize>) -> Result<DeleteResult, Error> {
+        let mut documents = self.documents();
+        let mut deleted_count = 0;
+        let mut i = 0;
+        while i < documents.len() {
+            if limit.is_some_and(|limit| deleted_count as usize >= limit) {
+                break;
+            }
+            if matches(&documents[i], filter)? {
+                documents.remove(i);
+                deleted_count += 1;
+            } else {
+                i += 1;
+            }
+        }
+        Ok(DeleteResult { deleted_count })
+    }
+
+    /// Run an aggregation pipeline over every document, in their natural order
+    pub fn aggregate(&self, pipeline: &[Document]) -> Result<Vec<Document>, Error> {
+        let documents = self.documents().clone();
+        run(documents, pipeline)
+    }
+
+    /// Check a document written at `position`, or added when none, shares no unique value
+    fn check_unique(
+        &self,
+        documents: &[Document],
+        document: &Document,
+        position: Option<usize>,
+    ) -> Result<(), Error> {
+        for field in &self.unique {
+            let value = document.get(field);
+            let taken = documents
+                .iter()
+                .enumerate()
+                .any(|(i, other)| Some(i) != position && other.get(field) == value);
+            if taken {
+                return Err(Error::DuplicateKey {
+                    collection: self.name.clone(),
+                    field,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sort documents by the fields of a sort, each ascending given `1` and descending given `-1`,
+/// keeping the natural order among equals
+pub fn sort(documents: &mut [Document], sort: &Document) -> Result<(), Error> {
+    let mut fields = Vec::with_capacity(sort.len());
+    for (path, direction) in sort {
+        match number(direction) {
+            Some(n) if n == 1.0 || n == -1.0 => fields.push((path, n < 0.0)),
+            _ => return Err(unsupported("$sort", direction)),
+        }
+    }
+    documents.sort_by(|a, b| {
+        fields
+            .iter()
+            .map(|(path, descending)| {
+                let ordering = order(get(a, path), get(b, path));
+                if *descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    });
+    Ok(())
+}
+
+/// Order two values of any kind, where missing values come first, then values by their kind, as
+/// MongoDB orders them when sorting
+fn order(a: Option<&Bson>, b: Option<&Bson>) -> Ordering {
+    fn rank(value: Option<&Bson>) -> u8 {
+        match value {
+            None | Some(Bson::Null) => 0,
+            Some(Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_)) => 1,
+            Some(Bson::String(_)) => 2,
+            Some(Bson::Document(_)) => 3,
+            Some(Bson::Array(_)) => 4,
+            Some(Bson::ObjectId(_)) => 6,
+            Some(Bson::Boolean(_)) => 7,
+            Some(Bson::DateTime(_)) => 8,
+            Some(_) => 5,
+        }
+    }
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            compare(Some(a), b).unwrap_or_else(|| rank(Some(a)).cmp(&rank(Some(b))))
+        }
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// Whether a document matches a filter
+fn matches(document: &Document, filter: &Document) -> Result<bool, Error> {
+    for (key, condition) in filter {
+        let matched = match key.as_str() {
+            "$and" | "$or" => {
+                let filters = match condition {
+                    Bson::Array(filters) => filters,
+                    _ => return Err(unsupported(key, condition)),
+                };
+                let mut results = Vec::with_capacity(filters.len());
+                for filter in filters {
+                    match filter {
+                        Bson::Document(filter) => results.push(matches(document, filter)?),
+                        _ => return Err(unsupported(key, condition)),
+                    }
+                }
+                if key == "$and" {
+                    results.iter().all(|matched| *matched)
+                } else {
+                    results.iter().any(|matched| *matched)
+                }
+            }
+            key if key.starts_with('$') => return Err(unsupported(key, condition)),
+            path => matches_condition(get(document, path), condition)?,
+        };
+        if !matched {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Whether the value of a field, if any, matches a condition on it
+fn matches_condition(value: Option<&Bson>, condition: &Bson) -> Result<bool, Error> {
+    let operators = match condition {
+        Bson::Document(operators) if operators.keys().all(|key| key.starts_with('$')) => operators,
+        expected => return Ok(equals(value, expected)),
+    };
+
+    for (operator, operand) in operators {
+        let matched = match operator.as_str() {
+            "$eq" => equals(value, operand),
+            "$ne" => !equals(value, operand),
+            "$in" | "$nin" => {
+                let candidates = match operand {
+                    Bson::Array(candidates) => candidates,
+                    _ => return Err(unsupported(operator, operand)),
+                };
+                let found = candidates.iter().any(|candidate| equals(value, candidate));
+                found == (operator == "$in")
+            }
+            "$exists" => value.is_some() == truthy(operand),
+            "$gt" => compare(value, operand) == Some(Ordering::Greater),
+            "$gte" => matches!(
+                compare(value, operand),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+            "$lt" => compare(value, operand) == Some(Ordering::Less),
+            "$lte" => matches!(
+                compare(value, operand),
+                Some(Ordering::Less | Ordering::Equal)
+            ),
+            _ => return Err(unsupported(operator, operand)),
+        };
+        if !matched {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Whether a field equals a value, where a missing field equals `null` and an array equals any
+/// of its elements
+fn equals(value: Option<&Bson>, expected: &Bson) -> bool {
+    match value {
+        None => *expected == Bson::Null,
+        Some(Bson::Array(elements)) if !matches!(expected, Bson::Array(_)) => elements
+            .iter()
+            .any(|element| compare(Some(element), expected) == Some(Ordering::Equal)),
+        Some(value) => value == expected || compare(Some(value), expected) == Some(Ordering::Equal),
+    }
+}
+
+/// Order two values of the same kind, where numbers of any type are compared with each other
+fn compare(value: Option<&Bson>, other: &Bson) -> Option<Ordering> {
+    match (value?, other) {
+        (Bson::String(a), Bson::String(b)) => Some(a.cmp(b)),
+        (Bson::DateTime(a), Bson::DateTime(b)) => Some(a.cmp(b)),
+        (Bson::Boolean(a), Bson::Boolean(b)) => Some(a.cmp(b)),
+        (Bson::ObjectId(a), Bson::ObjectId(b)) => Some(a.bytes().cmp(&b.bytes())),
+        (Bson::Null, Bson::Null) => Some(Ordering::Equal),
+        (a, b) => number(a)?.partial_cmp(&number(b)?),
+    }
+}
+
+fn number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(n) => Some(*n as f64),
+        Bson::Int64(n) => Some(*n as f64),
+        Bson::Double(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Bson) -> bool {
+    match value {
+        Bson::Boolean(b) => *b,
+        Bson::Null => false,
+        value => number(value) != Some(0.0),
+    }
+}
+
+/// The value at a dotted path into a document
+fn get<'a>(document: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut fields = path.split('.');
+    let mut value = document.get(fields.next()?)?;
+    for field in fields {
+        value = match value {
+            Bson::Document(document) => document.get(field)?,
+            _ => return None,
+        };
+    }
+    Some(value)
+}
+
+/// Set the value at a dotted path into a document, creating the sub-documents along the way
+fn set(document: &mut Document, path: &str, value: Bson) -> Result<(), Error> {
+    match path.split_once('.') {
+        None => {
+            document.insert(path, value);
+            Ok(())
+        }
+        Some((field, rest)) => {
+            if !document.contains_key(field) {
+                document.insert(field, Document::new());
+            }
+            match document.get_mut(field) {
+                Some(Bson::Document(inner)) => set(inner, rest, value),
+                _ => Err(Error::Unsupported(format!(
+                    "setting {} within a value that is not a document",
+                    path
+                ))),
+            }
+        }
+    }
+}
+
+fn unset(document: &mut Document, path: &str) {
+    match path.split_once('.') {
+        None => {
+            document.remove(path);
+        }
+        Some((field, rest)) => {
+            if let Some(Bson::Document(inner)) = document.get_mut(field) {
+                unset(inner, rest);
+            }
+        }
+    }
+}
+
+/// Apply an update made of `$set`, `$unset` and `$inc` operators to a copy of a document
+fn apply(document: &Document, update: &Document) -> Result<Document, Error> {
+    let mut updated = document.clone();
+    for (operator, fields) in update {
+        let fields = match fields {
+            Bson::Document(fields) => fields,
+            _ => return Err(unsupported(operator, fields)),
+        };
+        for (path, value) in fields {
+            match operator.as_str() {
+                "$set" => set(&mut updated, path, value.clone())?,
+                "$unset" => unset(&mut updated, path),
+                "$inc" => {
+                    let sum = match (get(&updated, path), value) {
+                        (None, value) => value.clone(),
+                        (Some(Bson::Int32(a)), Bson::Int32(b)) => Bson::Int32(a + b),
+                        (Some(Bson::Int64(a)), Bson::Int64(b)) => Bson::Int64(a + b),
+                        (Some(Bson::Int32(a)), Bson::Int64(b)) => Bson::Int64(*a as i64 + b),
+                        (Some(Bson::Int64(a)), Bson::Int32(b)) => Bson::Int64(a + *b as i64),
+                        (Some(a), b) => match (number(a), number(b)) {
+                            (Some(a), Some(b)) => Bson::Double(a + b),
+                            _ => return Err(unsupported(operator, value)),
+                        },
+                    };
+                    set(&mut updated, path, sum)?;
+                }
+                _ => return Err(unsupported(operator, value)),
+            }
+        }
+    }
+    Ok(updated)
+}
+
+/// Run the stages of a pipeline one after the other over some documents
+fn run(mut documents: Vec<Document>, pipeline: &[Document]) -> Result<Vec<Document>, Error> {
+    for stage in pipeline {
+        let (operator, operand) = match stage.iter().next() {
+            Some(only) if stage.len() == 1 => only,
+            _ => return Err(Error::Unsupported(format!("stage {}", stage))),
+        };
+        documents = match (operator.as_str(), operand) {
+            ("$match", Bson::Document(filter)) => {
+                let mut matched = Vec::with_capacity(documents.len());
+                for document in documents {
+                    if matches(&document, filter)? {
+                        matched.push(document);
+                    }
+                }
+                matched
+            }
+            ("$group", Bson::Document(group)) => self::group(&documents, group)?,
+            ("$sort", Bson::Document(order)) => {
+                sort(&mut documents, order)?;
+                documents
+            }
+            ("$limit", limit) => match number(limit) {
+                Some(limit) if limit >= 0.0 => documents.into_iter().take(limit as usize).collect(),
+                _ => return Err(unsupported(operator, limit)),
+            },
+            ("$facet", Bson::Document(facets)) => {
+                let mut result = Document::new();
+                for (name, facet) in facets {
+                    let facet = match facet {
+                        Bson::Array(stages) => stages
+                            .iter()
+                            .map(|stage| match stage {
+                                Bson::Document(stage) => Ok(stage.clone()),
+                                _ => Err(unsupported(operator, facet)),
+                            })
+                            .collect::<Result<Vec<_>, _>>()?,
+                        _ => return Err(unsupported(operator, facet)),
+                    };
+                    let output = run(documents.clone(), &facet)?;
+                    result.insert(
+                        name,
+                        output.into_iter().map(Bson::Document).collect::<Vec<_>>(),
+                    );
+                }
+                vec![result]
+            }
+            _ => return Err(unsupported(operator, operand)),
+        };
+    }
+    Ok(documents)
+}
+
+/// Group documents by the value of the `_id` expression, in the order each group is first seen,
+/// summing the other fields with `$sum`
+fn group(documents: &[Document], group: &Document) -> Result<Vec<Document>, Error> {
+    let id = group
+        .get("_id")
+        .ok_or_else(|| Error::Unsupported(format!("$group without _id: {}", group)))?;
+    let mut groups: Vec<Document> = Vec::new();
+    for document in documents {
+        let key = evaluate(document, id)?.unwrap_or(Bson::Null);
+        let position = match groups
+            .iter()
+            .position(|group| group.get("_id") == Some(&key))
+        {
+            Some(position) => position,
+            None => {
+                let mut new = Document::new();
+                new.insert("_id", key);
+                groups.push(new);
+                groups.len() - 1
+            }
+        };
+        for (field, accumulator) in group.iter().filter(|(field, _)| *field != "_id") {
+            let operand = match accumulator {
+                Bson::Document(accumulator) if accumulator.len() == 1 => {
+                    match accumulator.get("$sum") {
+                        Some(operand) => operand,
+                        None => {
+                            return Err(unsupported(field, &Bson::Document(accumulator.clone())))
+                        }
+                    }
+                }
+                _ => return Err(unsupported(field, accumulator)),
+            };
+            let value = evaluate(document, operand)?;
+            let sum = match (
+                groups[position].get(field),
+                value.as_ref().and_then(integer),
+            ) {
+                (None, Some(n)) => Bson::Int64(n),
+                (Some(Bson::Int64(sum)), Some(n)) => Bson::Int64(sum + n),
+                (Some(sum), _) => sum.clone(),
+                // Values that are not numbers are left out of sums, as MongoDB does
+                (None, None) => Bson::Int64(0),
+            };
+            groups[position].insert(field, sum);
+        }
+    }
+    // MongoDB sums integers into a 32-bit integer whenever the sum fits in one
+    let narrow = |(field, value): (String, Bson)| match value {
+        Bson::Int64(sum) if (i32::MIN as i64..=i32::MAX as i64).contains(&sum) => {
+            (field, Bson::Int32(sum as i32))
+        }
+        value => (field, value),
+    };
+    Ok(groups
+        .into_iter()
+        .map(|group| group.into_iter().map(narrow).collect())
+        .collect())
+}
+
+fn integer(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Int32(n) => Some(*n as i64),
+        Bson::Int64(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// The value of an aggregation expression over a document, or `None` for a missing field
+fn evaluate(document: &Document, expression: &Bson) -> Result<Option<Bson>, Error> {
+    let operators = match expression {
+        Bson::String(path) if path.starts_with('$') => {
+            return Ok(get(document, &path[1..]).cloned())
+        }
+        Bson::Document(operators) => operators,
+        literal => return Ok(Some(literal.clone())),
+    };
+    let (operator, operand) = match operators.iter().next() {
+        Some((operator, operand)) if operators.len() == 1 && operator.starts_with('$') => {
+            (operator.as_str(), operand)
+        }
+        // A document of expressions, like a compound `_id`
+        _ => {
+            let mut evaluated = Document::new();
+            for (field, expression) in operators {
+                if let Some(value) = evaluate(document, expression)? {
+                    evaluated.insert(field, value);
+                }
+            }
+            return Ok(Some(Bson::Document(evaluated)));
+        }
+    };
+    let arguments = |count: usize| -> Result<Vec<Option<Bson>>, Error> {
+        match operand {
+            Bson::Array(arguments) if arguments.len() == count || count == 0 => arguments
+                .iter()
+                .map(|argument| evaluate(document, argument))
+                .collect(),
+            _ => Err(unsupported(operator, operand)),
+        }
+    };
+    let value = match operator {
+        "$eq" | "$ne" | "$gt" | "$gte" | "$lt" | "$lte" => {
+            let arguments = arguments(2)?;
+            let ordering = order(arguments[0].as_ref(), arguments[1].as_ref());
+            Bson::Boolean(match operator {
+                "$eq" => ordering == Ordering::Equal,
+                "$ne" => ordering != Ordering::Equal,
+                "$gt" => ordering == Ordering::Greater,
+                "$gte" => ordering != Ordering::Less,
+                "$lt" => ordering == Ordering::Less,
+                _ => ordering != Ordering::Greater,
+            })
+        }
+        "$and" | "$or" => {
+            let values = arguments(0)?;
+            let truthy = values
+                .iter()
+                .map(|value| value.as_ref().is_some_and(truthy));
+            Bson::Boolean(if operator == "$and" {
+                truthy.clone().all(|truthy| truthy)
+            } else {
+                truthy.clone().any(|truthy| truthy)
+            })
+        }
+        "$type" => {
+            let value = match operand {
+                Bson::Array(arguments) if arguments.len() == 1 => {
+                    evaluate(document, &arguments[0])?
+                }
+                operand => evaluate(document, operand)?,
+            };
+            Bson::String(type_name(value.as_ref()).to_string())
+        }
+        "$switch" => {
+            let switch = match operand {
+                Bson::Document(switch) => switch,
+                _ => return Err(unsupported(operator, operand)),
+            };
+            let branches = match switch.get("branches") {
+                Some(Bson::Array(branches)) => branches,
+                _ => return Err(unsupported(operator, operand)),
+            };
+            for branch in branches {
+                let (case, then) = match branch {
+                    Bson::Document(branch) => match (branch.get("case"), branch.get("then")) {
+                        (Some(case), Some(then)) => (case, then),
+                        _ => return Err(unsupported(operator, operand)),
+                    },
+                    _ => return Err(unsupported(operator, operand)),
+                };
+                if evaluate(document, case)?.as_ref().is_some_and(truthy) {
+                    return evaluate(document, then);
+                }
+            }
+            match switch.get("default") {
+                Some(default) => return evaluate(document, default),
+                None => return Err(unsupported(operator, operand)),
+            }
+        }
+        "$dateToString" => {
+            let options = match operand {
+                Bson::Document(options) => options,
+                _ => return Err(unsupported(operator, operand)),
+            };
+            let format = match options.get("format") {
+                Some(Bson::String(format)) if supported_date_format(format) => format,
+                _ => return Err(unsupported(operator, operand)),
+            };
+            let date = match options.get("date") {
+                Some(date) => evaluate(document, date)?,
+                None => return Err(unsupported(operator, operand)),
+            };
+            match date {
+                Some(Bson::DateTime(date)) => Bson::String(date.format(format).to_string()),
+                None | Some(Bson::Null) => Bson::Null,
+                Some(date) => return Err(unsupported(operator, &date)),
+            }
+        }
+        _ => return Err(unsupported(operator, operand)),
+    };
+    Ok(Some(value))
+}
+
+/// Whether a `$dateToString` format only uses the specifiers MongoDB and chrono share
+fn supported_date_format(format: &str) -> bool {
+    let mut specifiers = format.split('%').skip(1);
+    specifiers.all(|rest| {
+        rest.chars()
+            .next()
+            .is_some_and(|specifier| "YmdHMS".contains(specifier))
+    })
+}
+
+/// The name `$type` gives the type of a value
+fn type_name(value: Option<&Bson>) -> &'static str {
+    match value {
+        None => "missing",
+        Some(Bson::Null) => "null",
+        Some(Bson::Double(_)) => "double",
+        Some(Bson::String(_)) => "string",
+        Some(Bson::Document(_)) => "object",
+        Some(Bson::Array(_)) => "array",
+        Some(Bson::Binary(_)) => "binData",
+        Some(Bson::ObjectId(_)) => "objectId",
+        Some(Bson::Boolean(_)) => "bool",
+        Some(Bson::DateTime(_)) => "date",
+        Some(Bson::Int32(_)) => "int",
+        Some(Bson::Int64(_)) => "long",
+        Some(_) => "unknown",
+    }
+}
+
+fn unsupported(operator: &str, operand: &Bson) -> Error {
+    Error::Unsupported(format!("{} with {}", operator, operand))
+}
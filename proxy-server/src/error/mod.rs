@@ -1,49 +1,98 @@
-use std::fmt::{Display, Formatter, Result as FmtResult};
+use actix_web::http::StatusCode;
 
-use actix_web::{http::StatusCode, HttpResponse, ResponseError};
-use serde::Serialize;
+pub use lanther_common::error::{ErrorCode, JsonError, REQUEST_ID};
+
+/// Header telling the upstream the largest request body the API key may send
+pub const MAX_PAYLOAD_SIZE: &str = "x-lanther-max-payload-size";
+
+/// Headers telling the upstream the name and kind of the principal the API key acts for
+pub const PRINCIPAL: &str = "x-lanther-principal";
+pub const PRINCIPAL_KIND: &str = "x-lanther-principal-kind";
 
 #[derive(thiserror::Error, Debug)]
 pub enum ApiError {
     #[error("MongoDB Operation failed: {source}")]
     MongoDBOperationError {
         #[from]
-        source: mongodb::error::Error,
+        source: lanther_store::Error,
     },
     #[error("Attempted to access an invalid field in BSON document: {0}")]
     InvalidFieldError(#[from] bson::document::ValueAccessError),
+    #[error("APIKey is required")]
+    ApiKeyRequired,
+    #[error("APIKey could not be found")]
+    ApiKeyNotFound,
+    #[error("APIKey is locked down")]
+    LockedDown,
+    #[error("APIKey is disabled")]
+    ApiKeyDisabled,
+    #[error("APIKey has expired")]
+    ApiKeyExpired,
+    #[error("APIKey may not be used outside of its allowed time windows")]
+    OutsideAllowedWindow { retry_after: Option<u64> },
+    #[error("APIKey may not make requests in the {0} scope")]
+    ScopeNotAllowed(&'static str),
+    #[error("Rate limit of {limit} requests per minute exceeded")]
+    RateLimited { limit: i64, retry_after: u64 },
+    #[error("Monthly quota of {limit} requests exceeded")]
+    QuotaExceeded { limit: i64, retry_after: u64 },
+    #[error("Request body is larger than the {0} bytes allowed")]
+    PayloadTooLarge(i64),
+    #[error("APIKey could not be validated: {0}")]
+    AuthServerError(String),
+    #[error("Upstream server could not be reached: {0}")]
+    UpstreamError(String),
+    #[error("Test APIKeys cannot be used, as there is no sandbox to forward their requests to")]
+    SandboxUnavailable,
 }
 
-#[derive(Debug, Serialize)]
-pub struct JsonError {
-    pub msg: String,
-    pub status: u16,
-    pub success: bool,
-}
-
-impl Display for JsonError {
-    fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        let err_json = serde_json::to_string(self).unwrap();
-        write!(f, "{}", err_json)
-    }
-}
+impl From<ApiError> for JsonError {
+    fn from(err: ApiError) -> Self {
+        let (status, code) = match err {
+            ApiError::MongoDBOperationError { source: _ } => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::DatabaseError)
+            }
+            ApiError::InvalidFieldError(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::InternalError)
+            }
+            ApiError::ApiKeyRequired => (StatusCode::UNAUTHORIZED, ErrorCode::ApikeyRequired),
+            ApiError::ApiKeyNotFound => (StatusCode::UNAUTHORIZED, ErrorCode::ApikeyNotFound),
+            ApiError::LockedDown => (StatusCode::FORBIDDEN, ErrorCode::LockedDown),
+            ApiError::ApiKeyDisabled => (StatusCode::UNAUTHORIZED, ErrorCode::ApikeyDisabled),
+            ApiError::ApiKeyExpired => (StatusCode::UNAUTHORIZED, ErrorCode::ApikeyExpired),
+            ApiError::OutsideAllowedWindow { .. } => {
+                (StatusCode::FORBIDDEN, ErrorCode::OutsideAllowedWindow)
+            }
+            ApiError::ScopeNotAllowed(_) => (StatusCode::FORBIDDEN, ErrorCode::ScopeNotAllowed),
+            ApiError::RateLimited { .. } => (StatusCode::TOO_MANY_REQUESTS, ErrorCode::RateLimited),
+            ApiError::QuotaExceeded { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, ErrorCode::QuotaExceeded)
+            }
+            ApiError::PayloadTooLarge(_) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, ErrorCode::PayloadTooLarge)
+            }
+            ApiError::AuthServerError(_) => {
+                (StatusCode::SERVICE_UNAVAILABLE, ErrorCode::AuthUnavailable)
+            }
+            ApiError::UpstreamError(_) => (StatusCode::BAD_GATEWAY, ErrorCode::UpstreamUnavailable),
+            ApiError::SandboxUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                ErrorCode::SandboxUnavailable,
+            ),
+        };
 
-impl ResponseError for JsonError {
-    fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(StatusCode::from_u16(self.status).unwrap()).json2(self)
+        let retry_after = match err {
+            ApiError::RateLimited { retry_after, .. }
+            | ApiError::QuotaExceeded { retry_after, .. } => Some(retry_after),
+            ApiError::OutsideAllowedWindow { retry_after } => retry_after,
+            _ => None,
+        };
+        JsonError::new(status, code, err.to_string()).with_retry_after(retry_after)
     }
 }
 
-impl From<ApiError> for JsonError {
+impl From<ApiError> for actix_web::Error {
     fn from(err: ApiError) -> Self {
-        let status = match err {
-            ApiError::MongoDBOperationError { source: _ } | ApiError::InvalidFieldError(_) => 500,
-        };
-
-        JsonError {
-            msg: format!("{}", err.to_string()),
-            status: status,
-            success: false,
-        }
+        JsonError::from(err).into()
     }
 }
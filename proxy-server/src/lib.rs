@@ -0,0 +1,124 @@
+//! The proxy-server, which authorizes requests with API keys and forwards them upstream
+//!
+//! The server is a library so it can also be started in-process, like the end-to-end tests do.
+use std::io;
+use std::net::TcpListener;
+use std::time::Duration;
+
+use actix_web::client::Client;
+use actix_web::dev::Server;
+use actix_web::{middleware, web, App, HttpServer};
+use metrics::Metrics;
+use services::RequestService;
+use settings::Settings;
+
+mod error;
+mod metrics;
+mod middlewares;
+mod models;
+mod routes;
+mod services;
+pub mod settings;
+
+struct ServiceContainer {
+    request: RequestService,
+}
+
+impl ServiceContainer {
+    fn new(request: RequestService) -> Self {
+        ServiceContainer { request }
+    }
+}
+
+// Application state to be shared
+struct AppState {
+    service: ServiceContainer,
+}
+
+/// Start the proxy on a listener, logging requests in a database
+///
+/// The settings must have been validated for the server, and the database migrated.
+pub fn serve(
+    settings: &Settings,
+    db: lanther_store::Database,
+    listener: TcpListener,
+) -> io::Result<Server> {
+    let requests = db.collection("requests");
+
+    let forward_url = settings.upstream.url();
+    let sandbox_url = if settings.sandbox.url.is_some() {
+        Some(settings.sandbox.url())
+    } else {
+        None
+    };
+    let auth_url = settings.auth.url();
+    let auth_timeout = Duration::from_secs(settings.auth.timeout);
+    let auth_retries = settings.auth.retries;
+
+    log::info!("Listening on: {}", listener.local_addr()?);
+    log::info!("Forwarding to: {}", forward_url);
+    match &sandbox_url {
+        Some(url) => log::info!("Forwarding test API keys to: {}", url),
+        None => log::info!("No sandbox configured, test API keys are rejected"),
+    }
+    log::info!("Authenticating on: {}", auth_url);
+
+    let metrics = Metrics::new();
+    let rate_limiter = middlewares::RateLimiter::default();
+    let server = HttpServer::new(move || {
+        let service = ServiceContainer::new(RequestService::new(requests.clone()));
+        let auth = simpleapikeys_client::Client::builder(auth_url.clone())
+            .timeout(auth_timeout)
+            .retries(auth_retries)
+            .build();
+
+        App::new()
+            .wrap(middleware::Logger::default())
+            .wrap(middlewares::Traced)
+            .wrap(middlewares::Metered::new(
+                metrics.http_requests.clone(),
+                metrics.http_request_duration.clone(),
+                "forwarded",
+            ))
+            .data(AppState { service })
+            .data(db.clone())
+            .data(routes::Dependencies {
+                auth_url: auth_url.clone(),
+                upstream_url: forward_url.clone(),
+                sandbox_url: sandbox_url.clone(),
+            })
+            .service(routes::healthz)
+            .service(routes::readyz)
+            .data(metrics.clone())
+            .service(routes::get_metrics)
+            .service(routes::get_all_requests)
+            .service(routes::get_requests_by_key)
+            .service(
+                web::scope("/apikeys/children")
+                    .data(auth.clone())
+                    .wrap(middlewares::Authorized::new(auth.clone(), metrics.clone()))
+                    .service(routes::get_children)
+                    .service(routes::create_child)
+                    .service(routes::delete_child),
+            )
+            .service(
+                web::scope("/")
+                    .data(Client::new())
+                    .data(routes::Upstreams {
+                        live: forward_url.clone(),
+                        sandbox: sandbox_url.clone(),
+                    })
+                    .wrap(middlewares::Limited::new(
+                        RequestService::new(requests.clone()),
+                        rate_limiter.clone(),
+                    ))
+                    .wrap(middlewares::Authorized::new(auth, metrics.clone()))
+                    .default_service(web::route().to(routes::forward)),
+            )
+    })
+    .listen(listener)?
+    // On SIGTERM or SIGINT, stop accepting connections and let in-flight requests finish
+    .shutdown_timeout(settings.server.shutdown_timeout)
+    .run();
+    Ok(server)
+}
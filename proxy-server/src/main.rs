@@ -1,31 +1,33 @@
-use std::net::ToSocketAddrs;
-
-use actix_web::client::Client;
-use actix_web::{middleware, web, App, HttpServer};
-use clap::{value_t, Arg};
-use mongodb::{self, options::ClientOptions};
-use services::RequestService;
-use url::Url;
-
-mod error;
-mod middlewares;
-mod models;
-mod routes;
-mod services;
-
-struct ServiceContainer {
-    request: RequestService,
-}
-
-impl ServiceContainer {
-    fn new(request: RequestService) -> Self {
-        ServiceContainer { request }
-    }
+use std::net::TcpListener;
+
+use clap::{Arg, SubCommand};
+use proxy_server::serve;
+use proxy_server::settings::Settings;
+
+/// Arguments shared by the server and the migrate subcommand
+///
+/// None of them are required, as they may also be set in a configuration file or the environment.
+fn common_args<'a, 'b>() -> [Arg<'a, 'b>; 3] {
+    [
+        Arg::with_name("config")
+            .long("config")
+            .help("A TOML or YAML configuration file, also read from LANTHER_CONFIG")
+            .takes_value(true),
+        Arg::with_name("mongo-db-address")
+            .long("mongo-db-address")
+            .help("The address of a Mongo DB instance")
+            .takes_value(true),
+        Arg::with_name("mongo-db")
+            .long("mongo-db")
+            .help("The name of a Mongo DB")
+            .takes_value(true),
+    ]
 }
 
-// Application state to be shared
-pub struct AppState {
-    service: ServiceContainer,
+/// Report an error that prevents the server from starting and exit
+fn exit_with(e: impl std::fmt::Display) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1)
 }
 
 #[actix_web::main]
@@ -37,123 +39,88 @@ async fn main() -> std::io::Result<()> {
             Arg::with_name("listen_addr")
                 .takes_value(true)
                 .value_name("LISTEN ADDR")
-                .index(1)
-                .required(true),
+                .index(1),
         )
         .arg(
             Arg::with_name("listen_port")
                 .takes_value(true)
                 .value_name("LISTEN PORT")
-                .index(2)
-                .required(true),
+                .index(2),
         )
         .arg(
             Arg::with_name("forward_addr")
                 .takes_value(true)
                 .value_name("FWD ADDR")
                 .index(3)
-                .required(true),
+                .requires("forward_port"),
         )
         .arg(
             Arg::with_name("forward_port")
                 .takes_value(true)
                 .value_name("FWD PORT")
                 .index(4)
-                .required(true),
+                .requires("forward_addr"),
         )
         .arg(
             Arg::with_name("auth_addr")
                 .takes_value(true)
                 .value_name("AUTH ADDR")
                 .index(5)
-                .required(true),
+                .requires("auth_port"),
         )
         .arg(
             Arg::with_name("auth_port")
                 .takes_value(true)
                 .value_name("AUTH PORT")
                 .index(6)
-                .required(true),
+                .requires("auth_addr"),
         )
+        .args(&common_args())
         .arg(
-            Arg::with_name("mongo-db-address")
-                .long("mongo-db-address")
-                .help("The address of a Mongo DB instance")
-                .takes_value(true)
-                .required(true),
+            Arg::with_name("skip-migrations")
+                .long("skip-migrations")
+                .help("Do not apply pending database migrations on startup"),
         )
-        .arg(
-            Arg::with_name("mongo-db")
-                .long("mongo-db")
-                .help("The name of a Mongo DB")
-                .takes_value(true)
-                .required(true),
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Apply pending database migrations and exit")
+                .args(&common_args()),
         )
         .get_matches();
+    let migrate = matches.subcommand_matches("migrate");
+
+    let settings = Settings::load(migrate.unwrap_or(&matches)).unwrap_or_else(|e| exit_with(e));
+    let validated = if migrate.is_some() {
+        settings.validate_migrate()
+    } else {
+        settings.validate_server()
+    };
+    validated.unwrap_or_else(|e| exit_with(e));
+
+    let db = lanther_store::connect(&settings.mongo)
+        .await
+        .unwrap_or_else(|e| exit_with(e));
+
+    if migrate.is_some() || settings.migrations.on_startup {
+        let applied = lanther_migrations::run(&db, &["requests"])
+            .await
+            .unwrap_or_else(|e| exit_with(e));
+        log::info!("Applied {} pending migrations", applied.len());
+    }
+    if migrate.is_some() {
+        return Ok(());
+    }
 
-    let listen_addr = matches.value_of("listen_addr").unwrap();
-    let listen_port = value_t!(matches, "listen_port", u16).unwrap_or_else(|e| e.exit());
-
-    let forwarded_addr = matches.value_of("forward_addr").unwrap();
-    let forwarded_port = value_t!(matches, "forward_port", u16).unwrap_or_else(|e| e.exit());
-
-    let auth_addr = matches.value_of("auth_addr").unwrap();
-    let auth_port = value_t!(matches, "auth_port", u16).unwrap_or_else(|e| e.exit());
-
-    let db_address = matches
-        .value_of("mongo-db-address")
-        .expect("mongo-db-address is a required argument");
-    let db_name = matches
-        .value_of("mongo-db")
-        .expect("mongo-db is a required argument");
-
-    let client_options = ClientOptions::parse(db_address).await.unwrap();
-    let client = mongodb::Client::with_options(client_options).unwrap();
-    let db = client.database(db_name);
-    let requests = db.collection("requests");
-
-    let auth_url = Url::parse(&format!(
-        "http://{}",
-        (auth_addr.to_owned().as_str(), auth_port)
-            .to_socket_addrs()
-            .unwrap()
-            .next()
-            .unwrap(),
-    ))
-    .unwrap();
-
-    let forward_url = Url::parse(&format!(
-        "http://{}",
-        (forwarded_addr, forwarded_port)
-            .to_socket_addrs()
-            .unwrap()
-            .next()
-            .unwrap(),
-    ))
-    .unwrap();
-
-    log::info!("Listening on: {}:{}", listen_addr, listen_port);
-    log::info!("Forwarding to: {}", forward_url);
-    log::info!("Authenticating on: {}:{}", auth_addr, auth_port);
-
-    HttpServer::new(move || {
-        let service = ServiceContainer::new(RequestService::new(requests.clone()));
-
-        App::new()
-            .wrap(middleware::Logger::default())
-            .data(AppState { service })
-            .service(routes::get_all_requests)
-            .service(routes::get_requests_by_key)
-            .service(
-                web::scope("/")
-                    .data(Client::new())
-                    .data(forward_url.clone())
-                    .wrap(middlewares::Authorized::new(&auth_url))
-                    .default_service(web::route().to(routes::forward)),
-            )
-    })
-    .bind((listen_addr, listen_port))?
-    .system_exit()
-    .run()
-    .await
+    let address = (settings.server.address.as_str(), settings.server.port);
+    let listener = TcpListener::bind(address).unwrap_or_else(|e| {
+        exit_with(format!(
+            "Could not listen on {}:{}: {}",
+            address.0, address.1, e
+        ))
+    });
+    serve(&settings, lanther_store::Database::Mongo(db), listener)?.await?;
+
+    log::info!("Proxy stopped");
+    log::logger().flush();
+    Ok(())
 }
@@ -0,0 +1,83 @@
+use lanther_common::middlewares::HTTP_LABELS;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+/// Prometheus metrics of the proxy-server
+///
+/// Metrics are kept in their own registry, which is cheap to clone and share across workers.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub auth_decisions: IntCounterVec,
+    pub auth_duration: Histogram,
+    pub upstream_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &HTTP_LABELS,
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time taken to handle HTTP requests",
+            ),
+            &HTTP_LABELS,
+        )
+        .unwrap();
+        let auth_decisions = IntCounterVec::new(
+            Opts::new(
+                "proxy_auth_decisions_total",
+                "Authorization decisions, by outcome",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let auth_duration = Histogram::with_opts(HistogramOpts::new(
+            "proxy_auth_duration_seconds",
+            "Time taken by the authorization server to look up API keys",
+        ))
+        .unwrap();
+        let upstream_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "proxy_upstream_duration_seconds",
+                "Time taken by the upstream server to respond to forwarded requests",
+            ),
+            &["environment"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(auth_decisions.clone())).unwrap();
+        registry.register(Box::new(auth_duration.clone())).unwrap();
+        registry
+            .register(Box::new(upstream_duration.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            auth_decisions,
+            auth_duration,
+            upstream_duration,
+        }
+    }
+
+    /// Render all metrics in the Prometheus text format
+    pub fn render(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
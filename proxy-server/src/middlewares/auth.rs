@@ -1,40 +1,41 @@
+use std::cell::RefCell;
 use std::pin::Pin;
 use std::rc::Rc;
 use std::task::{Context, Poll};
 
-use super::models::ApiKeyResponse;
+use super::models::{schedule, Verification};
+use super::RequestId;
+use crate::error::ApiError;
+use crate::metrics::Metrics;
 use actix_service::{Service, Transform};
-use actix_web::client::Client;
-use actix_web::http::{header, StatusCode};
-use actix_web::{dev::ServiceRequest, dev::ServiceResponse, error, Error};
+use actix_web::http::header;
+use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error, HttpMessage};
 use futures::future::{ok, Ready};
 use futures::Future;
-use url::Url;
+use simpleapikeys_client::Client;
 
+/// Middleware to authorize requests by their API key against the simpleapikeys-server
+///
+/// The verification of an authorized key, with the limits that apply to it, is inserted in the
+/// request extensions for the middlewares and routes that follow.
 pub struct Authorized(Rc<Inner>);
 
 struct Inner {
     client: Client,
-    auth_url: Url,
+    metrics: Metrics,
 }
 
 impl Authorized {
-    /// Initialize the Authorized service with an authorization URL and a HTTP client
-    /// to communicate with the authorization server
-    pub fn new(auth_url: &Url) -> Authorized {
-        let client = Client::new();
-        let new_url = auth_url.clone();
-
-        Authorized(Rc::new(Inner {
-            client: client,
-            auth_url: new_url,
-        }))
+    /// Initialize the Authorized service with a client for the authorization server,
+    /// recording every decision in the metrics
+    pub fn new(client: Client, metrics: Metrics) -> Authorized {
+        Authorized(Rc::new(Inner { client, metrics }))
     }
 }
 
 impl<S, B> Transform<S> for Authorized
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -47,7 +48,7 @@ where
 
     fn new_transform(&self, service: S) -> Self::Future {
         ok(AuthorizedMiddleware {
-            service,
+            service: Rc::new(RefCell::new(service)),
             inner: self.0.clone(),
         })
     }
@@ -55,12 +56,12 @@ where
 
 pub struct AuthorizedMiddleware<S> {
     inner: Rc<Inner>,
-    service: S,
+    service: Rc<RefCell<S>>,
 }
 
 impl<S, B> Service for AuthorizedMiddleware<S>
 where
-    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     S::Future: 'static,
     B: 'static,
 {
@@ -70,42 +71,72 @@ where
     type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        self.service.poll_ready(cx)
+        self.service.borrow_mut().poll_ready(cx)
     }
 
     fn call(&mut self, req: ServiceRequest) -> Self::Future {
         log::debug!("Checking request authorization");
-        let headers = req.headers().clone();
-        let fut = self.service.call(req);
-        let mut full_auth_url = self.inner.auth_url.clone();
+        let request_id = req.extensions().get::<RequestId>().cloned();
+        let service = self.service.clone();
         let client = self.inner.client.clone();
+        let metrics = self.inner.metrics.clone();
 
         Box::pin(async move {
-            let header = headers
-                .get(header::AUTHORIZATION)
-                .map(|h| h.to_str().unwrap());
-            if let Some(apikey) = header {
-                // TODO: Better error handling
-                full_auth_url.set_path(&format!("/apikeys/{}", apikey));
-
-                log::debug!("Auth url: {}", full_auth_url.as_str());
-                let auth_fut = client.get(full_auth_url.as_str()).send();
-
-                let mut res = auth_fut.await?;
-                if res.status() != StatusCode::OK {
-                    return Err(error::ErrorUnauthorized("APIKey could not be validated"));
-                }
-
-                let apikey_res: ApiKeyResponse = res.json().await?;
-                if apikey_res.payload.disabled == true {
-                    return Err(error::ErrorUnauthorized("APIKey is disabled"));
-                }
-
-                log::debug!("Request is valid!");
-                Ok(fut.await?)
-            } else {
-                Err(error::ErrorUnauthorized("APIKey is required"))
-            }
+            let decision = authorize(&client, req.headers(), request_id, &metrics).await;
+            let outcome = match &decision {
+                Ok(_) => "authorized",
+                Err(ApiError::ApiKeyRequired) => "key_required",
+                Err(ApiError::ApiKeyNotFound) => "key_not_found",
+                Err(ApiError::LockedDown) => "locked_down",
+                Err(ApiError::ApiKeyDisabled) => "key_disabled",
+                Err(ApiError::ApiKeyExpired) => "key_expired",
+                Err(ApiError::OutsideAllowedWindow { .. }) => "outside_window",
+                Err(_) => "auth_error",
+            };
+            metrics.auth_decisions.with_label_values(&[outcome]).inc();
+            let verification = decision?;
+
+            log::debug!("Request is valid!");
+            req.extensions_mut().insert(verification);
+            let fut = service.borrow_mut().call(req);
+            fut.await
         })
     }
 }
+
+/// Verify the API key in the Authorization header against the authorization server
+async fn authorize(
+    client: &Client,
+    headers: &header::HeaderMap,
+    request_id: Option<RequestId>,
+    metrics: &Metrics,
+) -> Result<Verification, ApiError> {
+    let apikey = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .ok_or(ApiError::ApiKeyRequired)?;
+    let client = match request_id {
+        Some(RequestId(request_id)) => client.with_request_id(request_id),
+        None => client.clone(),
+    };
+
+    let timer = metrics.auth_duration.start_timer();
+    let verification = client.verify(apikey).await.map_err(|e| match e.status() {
+        Some(404) => ApiError::ApiKeyNotFound,
+        _ => ApiError::AuthServerError(e.to_string()),
+    })?;
+    timer.observe_duration();
+    // A lockdown stops the key whatever state it is in, as it is meant for incidents
+    if verification.locked_down {
+        return Err(ApiError::LockedDown);
+    }
+    if verification.disabled {
+        return Err(ApiError::ApiKeyDisabled);
+    }
+    if verification.expired {
+        return Err(ApiError::ApiKeyExpired);
+    }
+    schedule::check(&verification.schedules, chrono::Utc::now())
+        .map_err(|retry_after| ApiError::OutsideAllowedWindow { retry_after })?;
+    Ok(verification)
+}
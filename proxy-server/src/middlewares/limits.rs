@@ -0,0 +1,195 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use actix_service::{Service, Transform};
+use actix_web::http::header;
+use actix_web::{dev::ServiceRequest, dev::ServiceResponse, Error, HttpMessage};
+use chrono::{Datelike, TimeZone, Utc};
+use futures::future::{ok, Ready};
+use futures::Future;
+use uuid::Uuid;
+
+use super::models::Verification;
+use crate::error::ApiError;
+use crate::services::RequestService;
+
+/// Length of the windows requests per minute are counted in
+const WINDOW: Duration = Duration::from_secs(60);
+/// Number of tracked keys above which expired windows are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Counts requests per API key in fixed one minute windows
+///
+/// Counts are kept in memory and shared by every worker, so each proxy enforces the limit on
+/// its own.
+#[derive(Clone, Default)]
+pub struct RateLimiter(Arc<Mutex<HashMap<Uuid, RateWindow>>>);
+
+struct RateWindow {
+    started: Instant,
+    count: i64,
+}
+
+impl RateLimiter {
+    /// Count a request, returning the seconds until the window resets if over the limit
+    pub fn check(&self, key: Uuid, limit: i64) -> Result<(), u64> {
+        let now = Instant::now();
+        let mut windows = self.0.lock().expect("rate limiter lock is poisoned");
+        if windows.len() > PRUNE_THRESHOLD {
+            windows.retain(|_, window| now.duration_since(window.started) < WINDOW);
+        }
+
+        let window = windows.entry(key).or_insert(RateWindow {
+            started: now,
+            count: 0,
+        });
+        if now.duration_since(window.started) >= WINDOW {
+            window.started = now;
+            window.count = 0;
+        }
+        if window.count >= limit {
+            let remaining = WINDOW - now.duration_since(window.started);
+            return Err(remaining.as_secs().max(1));
+        }
+        window.count += 1;
+        Ok(())
+    }
+}
+
+/// Middleware to enforce the limits of the API key a request was authorized with
+///
+/// Must be wrapped by `Authorized`, which resolves the limits. Requests over the payload size are
+/// rejected by their Content-Length, while the upstream enforces it on bodies of unknown length.
+/// Requests made with a child key are counted along with those of its parent, so delegating keys
+/// never multiplies the requests a key may make.
+pub struct Limited(Rc<Inner>);
+
+struct Inner {
+    requests: RequestService,
+    rate_limiter: RateLimiter,
+}
+
+impl Limited {
+    pub fn new(requests: RequestService, rate_limiter: RateLimiter) -> Limited {
+        Limited(Rc::new(Inner {
+            requests,
+            rate_limiter,
+        }))
+    }
+}
+
+impl<S, B> Transform<S> for Limited
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = LimitedMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(LimitedMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            inner: self.0.clone(),
+        })
+    }
+}
+
+pub struct LimitedMiddleware<S> {
+    inner: Rc<Inner>,
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for LimitedMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let verification = req.extensions().get::<Verification>().cloned();
+        let service = self.service.clone();
+        let inner = self.inner.clone();
+
+        Box::pin(async move {
+            if let Some(verification) = verification {
+                enforce(&inner, &req, &verification).await?;
+            }
+            let fut = service.borrow_mut().call(req);
+            fut.await
+        })
+    }
+}
+
+async fn enforce(
+    inner: &Inner,
+    req: &ServiceRequest,
+    verification: &Verification,
+) -> Result<(), ApiError> {
+    let limits = verification.limits;
+
+    if let Some(max) = limits.max_payload_size {
+        let length = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.parse::<i64>().ok());
+        if length.is_some_and(|length| length > max) {
+            return Err(ApiError::PayloadTooLarge(max));
+        }
+    }
+
+    if let Some(limit) = limits.requests_per_minute {
+        inner
+            .rate_limiter
+            .check(verification.root(), limit)
+            .map_err(|retry_after| ApiError::RateLimited { limit, retry_after })?;
+    }
+
+    if let Some(limit) = limits.monthly_quota {
+        let now = Utc::now();
+        let month_start = Utc
+            .with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0)
+            .unwrap();
+        let next_month_start = if now.month() == 12 {
+            Utc.with_ymd_and_hms(now.year() + 1, 1, 1, 0, 0, 0)
+        } else {
+            Utc.with_ymd_and_hms(now.year(), now.month() + 1, 1, 0, 0, 0)
+        }
+        .unwrap();
+
+        // Failing to count is not the client's fault, so the request is let through
+        match inner
+            .requests
+            .count_since(&verification.root(), month_start)
+            .await
+        {
+            Ok(count) if count >= limit => {
+                let retry_after = (next_month_start - now).num_seconds().max(1) as u64;
+                return Err(ApiError::QuotaExceeded { limit, retry_after });
+            }
+            Ok(_) => {}
+            Err(e) => log::warn!("Could not count requests for the monthly quota: {}", e),
+        }
+    }
+
+    Ok(())
+}
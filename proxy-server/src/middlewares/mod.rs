@@ -1,5 +1,8 @@
 pub mod auth;
+pub mod limits;
 
 pub use auth::Authorized;
+pub use lanther_common::middlewares::{Metered, RequestId, Traced};
+pub use limits::{Limited, RateLimiter};
 
 use super::models;
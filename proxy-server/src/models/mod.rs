@@ -5,6 +5,11 @@ use chrono::prelude::*;
 use serde::{Deserialize, Serialize};
 use uuid::{Error, Uuid};
 
+pub mod schedule;
+pub mod scope;
+
+pub use lanther_common::models::{Environment, Verification};
+
 /// A simple model for an HTTP Request
 /// Could be extended to support more information, like aditional headers
 #[derive(Serialize, Deserialize, Debug)]
@@ -14,6 +19,7 @@ pub struct Request {
     method: String,
     path: String,
     authorization: Option<Uuid>,
+    environment: Environment,
     created_at: DateTime<Utc>,
 }
 
@@ -30,7 +36,12 @@ impl Request {
             id: doc.get_object_id("_id")?.to_hex(),
             method: doc.get_str("method")?.to_string(),
             path: doc.get_str("path")?.to_string(),
-            authorization: authorization,
+            authorization,
+            environment: doc
+                .get_str("environment")
+                .ok()
+                .and_then(Environment::parse)
+                .unwrap_or_default(),
             created_at: *doc.get_datetime("created_at")?,
         })
     }
@@ -41,6 +52,9 @@ pub struct NewRequest {
     pub method: String,
     pub path: String,
     pub authorization: Option<Uuid>,
+    /// The key the API key descends from, itself unless it is a child key
+    pub root: Option<Uuid>,
+    pub environment: Environment,
 }
 
 impl NewRequest {
@@ -53,27 +67,17 @@ impl NewRequest {
             Some(key) => Some(Uuid::parse_str(key)?),
             None => None,
         };
+        let verification = req.extensions().get::<Verification>().cloned();
+        let environment = verification
+            .as_ref()
+            .map(|verification| verification.environment)
+            .unwrap_or_default();
         Ok(NewRequest {
             method: req.method().as_str().to_string(),
             path: req.path().to_string(),
-            authorization: authorization,
+            authorization,
+            root: verification.as_ref().map(Verification::root),
+            environment,
         })
     }
 }
-
-#[derive(Deserialize, Debug)]
-pub struct ApiKey {
-    #[serde(rename = "_id")]
-    id: String,
-    pub key: Uuid,
-    pub disabled: bool,
-    created_at: DateTime<Utc>,
-    updated_at: DateTime<Utc>,
-}
-
-#[derive(Deserialize, Debug)]
-pub struct ApiKeyResponse {
-    pub status: u16,
-    pub success: bool,
-    pub payload: ApiKey,
-}
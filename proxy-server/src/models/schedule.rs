@@ -0,0 +1,104 @@
+use chrono::prelude::*;
+use lanther_common::models::Schedule;
+
+/// When all of the given schedules next allow requests, as seconds from `now`
+///
+/// Returns `Ok(())` when they all allow requests right away. Otherwise the error holds a hint of
+/// how long to wait, when there is one: the longest wait of any of the schedules, as they may not
+/// all be open at that time.
+pub fn check(schedules: &[Schedule], now: DateTime<Utc>) -> Result<(), Option<u64>> {
+    let mut opens_at = now;
+    for schedule in schedules {
+        match schedule.opens_at(now) {
+            Some(at) => opens_at = opens_at.max(at),
+            None => return Err(None),
+        }
+    }
+    if opens_at > now {
+        let wait = (opens_at - now).num_milliseconds();
+        // Round up, so retrying after the hint never lands just before the window opens
+        Err(Some(((wait + 999) / 1000) as u64))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lanther_common::models::Window;
+
+    use super::*;
+
+    fn schedule(timezone: &str, windows: &[(&[&str], &str, &str)]) -> Schedule {
+        Schedule {
+            timezone: timezone.to_string(),
+            windows: windows
+                .iter()
+                .map(|(days, start, end)| Window {
+                    days: days.iter().map(|day| day.to_string()).collect(),
+                    start: start.to_string(),
+                    end: end.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, second)
+            .unwrap()
+    }
+
+    #[test]
+    fn overnight_windows_are_open_on_both_days() {
+        // Friday night until Saturday morning, given as a window on each day
+        let nights = [schedule(
+            "UTC",
+            &[(&["fri"], "22:00", "24:00"), (&["sat"], "00:00", "06:00")],
+        )];
+
+        assert_eq!(check(&nights, at(2026, 3, 20, 23, 30, 0)), Ok(()));
+        assert_eq!(check(&nights, at(2026, 3, 21, 0, 0, 0)), Ok(()));
+        assert_eq!(check(&nights, at(2026, 3, 21, 5, 59, 59)), Ok(()));
+        // Closed from Saturday at 06:00 until the next Friday at 22:00
+        let wait = (6 * 24 + 16) * 3600;
+        assert_eq!(check(&nights, at(2026, 3, 21, 6, 0, 0)), Err(Some(wait)));
+        assert_eq!(check(&nights, at(2026, 3, 20, 21, 0, 0)), Err(Some(3600)));
+    }
+
+    #[test]
+    fn waits_count_the_hour_lost_when_clocks_go_forward() {
+        // Clocks in Madrid went from 02:00 to 03:00 on Sunday the 29th of March of 2026
+        let weekdays = [schedule("Europe/Madrid", &[(&["mon"], "09:00", "17:00")])];
+        // From Sunday at 01:00 in Madrid, before the change, to Monday at 09:00 is 31 hours
+        let sunday = at(2026, 3, 29, 0, 0, 0);
+        assert_eq!(check(&weekdays, sunday), Err(Some(31 * 3600)));
+
+        // A window starting in the hour that was skipped opens when the clocks go forward
+        let skipped = [schedule("Europe/Madrid", &[(&["sun"], "02:30", "04:00")])];
+        assert_eq!(check(&skipped, sunday), Err(Some(90 * 60)));
+        assert_eq!(check(&skipped, at(2026, 3, 29, 1, 30, 0)), Ok(()));
+    }
+
+    #[test]
+    fn retry_after_is_rounded_up_to_the_longest_wait() {
+        let evenings = schedule("UTC", &[(&["fri"], "18:00", "22:00")]);
+        let nights = schedule("UTC", &[(&["fri"], "20:00", "24:00")]);
+        let both = [evenings, nights];
+
+        // Half a second before the window opens is a second, rather than none
+        let now = at(2026, 3, 20, 17, 59, 59) + chrono::Duration::milliseconds(500);
+        assert_eq!(check(&both[..1], now), Err(Some(1)));
+        // Requests must be allowed by every schedule, so the latest to open is waited for
+        let now = at(2026, 3, 20, 17, 0, 0);
+        assert_eq!(check(&both, now), Err(Some(3 * 3600)));
+        assert_eq!(check(&both, at(2026, 3, 20, 21, 0, 0)), Ok(()));
+    }
+
+    #[test]
+    fn schedules_that_never_open_give_no_hint() {
+        let unknown = schedule("Mars/Olympus_Mons", &[(&["mon"], "09:00", "17:00")]);
+        assert_eq!(check(&[unknown], at(2026, 3, 20, 12, 0, 0)), Err(None));
+        let empty = schedule("UTC", &[]);
+        assert_eq!(check(&[empty], at(2026, 3, 20, 12, 0, 0)), Err(None));
+    }
+}
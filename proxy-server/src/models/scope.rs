@@ -0,0 +1,25 @@
+use actix_web::http::Method;
+
+/// Scope of the requests that only read from the upstream, like fetching a file
+pub const READ: &str = "read";
+/// Scope of every other request, like uploading a file
+pub const UPLOAD: &str = "upload";
+
+/// The scope a request is made in, by its method
+pub fn required(method: &Method) -> &'static str {
+    match *method {
+        Method::GET | Method::HEAD | Method::OPTIONS => READ,
+        _ => UPLOAD,
+    }
+}
+
+/// Whether an API key restricted to the given scopes, if any, may make a request
+///
+/// Returns the scope the request needed when the key may not make it.
+pub fn check(scopes: Option<&[String]>, method: &Method) -> Result<(), &'static str> {
+    let scope = required(method);
+    match scopes {
+        Some(scopes) if !scopes.iter().any(|s| s == scope) => Err(scope),
+        _ => Ok(()),
+    }
+}
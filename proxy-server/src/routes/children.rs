@@ -0,0 +1,103 @@
+//! Lets API key holders manage the child keys delegated from their own key
+//!
+//! Requests are authorized with the parent key as any other, and relayed to the
+//! simpleapikeys-server, which checks child keys stay within their parent.
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, web, Error, HttpRequest, HttpResponse};
+use lanther_common::Envelope;
+use serde::Serialize;
+use serde_json::{json, Value};
+use simpleapikeys_client::Client;
+
+use crate::error::{ApiError, ErrorCode, JsonError};
+use crate::middlewares::RequestId;
+use crate::models::Verification;
+
+/// Header carried over to the simpleapikeys-server, so creating a child key can be retried
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// The authorized key, whose children are managed, along with a client to manage them with
+///
+/// The client carries over the request's id and Idempotency-Key, if any.
+fn parent(req: &HttpRequest, client: &Client) -> Result<(String, Client), ApiError> {
+    let parent = req
+        .extensions()
+        .get::<Verification>()
+        .map(|verification| verification.key.to_hyphenated().to_string())
+        .ok_or(ApiError::ApiKeyRequired)?;
+
+    let mut client = client.clone();
+    if let Some(RequestId(request_id)) = req.extensions().get::<RequestId>() {
+        client = client.with_request_id(request_id.clone());
+    }
+    if let Some(key) = req
+        .headers()
+        .get(IDEMPOTENCY_KEY)
+        .and_then(|key| key.to_str().ok())
+    {
+        client = client.with_idempotency_key(key);
+    }
+    Ok((parent, client))
+}
+
+/// Answer with the outcome of a request to the simpleapikeys-server, passing its errors on as is
+fn respond<T: Serialize>(
+    status: StatusCode,
+    result: Result<T, simpleapikeys_client::Error>,
+) -> Result<HttpResponse, Error> {
+    match result {
+        Ok(payload) => Ok(HttpResponse::build(status).json(Envelope::new(status, payload))),
+        Err(simpleapikeys_client::Error::Api { status, error }) => {
+            let status = StatusCode::from_u16(status).unwrap_or(StatusCode::BAD_GATEWAY);
+            Ok(HttpResponse::build(status).json(json!({
+                "status": status.as_u16(),
+                "success": false,
+                "error": error,
+            })))
+        }
+        Err(e) => Err(ApiError::AuthServerError(e.to_string()).into()),
+    }
+}
+
+#[post("")]
+pub async fn create_child(
+    req: HttpRequest,
+    body: web::Bytes,
+    client: web::Data<Client>,
+) -> Result<HttpResponse, Error> {
+    let (parent, client) = parent(&req, &client)?;
+    let child: Value = if body.is_empty() {
+        json!({})
+    } else {
+        serde_json::from_slice(&body).map_err(|e| {
+            JsonError::new(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::BadRequest,
+                e.to_string(),
+            )
+        })?
+    };
+    let result = client.create_child(&parent, &child).await;
+    respond(StatusCode::CREATED, result)
+}
+
+#[get("")]
+pub async fn get_children(
+    req: HttpRequest,
+    client: web::Data<Client>,
+) -> Result<HttpResponse, Error> {
+    let (parent, client) = parent(&req, &client)?;
+    let result = client.get_children(&parent).await;
+    respond(StatusCode::OK, result)
+}
+
+#[delete("/{child}")]
+pub async fn delete_child(
+    req: HttpRequest,
+    child: web::Path<String>,
+    client: web::Data<Client>,
+) -> Result<HttpResponse, Error> {
+    let (parent, client) = parent(&req, &client)?;
+    let result = client.delete_child(&parent, &child).await;
+    respond(StatusCode::OK, result)
+}
@@ -0,0 +1,81 @@
+//! Liveness and readiness probes
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use actix_web::client::Client;
+use actix_web::http::StatusCode;
+use actix_web::{get, web, HttpResponse};
+use lanther_common::{health, Envelope};
+use lanther_store::Database;
+use serde_json::json;
+use url::Url;
+
+/// How long a dependency may take to answer before it is considered down
+///
+/// This is longer than the timeout of the authorization server's own checks, so a degraded
+/// authorization server is reported as such rather than as timing out.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The HTTP services the proxy depends on
+pub struct Dependencies {
+    pub auth_url: Url,
+    pub upstream_url: Url,
+    pub sandbox_url: Option<Url>,
+}
+
+/// Check a service answers at a URL with a status accepted by `healthy`
+async fn get(url: Url, healthy: fn(StatusCode) -> bool) -> Result<(), String> {
+    let res = Client::default()
+        .get(url.as_str())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if healthy(res.status()) {
+        Ok(())
+    } else {
+        Err(format!("responded with {}", res.status()))
+    }
+}
+
+/// The server is alive as long as it can answer, regardless of its dependencies
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(Envelope::ok(json!({ "status": "ok" })))
+}
+
+/// The server is ready to proxy requests when Mongo DB answers a ping, the authorization server
+/// is ready itself, and the upstream, as well as the sandbox if any, answer without a server error
+#[get("/readyz")]
+pub async fn readyz(db: web::Data<Database>, deps: web::Data<Dependencies>) -> HttpResponse {
+    let mut auth_url = deps.auth_url.clone();
+    auth_url.set_path("/readyz");
+    let sandbox = async {
+        match deps.sandbox_url.clone() {
+            Some(url) => Some(
+                health::check(CHECK_TIMEOUT, get(url, |status| !status.is_server_error())).await,
+            ),
+            None => None,
+        }
+    };
+    let (mongo, auth, upstream, sandbox) = futures::join!(
+        health::check(CHECK_TIMEOUT, db.ping()),
+        health::check(CHECK_TIMEOUT, get(auth_url, |status| status.is_success())),
+        health::check(
+            CHECK_TIMEOUT,
+            get(deps.upstream_url.clone(), |status| {
+                !status.is_server_error()
+            })
+        ),
+        sandbox,
+    );
+
+    let mut checks = BTreeMap::new();
+    checks.insert("mongo".to_string(), mongo);
+    checks.insert("auth".to_string(), auth);
+    checks.insert("upstream".to_string(), upstream);
+    if let Some(sandbox) = sandbox {
+        checks.insert("sandbox".to_string(), sandbox);
+    }
+
+    health::respond(checks)
+}
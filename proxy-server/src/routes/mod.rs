@@ -1,19 +1,56 @@
 use actix_web::client::Client;
+use actix_web::http::StatusCode;
 use actix_web::{get, web, Error, HttpRequest, HttpResponse};
-use serde_json::json;
+use lanther_common::Envelope;
+use serde::Deserialize;
 use url::Url;
 
-use super::error::JsonError;
+use super::error::{
+    ApiError, ErrorCode, JsonError, MAX_PAYLOAD_SIZE, PRINCIPAL, PRINCIPAL_KIND, REQUEST_ID,
+};
+use super::metrics::Metrics;
+use super::middlewares::RequestId;
 use super::models;
 
+mod children;
+mod health;
+pub use children::{create_child, delete_child, get_children};
+pub use health::{healthz, readyz, Dependencies};
+
+/// The services requests are forwarded to, by the environment of their API key
+pub struct Upstreams {
+    pub live: Url,
+    /// Where requests made with test API keys go, which are rejected when there is none
+    pub sandbox: Option<Url>,
+}
+
+impl Upstreams {
+    fn url(&self, environment: models::Environment) -> Result<&Url, ApiError> {
+        match environment {
+            models::Environment::Live => Ok(&self.live),
+            models::Environment::Test => self.sandbox.as_ref().ok_or(ApiError::SandboxUnavailable),
+        }
+    }
+}
+
 pub async fn forward(
     req: HttpRequest,
     body: web::Bytes,
-    forward_url: web::Data<Url>,
+    upstreams: web::Data<Upstreams>,
     app_data: web::Data<crate::AppState>,
     client: web::Data<Client>,
+    metrics: web::Data<Metrics>,
 ) -> Result<HttpResponse, Error> {
+    // Requests outside the scopes of their key are rejected before they are logged or forwarded
+    let verification = req.extensions().get::<models::Verification>().cloned();
+    if let Some(verification) = &verification {
+        models::scope::check(verification.scopes.as_deref(), req.method())
+            .map_err(ApiError::ScopeNotAllowed)?;
+    }
     let request = models::NewRequest::from_http_request(&req).unwrap();
+    let environment = request.environment;
+    let forward_url = upstreams.url(environment)?;
+
     let result = app_data.service.request.create(request).await;
 
     // TODO: Figure out a better way to log requests, perhaps with a middelware?
@@ -23,7 +60,7 @@ pub async fn forward(
     };
 
     log::info!("Processing request");
-    let mut new_url = forward_url.as_ref().clone();
+    let mut new_url = forward_url.clone();
     new_url.set_path(req.uri().path());
     new_url.set_query(req.uri().query());
 
@@ -38,8 +75,38 @@ pub async fn forward(
     } else {
         forwarded_req
     };
+    let mut forwarded_req = match req.extensions().get::<RequestId>() {
+        Some(RequestId(request_id)) => forwarded_req.header(REQUEST_ID, request_id.as_str()),
+        None => forwarded_req,
+    };
+    // Clients must not be able to raise their own limit or act for someone else, so these
+    // headers are only ever set by us
+    for header in [MAX_PAYLOAD_SIZE, PRINCIPAL, PRINCIPAL_KIND] {
+        forwarded_req.headers_mut().remove(header);
+    }
+    let max_payload_size = verification
+        .as_ref()
+        .and_then(|verification| verification.limits.max_payload_size);
+    let forwarded_req = match max_payload_size {
+        Some(max) => forwarded_req.header(MAX_PAYLOAD_SIZE, max.to_string()),
+        None => forwarded_req,
+    };
+    let principal = verification.and_then(|verification| verification.principal);
+    let forwarded_req = match principal {
+        Some(principal) => forwarded_req
+            .header(PRINCIPAL, principal.name)
+            .header(PRINCIPAL_KIND, principal.kind.as_str()),
+        None => forwarded_req,
+    };
 
-    let mut res = forwarded_req.send_body(body).await.map_err(Error::from)?;
+    let timer = metrics
+        .upstream_duration
+        .with_label_values(&[environment.as_str()])
+        .start_timer();
+    let mut res = forwarded_req
+        .send_body(body)
+        .await
+        .map_err(|e| ApiError::UpstreamError(e.to_string()))?;
 
     let mut client_resp = HttpResponse::build(res.status());
     // Remove `Connection` as per
@@ -48,36 +115,101 @@ pub async fn forward(
         client_resp.header(header_name.clone(), header_value.clone());
     }
 
-    Ok(client_resp.body(res.body().await?))
+    let body = res
+        .body()
+        .await
+        .map_err(|e| ApiError::UpstreamError(e.to_string()))?;
+    timer.observe_duration();
+    Ok(client_resp.body(body))
+}
+
+/// Expose metrics in the Prometheus text format
+#[get("/metrics")]
+pub async fn get_metrics(metrics: web::Data<Metrics>) -> Result<HttpResponse, JsonError> {
+    let body = metrics.render().map_err(|e| {
+        JsonError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::InternalError,
+            e.to_string(),
+        )
+    })?;
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
 }
 
+#[derive(Deserialize)]
+pub struct RequestsQuery {
+    environment: Option<String>,
+}
+
+/// List the requests made with live API keys, or with test ones given `?environment=test`
 #[get("/requests")]
 pub async fn get_all_requests(
+    query: web::Query<RequestsQuery>,
     app_data: web::Data<crate::AppState>,
 ) -> Result<HttpResponse, JsonError> {
-    let result = app_data.service.request.get_all().await;
+    let environment = match query.environment.as_deref() {
+        None => models::Environment::default(),
+        Some(environment) => models::Environment::parse(environment).ok_or_else(|| {
+            JsonError::new(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::BadRequest,
+                "environment must be either live or test",
+            )
+        })?,
+    };
+    let result = app_data.service.request.get_all(environment).await;
     match result {
-        Ok(requests) => Ok(HttpResponse::Ok().json(json!({
-            "status": 200,
-            "sucess": true,
-            "payload": requests,
-        }))),
+        Ok(requests) => Ok(HttpResponse::Ok().json(Envelope::ok(requests))),
         Err(e) => Err(e.into()),
     }
 }
 
+/// Most requests made with an API key listed at once
+const MAX_REQUESTS_LIMIT: i64 = 1000;
+
+#[derive(Deserialize)]
+pub struct RequestsByKeyQuery {
+    limit: Option<i64>,
+    order: Option<String>,
+}
+
+/// List the requests made with an API key, oldest first unless given `?order=newest`, and only
+/// the first ones given `?limit=`
 #[get("/requests/{key}")]
 pub async fn get_requests_by_key(
     key: web::Path<String>,
+    query: web::Query<RequestsByKeyQuery>,
     app_data: web::Data<crate::AppState>,
 ) -> Result<HttpResponse, JsonError> {
-    let result = app_data.service.request.get_by_key(&key).await;
+    let newest_first = match query.order.as_deref() {
+        None | Some("oldest") => false,
+        Some("newest") => true,
+        Some(_) => {
+            return Err(JsonError::new(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::BadRequest,
+                "order must be either oldest or newest",
+            ))
+        }
+    };
+    if let Some(limit) = query.limit {
+        if !(1..=MAX_REQUESTS_LIMIT).contains(&limit) {
+            return Err(JsonError::new(
+                StatusCode::BAD_REQUEST,
+                ErrorCode::BadRequest,
+                format!("limit must be a number from 1 to {}", MAX_REQUESTS_LIMIT),
+            ));
+        }
+    }
+    let result = app_data
+        .service
+        .request
+        .get_by_key(&key, newest_first, query.limit)
+        .await;
     match result {
-        Ok(requests) => Ok(HttpResponse::Ok().json(json!({
-            "status": 200,
-            "success": true,
-            "payload": requests,
-        }))),
+        Ok(requests) => Ok(HttpResponse::Ok().json(Envelope::ok(requests))),
         Err(e) => Err(e.into()),
     }
 }
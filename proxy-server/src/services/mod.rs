@@ -1,7 +1,8 @@
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use futures::StreamExt;
-use mongodb::results::InsertOneResult;
-use mongodb::{bson::doc, bson::Bson, Collection};
+use lanther_store::{Collection, InsertOneResult};
+use mongodb::{bson::doc, bson::Bson};
+use uuid::Uuid;
 
 use super::error::ApiError;
 use super::models;
@@ -19,12 +20,13 @@ impl RequestService {
 
     /// Create a new entry for a Request
     pub async fn create(&self, req: models::NewRequest) -> Result<InsertOneResult, ApiError> {
-        let document = match req.authorization {
+        let mut document = match req.authorization {
             Some(a) => {
                 doc! {
                     "method": req.method.clone(),
                     "path": req.path.clone(),
                     "authorization": a.to_hyphenated().to_string(),
+                    "environment": req.environment.as_str(),
                     "created_at": Utc::now(),
                 }
             }
@@ -33,17 +35,29 @@ impl RequestService {
                     "method": req.method.clone(),
                     "path": req.path.clone(),
                     "authorization": Bson::Null,
+                    "environment": req.environment.as_str(),
                     "created_at": Utc::now(),
                 }
             }
         };
-        let result = self.collection.insert_one(document, None).await?;
+        if let Some(root) = req.root {
+            document.insert("root", root.to_hyphenated().to_string());
+        }
+        let result = self.collection.insert_one(document).await?;
         Ok(result)
     }
 
-    /// Get all existing Requests
-    pub async fn get_all(&self) -> Result<Vec<models::Request>, ApiError> {
-        let mut cursor = self.collection.find(None, None).await?;
+    /// Get all existing Requests made in an environment
+    pub async fn get_all(
+        &self,
+        environment: models::Environment,
+    ) -> Result<Vec<models::Request>, ApiError> {
+        let filter = match environment {
+            // Requests logged before environments were introduced have none, and are all live
+            models::Environment::Live => doc! { "environment": { "$ne": "test" } },
+            models::Environment::Test => doc! { "environment": "test" },
+        };
+        let mut cursor = self.collection.find(filter).await?;
         let mut result: Vec<models::Request> = Vec::new();
         while let Some(doc) = cursor.next().await {
             log::debug!("doc: {:?}", doc);
@@ -52,12 +66,18 @@ impl RequestService {
         Ok(result)
     }
 
-    /// Find all existing Requests by API key
-    pub async fn get_by_key(&self, key: &str) -> Result<Vec<models::Request>, ApiError> {
+    /// Find the Requests made with an API key, oldest or newest first, up to `limit` of them
+    pub async fn get_by_key(
+        &self,
+        key: &str,
+        newest_first: bool,
+        limit: Option<i64>,
+    ) -> Result<Vec<models::Request>, ApiError> {
         let filter = doc! {
             "authorization": key.to_string(),
         };
-        let mut cursor = self.collection.find(filter, None).await?;
+        let sort = doc! { "created_at": if newest_first { -1 } else { 1 } };
+        let mut cursor = self.collection.find_sorted(filter, sort, limit).await?;
         let mut result: Vec<models::Request> = Vec::new();
         while let Some(doc) = cursor.next().await {
             log::debug!("doc: {:?}", doc);
@@ -65,4 +85,19 @@ impl RequestService {
         }
         Ok(result)
     }
+
+    /// Count the Requests made with an API key, or any of its child keys, since a given time
+    ///
+    /// Requests logged before their root key was recorded are only counted for their own key.
+    pub async fn count_since(&self, root: &Uuid, since: DateTime<Utc>) -> Result<i64, ApiError> {
+        let root = root.to_hyphenated().to_string();
+        let filter = doc! {
+            "$or": [
+                { "root": root.clone() },
+                { "authorization": root, "root": { "$exists": false } },
+            ],
+            "created_at": { "$gte": since },
+        };
+        Ok(self.collection.count_documents(filter).await?)
+    }
 }
@@ -0,0 +1,275 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use config::{Config, Environment, File};
+use serde::Deserialize;
+use url::Url;
+
+pub use lanther_store::MongoSettings;
+
+/// Prefix of the environment variables that override the configuration file
+pub const ENV_PREFIX: &str = "LANTHER";
+
+#[derive(thiserror::Error, Debug)]
+pub enum SettingsError {
+    #[error("Could not load configuration: {0}")]
+    Load(#[from] config::ConfigError),
+    #[error("Could not read {key} from {}: {source}", path.display())]
+    SecretFile {
+        key: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[error("Invalid configuration:\n  - {}", .0.join("\n  - "))]
+    Invalid(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ServerSettings {
+    pub address: String,
+    pub port: u16,
+    /// Seconds to let in-flight requests finish after a shutdown signal
+    pub shutdown_timeout: u64,
+}
+
+/// An HTTP service the proxy sends requests to
+#[derive(Debug, Default, Deserialize)]
+pub struct ServiceSettings {
+    pub url: Option<String>,
+}
+
+impl ServiceSettings {
+    pub fn url(&self) -> Url {
+        self.url
+            .as_deref()
+            .and_then(|url| Url::parse(url).ok())
+            .expect("URLs are validated")
+    }
+}
+
+/// The simpleapikeys-server API keys are checked against
+#[derive(Debug, Default, Deserialize)]
+pub struct AuthSettings {
+    pub url: Option<String>,
+    /// Seconds to wait for each attempt at checking an API key
+    pub timeout: u64,
+    /// How many times to retry checking an API key when the server cannot be reached
+    pub retries: u32,
+}
+
+impl AuthSettings {
+    pub fn url(&self) -> Url {
+        self.url
+            .as_deref()
+            .and_then(|url| Url::parse(url).ok())
+            .expect("URLs are validated")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MigrationSettings {
+    pub on_startup: bool,
+}
+
+/// Configuration of the proxy-server
+///
+/// Settings are layered, each layer overriding the ones before it: defaults, a TOML or YAML
+/// configuration file, environment variables prefixed with `LANTHER_`, and command line arguments.
+/// Nested settings are separated by a double underscore in environment variables, so
+/// `mongo.password_file` is set with `LANTHER_MONGO__PASSWORD_FILE`.
+#[derive(Debug, Deserialize)]
+pub struct Settings {
+    pub server: ServerSettings,
+    /// The service requests are forwarded to once authorized
+    #[serde(default)]
+    pub upstream: ServiceSettings,
+    /// The service requests made with test API keys are forwarded to, if any
+    #[serde(default)]
+    pub sandbox: ServiceSettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    #[serde(default)]
+    pub mongo: MongoSettings,
+    pub migrations: MigrationSettings,
+}
+
+impl Settings {
+    pub fn load(matches: &ArgMatches) -> Result<Self, SettingsError> {
+        let mut builder = Config::builder()
+            .set_default("server.address", "127.0.0.1")?
+            .set_default("server.port", 8080)?
+            .set_default("server.shutdown_timeout", 30)?
+            .set_default("auth.timeout", 5)?
+            .set_default("auth.retries", 2)?
+            .set_default("mongo.connect_attempts", 5)?
+            .set_default("mongo.connect_backoff", 1)?
+            .set_default("migrations.on_startup", true)?;
+
+        let path = matches
+            .value_of("config")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os(format!("{}_CONFIG", ENV_PREFIX)).map(PathBuf::from));
+        if let Some(path) = path {
+            builder = builder.add_source(File::from(path.as_path()).required(true));
+        }
+
+        builder = builder.add_source(
+            Environment::with_prefix(ENV_PREFIX)
+                .prefix_separator("_")
+                .separator("__"),
+        );
+
+        let overrides = [
+            ("listen_addr", "server.address"),
+            ("listen_port", "server.port"),
+            ("mongo-db-address", "mongo.address"),
+            ("mongo-db", "mongo.database"),
+        ];
+        for (arg, key) in overrides.iter() {
+            if let Some(value) = matches.value_of(arg) {
+                builder = builder.set_override(*key, value)?;
+            }
+        }
+        let services = [
+            ("forward_addr", "forward_port", "upstream.url"),
+            ("auth_addr", "auth_port", "auth.url"),
+        ];
+        for (addr, port, key) in services.iter() {
+            if let (Some(addr), Some(port)) = (matches.value_of(addr), matches.value_of(port)) {
+                builder = builder.set_override(*key, format!("http://{}:{}", addr, port))?;
+            }
+        }
+        if matches.is_present("skip-migrations") {
+            builder = builder.set_override("migrations.on_startup", false)?;
+        }
+
+        let mut settings: Settings = builder.build()?.try_deserialize()?;
+        settings.mongo.password = secret(
+            "mongo.password",
+            settings.mongo.password.take(),
+            settings.mongo.password_file.take(),
+        )?;
+        Ok(settings)
+    }
+
+    /// Check the settings the server needs to proxy requests are valid
+    pub fn validate_server(&self) -> Result<(), SettingsError> {
+        let mut errors = self.mongo_errors();
+        let urls = [
+            (
+                "upstream.url",
+                "the FWD ADDR and FWD PORT arguments",
+                &self.upstream.url,
+            ),
+            (
+                "auth.url",
+                "the AUTH ADDR and AUTH PORT arguments",
+                &self.auth.url,
+            ),
+        ];
+        for (key, args, url) in urls.iter() {
+            match url.as_deref() {
+                None => errors.push(missing(key, args)),
+                Some(url) => errors.extend(url_error(key, url)),
+            }
+        }
+        if let Some(url) = self.sandbox.url.as_deref() {
+            errors.extend(url_error("sandbox.url", url));
+        }
+        if self.auth.timeout == 0 {
+            errors.push("auth.timeout must be at least 1 second".to_string());
+        }
+        invalid_if_any(errors)
+    }
+
+    /// Check the settings needed to apply migrations are valid
+    pub fn validate_migrate(&self) -> Result<(), SettingsError> {
+        invalid_if_any(self.mongo_errors())
+    }
+
+    fn mongo_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        match &self.mongo.address {
+            None => errors.push(missing("mongo.address", "--mongo-db-address")),
+            Some(address)
+                if !address.starts_with("mongodb://") && !address.starts_with("mongodb+srv://") =>
+            {
+                errors.push(format!(
+                    "mongo.address `{}` must be a mongodb:// or mongodb+srv:// connection string",
+                    address
+                ))
+            }
+            Some(_) => {}
+        }
+        if self
+            .mongo
+            .database
+            .as_deref()
+            .unwrap_or_default()
+            .is_empty()
+        {
+            errors.push(missing("mongo.database", "--mongo-db"));
+        }
+        if self.mongo.connect_attempts == 0 {
+            errors.push("mongo.connect_attempts must be at least 1".to_string());
+        }
+        if self.mongo.password.is_some() && self.mongo.username.is_none() {
+            errors.push("mongo.password is set, but mongo.username is not".to_string());
+        }
+        errors
+    }
+}
+
+/// Resolve a secret given either directly or as the path to a file holding it
+fn secret(
+    key: &'static str,
+    value: Option<String>,
+    file: Option<PathBuf>,
+) -> Result<Option<String>, SettingsError> {
+    match (value, file) {
+        (Some(_), Some(_)) => Err(SettingsError::Invalid(vec![format!(
+            "only one of {} and {}_file may be set",
+            key, key
+        )])),
+        (None, Some(path)) => read_secret(key, &path).map(Some),
+        (value, None) => Ok(value),
+    }
+}
+
+fn read_secret(key: &'static str, path: &Path) -> Result<String, SettingsError> {
+    let secret = fs::read_to_string(path).map_err(|source| SettingsError::SecretFile {
+        key,
+        path: path.to_path_buf(),
+        source,
+    })?;
+    // Files written by editors or `echo` usually end in a newline that is not part of the secret
+    Ok(secret.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn url_error(key: &str, url: &str) -> Option<String> {
+    match Url::parse(url) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => None,
+        _ => Some(format!("{} must be an http:// or https:// URL", key)),
+    }
+}
+
+fn missing(key: &str, arg: &str) -> String {
+    format!(
+        "{} is required: set it in the configuration file, with {}_{}, or with {}",
+        key,
+        ENV_PREFIX,
+        key.to_uppercase().replace('.', "__"),
+        arg
+    )
+}
+
+fn invalid_if_any(errors: Vec<String>) -> Result<(), SettingsError> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(SettingsError::Invalid(errors))
+    }
+}
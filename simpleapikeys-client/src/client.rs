@@ -0,0 +1,537 @@
+use std::time::Duration;
+
+use actix_web::client::{Client as HttpClient, SendRequestError};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::web::Bytes;
+use lanther_common::Envelope;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use uuid::Uuid;
+
+use crate::error::{Error, ErrorBody};
+use crate::models::{
+    ApiKey, ApiKeyRequest, ApiKeySearch, ApiKeyStats, Deleted, LeakReportRequest, LeakReportResult,
+    Lockdown, LockdownRequest, OwnerUsage, Plan, PlanRequest, Principal, PrincipalRequest,
+    Readiness, Signup, SignupRequest, UpdateApiKey, Verification,
+};
+
+/// Header identifying a request across every lanther server
+const REQUEST_ID: &str = "x-request-id";
+
+/// Header that makes the server replay the response of a write when it is retried
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// Listing every API key may take a while, so responses are allowed to be large
+const MAX_RESPONSE_SIZE: usize = 32 * 1024 * 1024;
+
+/// Builds a `Client`, starting from a timeout of 5 seconds and 2 retries 100ms apart
+pub struct ClientBuilder {
+    base_url: Url,
+    timeout: Duration,
+    retries: u32,
+    backoff: Duration,
+    bearer_token: Option<String>,
+}
+
+impl ClientBuilder {
+    /// How long to wait for each attempt at a request, including reading the response
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many times to retry a request that could not be sent or that the server could not
+    /// handle for now, like a `503 Service Unavailable`
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// How long to wait before the first retry, doubling on each one after it
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// A token to send as a bearer token in the Authorization header of every request
+    pub fn bearer_token(mut self, token: impl Into<String>) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+
+    pub fn build(self) -> Client {
+        let mut headers = Vec::new();
+        if let Some(token) = self.bearer_token {
+            headers.push((header::AUTHORIZATION.as_str(), format!("Bearer {}", token)));
+        }
+        Client {
+            http: HttpClient::builder().timeout(self.timeout).finish(),
+            base_url: self.base_url,
+            retries: self.retries,
+            backoff: self.backoff,
+            headers,
+        }
+    }
+}
+
+/// A client for every route of the simpleapikeys-server
+///
+/// Writes of clients with a bearer token carry an Idempotency-Key, generated unless one is given
+/// with `with_idempotency_key`, so retrying them never applies them twice. The server refuses
+/// Idempotency-Keys on anonymous requests, so those are sent without one.
+#[derive(Clone)]
+pub struct Client {
+    http: HttpClient,
+    base_url: Url,
+    retries: u32,
+    backoff: Duration,
+    headers: Vec<(&'static str, String)>,
+}
+
+#[derive(Deserialize)]
+struct ErrorEnvelope {
+    error: ErrorBody,
+}
+
+/// A JSON body and the content type to send it as
+struct Body {
+    content_type: &'static str,
+    json: Bytes,
+}
+
+impl Body {
+    fn json(body: &impl Serialize) -> Result<Self, Error> {
+        Body::new("application/json", body)
+    }
+
+    fn new(content_type: &'static str, body: &impl Serialize) -> Result<Self, Error> {
+        let json = serde_json::to_vec(body).map_err(|e| Error::Decode(e.to_string()))?;
+        Ok(Body {
+            content_type,
+            json: Bytes::from(json),
+        })
+    }
+}
+
+impl Client {
+    /// Start building a client for the simpleapikeys-server at `base_url`
+    ///
+    /// Routes are appended to the path of `base_url`, so the server may be behind a prefix.
+    pub fn builder(base_url: Url) -> ClientBuilder {
+        ClientBuilder {
+            base_url,
+            timeout: Duration::from_secs(5),
+            retries: 2,
+            backoff: Duration::from_millis(100),
+            bearer_token: None,
+        }
+    }
+
+    /// A client sending `request_id` as the X-Request-Id of its requests, to be traced by it
+    pub fn with_request_id(&self, request_id: impl Into<String>) -> Client {
+        self.with_header(REQUEST_ID, request_id.into())
+    }
+
+    /// A client sending `key` as the Idempotency-Key of its writes, for callers that retry them
+    /// on their own
+    pub fn with_idempotency_key(&self, key: impl Into<String>) -> Client {
+        self.with_header(IDEMPOTENCY_KEY, key.into())
+    }
+
+    fn with_header(&self, name: &'static str, value: String) -> Client {
+        let mut client = self.clone();
+        client.headers.retain(|(header, _)| *header != name);
+        client.headers.push((name, value));
+        client
+    }
+
+    pub async fn healthz(&self) -> Result<(), Error> {
+        let (status, body) = self
+            .send(Method::GET, self.url(&["healthz"]), None, None)
+            .await?;
+        payload::<serde_json::Value>(status, &body).map(|_| ())
+    }
+
+    /// Check whether the server is ready, which it reports even while degraded
+    pub async fn readyz(&self) -> Result<Readiness, Error> {
+        let (status, body) = self
+            .send(Method::GET, self.url(&["readyz"]), None, None)
+            .await?;
+        match status {
+            StatusCode::SERVICE_UNAVAILABLE => serde_json::from_slice::<Envelope<Readiness>>(&body)
+                .map(|envelope| envelope.payload)
+                .map_err(|e| Error::Decode(e.to_string())),
+            status => payload(status, &body),
+        }
+    }
+
+    /// The server's metrics, in the Prometheus text format
+    pub async fn metrics(&self) -> Result<String, Error> {
+        let (status, body) = self
+            .send(Method::GET, self.url(&["metrics"]), None, None)
+            .await?;
+        if !status.is_success() {
+            return Err(error(status, &body));
+        }
+        String::from_utf8(body.to_vec()).map_err(|e| Error::Decode(e.to_string()))
+    }
+
+    pub async fn list_apikeys(&self) -> Result<Vec<ApiKey>, Error> {
+        self.call(Method::GET, &["apikeys"], None, None).await
+    }
+
+    pub async fn stats(&self) -> Result<ApiKeyStats, Error> {
+        self.call(Method::GET, &["apikeys", "stats"], None, None)
+            .await
+    }
+
+    /// Count the active API keys of every owner, against how many each may have
+    pub async fn usage(&self) -> Result<Vec<OwnerUsage>, Error> {
+        self.call(Method::GET, &["apikeys", "usage"], None, None)
+            .await
+    }
+
+    /// Count the active API keys of an owner, against how many it may have
+    pub async fn owner_usage(&self, owner: &str) -> Result<OwnerUsage, Error> {
+        self.call(Method::GET, &["apikeys", "usage", owner], None, None)
+            .await
+    }
+
+    /// Search API keys by prefixes of their key, and by words in their owner, name and labels,
+    /// getting a page of the best matches
+    pub async fn search_apikeys(
+        &self,
+        query: &str,
+        page: u32,
+        per_page: u32,
+    ) -> Result<ApiKeySearch, Error> {
+        let mut url = self.url(&["apikeys", "search"]);
+        url.query_pairs_mut()
+            .append_pair("q", query)
+            .append_pair("page", &page.to_string())
+            .append_pair("per_page", &per_page.to_string());
+        let (status, body) = self.send(Method::GET, url, None, None).await?;
+        payload(status, &body)
+    }
+
+    pub async fn get_apikey(&self, key: &str) -> Result<ApiKey, Error> {
+        self.call(Method::GET, &["apikeys", key], None, None).await
+    }
+
+    /// Resolve the plan, principal, scopes and limits of an API key, to authorize a request made
+    /// with it
+    pub async fn verify(&self, key: &str) -> Result<Verification, Error> {
+        self.call(Method::GET, &["apikeys", key, "verify"], None, None)
+            .await
+    }
+
+    pub async fn create_apikey(&self, apikey: &ApiKeyRequest) -> Result<ApiKey, Error> {
+        let body = Body::json(apikey)?;
+        self.call(Method::POST, &["apikeys"], Some(body), None)
+            .await
+    }
+
+    /// Enable or disable an API key, only if it is still at `version` when one is given
+    pub async fn update_apikey(
+        &self,
+        apikey: &UpdateApiKey,
+        version: Option<i64>,
+    ) -> Result<ApiKey, Error> {
+        let body = Body::json(apikey)?;
+        self.call(Method::PUT, &["apikeys"], Some(body), version)
+            .await
+    }
+
+    /// Apply a JSON merge patch to an API key, only if it is still at `version` when one is given
+    pub async fn patch_apikey(
+        &self,
+        key: &str,
+        patch: &impl Serialize,
+        version: Option<i64>,
+    ) -> Result<ApiKey, Error> {
+        let body = Body::new("application/merge-patch+json", patch)?;
+        self.call(Method::PATCH, &["apikeys", key], Some(body), version)
+            .await
+    }
+
+    /// Delete an API key, along with its child keys
+    pub async fn delete_apikey(&self, key: &str) -> Result<Deleted, Error> {
+        self.call(Method::DELETE, &["apikeys", key], None, None)
+            .await
+    }
+
+    /// Revoke every reported candidate that is an API key, which takes the reporter token as the
+    /// client's bearer token
+    pub async fn report_leaks(
+        &self,
+        report: &LeakReportRequest,
+    ) -> Result<LeakReportResult, Error> {
+        let body = Body::json(report)?;
+        self.call(Method::POST, &["apikeys", "leaks"], Some(body), None)
+            .await
+    }
+
+    /// Delegate a child key from `parent`, given a `ChildKeyRequest` or any JSON body like it
+    pub async fn create_child(
+        &self,
+        parent: &str,
+        child: &impl Serialize,
+    ) -> Result<ApiKey, Error> {
+        let body = Body::json(child)?;
+        self.call(
+            Method::POST,
+            &["apikeys", parent, "children"],
+            Some(body),
+            None,
+        )
+        .await
+    }
+
+    pub async fn get_children(&self, parent: &str) -> Result<Vec<ApiKey>, Error> {
+        self.call(Method::GET, &["apikeys", parent, "children"], None, None)
+            .await
+    }
+
+    pub async fn delete_child(&self, parent: &str, child: &str) -> Result<Deleted, Error> {
+        let path = ["apikeys", parent, "children", child];
+        self.call(Method::DELETE, &path, None, None).await
+    }
+
+    pub async fn list_plans(&self) -> Result<Vec<Plan>, Error> {
+        self.call(Method::GET, &["plans"], None, None).await
+    }
+
+    pub async fn get_plan(&self, name: &str) -> Result<Plan, Error> {
+        self.call(Method::GET, &["plans", name], None, None).await
+    }
+
+    pub async fn create_plan(&self, plan: &PlanRequest) -> Result<Plan, Error> {
+        let body = Body::json(plan)?;
+        self.call(Method::POST, &["plans"], Some(body), None).await
+    }
+
+    /// Replace the limits and scopes of the plan named in `plan`
+    pub async fn replace_plan(&self, plan: &PlanRequest) -> Result<Plan, Error> {
+        let body = Body::json(plan)?;
+        self.call(Method::PUT, &["plans", &plan.name], Some(body), None)
+            .await
+    }
+
+    /// Delete a plan, which the server refuses while API keys are on it
+    pub async fn delete_plan(&self, name: &str) -> Result<Deleted, Error> {
+        self.call(Method::DELETE, &["plans", name], None, None)
+            .await
+    }
+
+    pub async fn list_principals(&self) -> Result<Vec<Principal>, Error> {
+        self.call(Method::GET, &["principals"], None, None).await
+    }
+
+    pub async fn get_principal(&self, name: &str) -> Result<Principal, Error> {
+        self.call(Method::GET, &["principals", name], None, None)
+            .await
+    }
+
+    pub async fn create_principal(&self, principal: &PrincipalRequest) -> Result<Principal, Error> {
+        let body = Body::json(principal)?;
+        self.call(Method::POST, &["principals"], Some(body), None)
+            .await
+    }
+
+    /// Apply a JSON merge patch to a principal, like `{"disabled":true}` to disable its API keys
+    pub async fn patch_principal(
+        &self,
+        name: &str,
+        patch: &impl Serialize,
+    ) -> Result<Principal, Error> {
+        let body = Body::new("application/merge-patch+json", patch)?;
+        self.call(Method::PATCH, &["principals", name], Some(body), None)
+            .await
+    }
+
+    /// Delete a principal, which the server refuses while API keys act for it
+    pub async fn delete_principal(&self, name: &str) -> Result<Deleted, Error> {
+        self.call(Method::DELETE, &["principals", name], None, None)
+            .await
+    }
+
+    /// List every lockdown in place, which only the admin may do
+    pub async fn list_lockdowns(&self) -> Result<Vec<Lockdown>, Error> {
+        self.call(Method::GET, &["lockdowns"], None, None).await
+    }
+
+    /// Lock down every API key, except those of the owners the lockdown allows
+    pub async fn lock_down(&self, lockdown: &LockdownRequest) -> Result<Lockdown, Error> {
+        let body = Body::json(lockdown)?;
+        self.call(Method::PUT, &["lockdowns", "global"], Some(body), None)
+            .await
+    }
+
+    /// Lift the global lockdown
+    pub async fn lift_lockdown(&self) -> Result<Deleted, Error> {
+        self.call(Method::DELETE, &["lockdowns", "global"], None, None)
+            .await
+    }
+
+    /// Lock down every API key of an owner
+    pub async fn lock_down_owner(
+        &self,
+        owner: &str,
+        lockdown: &LockdownRequest,
+    ) -> Result<Lockdown, Error> {
+        let body = Body::json(lockdown)?;
+        let path = ["lockdowns", "owners", owner];
+        self.call(Method::PUT, &path, Some(body), None).await
+    }
+
+    /// Lift the lockdown of an owner
+    pub async fn lift_owner_lockdown(&self, owner: &str) -> Result<Deleted, Error> {
+        let path = ["lockdowns", "owners", owner];
+        self.call(Method::DELETE, &path, None, None).await
+    }
+
+    /// Sign up for an API key, which is emailed a link to verify the address with
+    pub async fn signup(&self, signup: &SignupRequest) -> Result<Signup, Error> {
+        let body = Body::json(signup)?;
+        self.call(Method::POST, &["signups"], Some(body), None)
+            .await
+    }
+
+    /// Verify a signup with the token from its email, creating its API key
+    pub async fn verify_signup(&self, token: &str) -> Result<ApiKey, Error> {
+        self.call(Method::POST, &["signups", token, "verify"], None, None)
+            .await
+    }
+
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &[&str],
+        body: Option<Body>,
+        version: Option<i64>,
+    ) -> Result<T, Error> {
+        let (status, body) = self.send(method, self.url(path), body, version).await?;
+        payload(status, &body)
+    }
+
+    /// Build the URL of a path under the base URL
+    fn url(&self, path: &[&str]) -> Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .expect("base URLs are http:// or https:// URLs")
+            .pop_if_empty()
+            .extend(path);
+        url
+    }
+
+    /// Send a request, retrying it while it fails in a way that may not last
+    async fn send(
+        &self,
+        method: Method,
+        url: Url,
+        body: Option<Body>,
+        version: Option<i64>,
+    ) -> Result<(StatusCode, Bytes), Error> {
+        let mut headers = self.headers.clone();
+        let is_write = method != Method::GET;
+        let is_authenticated = headers
+            .iter()
+            .any(|(name, _)| *name == header::AUTHORIZATION.as_str());
+        if is_write && is_authenticated && !headers.iter().any(|(name, _)| *name == IDEMPOTENCY_KEY)
+        {
+            headers.push((IDEMPOTENCY_KEY, Uuid::new_v4().to_string()));
+        }
+        if let Some(version) = version {
+            headers.push((header::IF_MATCH.as_str(), format!("\"{}\"", version)));
+        }
+
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            let result = self.attempt(&method, &url, &headers, body.as_ref()).await;
+            let retry = match &result {
+                Ok((status, body)) => retryable(*status, body),
+                Err(Error::Connect(_)) | Err(Error::Timeout) => true,
+                Err(_) => false,
+            };
+            if !retry || attempt >= self.retries {
+                return result;
+            }
+
+            attempt += 1;
+            log::debug!(
+                "Retrying {} {} in {:?}, attempt {} of {}",
+                method,
+                url,
+                backoff,
+                attempt,
+                self.retries
+            );
+            actix_web::rt::time::delay_for(backoff).await;
+            backoff *= 2;
+        }
+    }
+
+    async fn attempt(
+        &self,
+        method: &Method,
+        url: &Url,
+        headers: &[(&'static str, String)],
+        body: Option<&Body>,
+    ) -> Result<(StatusCode, Bytes), Error> {
+        let mut req = self.http.request(method.clone(), url.as_str());
+        for (name, value) in headers {
+            req = req.header(*name, value.as_str());
+        }
+        let sent = match body {
+            Some(body) => {
+                req.header(header::CONTENT_TYPE, body.content_type)
+                    .send_body(body.json.clone())
+                    .await
+            }
+            None => req.send().await,
+        };
+        let mut res = sent.map_err(|e| match e {
+            SendRequestError::Timeout => Error::Timeout,
+            e => Error::Connect(e.to_string()),
+        })?;
+        let body = res
+            .body()
+            .limit(MAX_RESPONSE_SIZE)
+            .await
+            .map_err(|e| Error::Connect(e.to_string()))?;
+        Ok((res.status(), body))
+    }
+}
+
+/// Whether the server could not handle a request for now, and may once retried
+fn retryable(status: StatusCode, body: &[u8]) -> bool {
+    match status {
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT => {
+            true
+        }
+        // A previous attempt that timed out on our side may still be running on the server's
+        StatusCode::CONFLICT => error(status, body).code() == Some("idempotency_key_in_progress"),
+        _ => false,
+    }
+}
+
+fn payload<T: DeserializeOwned>(status: StatusCode, body: &[u8]) -> Result<T, Error> {
+    if !status.is_success() {
+        return Err(error(status, body));
+    }
+    serde_json::from_slice::<Envelope<T>>(body)
+        .map(|envelope| envelope.payload)
+        .map_err(|e| Error::Decode(e.to_string()))
+}
+
+fn error(status: StatusCode, body: &[u8]) -> Error {
+    match serde_json::from_slice::<ErrorEnvelope>(body) {
+        Ok(envelope) => Error::Api {
+            status: status.as_u16(),
+            error: Box::new(envelope.error),
+        },
+        Err(_) => Error::Decode(format!("{} response without an error body", status)),
+    }
+}
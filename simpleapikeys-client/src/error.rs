@@ -0,0 +1,46 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    /// The server answered with an error, as described in its body
+    #[error("{} {}: {}", .status, .error.code, .error.message)]
+    Api { status: u16, error: Box<ErrorBody> },
+    #[error("Request to the simpleapikeys-server timed out")]
+    Timeout,
+    #[error("Could not reach the simpleapikeys-server: {0}")]
+    Connect(String),
+    #[error("Unexpected response from the simpleapikeys-server: {0}")]
+    Decode(String),
+}
+
+impl Error {
+    /// The status the server answered with, if it answered at all
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::Api { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// The stable error code the server answered with, like `apikey_not_found`
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::Api { error, .. } => Some(&error.code),
+            _ => None,
+        }
+    }
+}
+
+/// Describes an error in the body of an error response
+///
+/// Codes are kept as strings, so clients keep working when the server introduces new ones.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorBody {
+    pub code: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
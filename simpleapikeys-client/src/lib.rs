@@ -0,0 +1,25 @@
+//! A typed client for the simpleapikeys-server
+//!
+//! Every route of the server has a method, which unwraps the `payload` of successful responses
+//! and turns error responses into `Error::Api`:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), simpleapikeys_client::Error> {
+//! use simpleapikeys_client::{models::ApiKeyRequest, Client};
+//!
+//! let url = url::Url::parse("http://127.0.0.1:8083").unwrap();
+//! let client = Client::builder(url).build();
+//! let apikey = client.create_apikey(&ApiKeyRequest::default()).await?;
+//! let verification = client.verify(&apikey.key.to_string()).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The client is built on actix-web's, so it must be used within an actix runtime.
+mod client;
+mod error;
+
+pub use lanther_common::models;
+
+pub use client::{Client, ClientBuilder};
+pub use error::{Error, ErrorBody};
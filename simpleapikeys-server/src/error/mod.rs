@@ -1,52 +1,147 @@
-use std::fmt::{Display, Formatter, Result as FmtResult};
+use actix_web::http::StatusCode;
+use serde_json::json;
 
-use actix_web::{http::StatusCode, HttpResponse, ResponseError};
-use serde::Serialize;
+pub use lanther_common::error::{ErrorCode, FieldErrors, JsonError};
 
 #[derive(thiserror::Error, Debug)]
 pub enum ApiError {
     #[error("MongoDB Operation failed: {source}")]
     MongoDBOperationError {
         #[from]
-        source: mongodb::error::Error,
+        source: lanther_store::Error,
     },
     #[error("Attempted to access an invalid field in BSON document: {0}")]
     InvalidFieldError(#[from] bson::document::ValueAccessError),
     #[error("MongoDB query returned no results")]
     MongoDBEmptyResult,
-}
-
-#[derive(Debug, Serialize)]
-pub struct JsonError {
-    pub msg: String,
-    pub status: u16,
-    pub success: bool,
-}
-
-impl Display for JsonError {
-    fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        let err_json = serde_json::to_string(self).unwrap();
-        write!(f, "{}", err_json)
-    }
-}
-
-impl ResponseError for JsonError {
-    fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(StatusCode::from_u16(self.status).unwrap()).json2(self)
-    }
+    #[error("API key version does not match any of the expected versions")]
+    VersionMismatch,
+    #[error("Request body is not valid JSON: {0}")]
+    InvalidJsonError(#[from] serde_json::Error),
+    #[error("Request contains invalid fields")]
+    ValidationError(FieldErrors),
+    #[error("Idempotency-Key must be a non-empty string of at most 255 characters")]
+    InvalidIdempotencyKey,
+    #[error("Idempotency-Key may only be sent along with an Authorization header")]
+    AnonymousIdempotencyKey,
+    #[error("A request with this Idempotency-Key is still being processed")]
+    IdempotencyKeyInProgress,
+    #[error("Idempotency-Key was already used with a different request")]
+    IdempotencyKeyMismatch,
+    #[error("Request body is too large")]
+    PayloadTooLarge,
+    #[error("Plan could not be found: {0}")]
+    PlanNotFound(String),
+    #[error("A plan named {0} already exists")]
+    PlanAlreadyExists(String),
+    #[error("Plan {name} is still used by {keys} API keys")]
+    PlanInUse { name: String, keys: i64 },
+    #[error("Principal could not be found: {0}")]
+    PrincipalNotFound(String),
+    #[error("A principal named {0} already exists")]
+    PrincipalAlreadyExists(String),
+    #[error("Principal {name} is still used by {keys} API keys")]
+    PrincipalInUse { name: String, keys: i64 },
+    #[error("{holder} already has the {limit} active API keys it may have")]
+    KeyQuotaExceeded { holder: String, limit: i64 },
+    #[error("No such lockdown is in place")]
+    LockdownNotFound,
+    #[error("API key was revoked and cannot be enabled again")]
+    ApiKeyRevoked,
+    #[error("Child key cannot be created: {0}")]
+    ChildKeyNotAllowed(String),
+    #[error("A valid leak reporter token is required")]
+    ReporterUnauthorized,
+    #[error("API key encryption failed: {0}")]
+    Encryption(#[from] crate::services::EncryptionError),
+    #[error("This request requires the admin token")]
+    AdminUnauthorized,
+    #[error("Signup token does not exist, has expired or was already used")]
+    SignupTokenInvalid,
+    #[error("Too many signups from the same {by}")]
+    SignupRateLimited { by: &'static str, retry_after: u64 },
+    #[error("Verification email could not be sent: {0}")]
+    EmailUnavailable(String),
 }
 
 impl From<ApiError> for JsonError {
     fn from(err: ApiError) -> Self {
-        let status = match err {
-            ApiError::MongoDBOperationError { source: _ } | ApiError::InvalidFieldError(_) => 500,
-            ApiError::MongoDBEmptyResult => 404,
+        let (status, code) = match err {
+            ApiError::MongoDBOperationError { source: _ } => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::DatabaseError)
+            }
+            ApiError::InvalidFieldError(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::InternalError)
+            }
+            ApiError::MongoDBEmptyResult => (StatusCode::NOT_FOUND, ErrorCode::ApikeyNotFound),
+            ApiError::VersionMismatch => {
+                (StatusCode::PRECONDITION_FAILED, ErrorCode::VersionMismatch)
+            }
+            ApiError::InvalidJsonError(_) => (StatusCode::BAD_REQUEST, ErrorCode::InvalidJson),
+            ApiError::ValidationError(_) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                ErrorCode::ValidationFailed,
+            ),
+            ApiError::InvalidIdempotencyKey | ApiError::AnonymousIdempotencyKey => {
+                (StatusCode::BAD_REQUEST, ErrorCode::IdempotencyKeyInvalid)
+            }
+            ApiError::IdempotencyKeyInProgress => {
+                (StatusCode::CONFLICT, ErrorCode::IdempotencyKeyInProgress)
+            }
+            ApiError::IdempotencyKeyMismatch => {
+                (StatusCode::CONFLICT, ErrorCode::IdempotencyKeyMismatch)
+            }
+            ApiError::PayloadTooLarge => {
+                (StatusCode::PAYLOAD_TOO_LARGE, ErrorCode::PayloadTooLarge)
+            }
+            ApiError::PlanNotFound(_) => (StatusCode::NOT_FOUND, ErrorCode::PlanNotFound),
+            ApiError::PlanAlreadyExists(_) => (StatusCode::CONFLICT, ErrorCode::PlanExists),
+            ApiError::PlanInUse { .. } => (StatusCode::CONFLICT, ErrorCode::PlanInUse),
+            ApiError::PrincipalNotFound(_) => (StatusCode::NOT_FOUND, ErrorCode::PrincipalNotFound),
+            ApiError::PrincipalAlreadyExists(_) => {
+                (StatusCode::CONFLICT, ErrorCode::PrincipalExists)
+            }
+            ApiError::PrincipalInUse { .. } => (StatusCode::CONFLICT, ErrorCode::PrincipalInUse),
+            ApiError::KeyQuotaExceeded { .. } => {
+                (StatusCode::CONFLICT, ErrorCode::KeyQuotaExceeded)
+            }
+            ApiError::LockdownNotFound => (StatusCode::NOT_FOUND, ErrorCode::LockdownNotFound),
+            ApiError::ApiKeyRevoked => (StatusCode::CONFLICT, ErrorCode::ApikeyRevoked),
+            ApiError::ChildKeyNotAllowed(_) => {
+                (StatusCode::FORBIDDEN, ErrorCode::ChildKeyNotAllowed)
+            }
+            ApiError::ReporterUnauthorized => {
+                (StatusCode::UNAUTHORIZED, ErrorCode::ReporterUnauthorized)
+            }
+            ApiError::Encryption(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, ErrorCode::InternalError)
+            }
+            ApiError::AdminUnauthorized => (StatusCode::UNAUTHORIZED, ErrorCode::AdminUnauthorized),
+            ApiError::SignupTokenInvalid => (StatusCode::NOT_FOUND, ErrorCode::SignupTokenInvalid),
+            ApiError::SignupRateLimited { .. } => {
+                (StatusCode::TOO_MANY_REQUESTS, ErrorCode::RateLimited)
+            }
+            ApiError::EmailUnavailable(_) => {
+                (StatusCode::SERVICE_UNAVAILABLE, ErrorCode::EmailUnavailable)
+            }
         };
+        let error = JsonError::new(status, code, err.to_string());
 
-        JsonError {
-            msg: format!("{}", err.to_string()),
-            status: status,
-            success: false,
+        match err {
+            ApiError::ValidationError(fields) => error.with_details(json!({ "fields": fields })),
+            ApiError::KeyQuotaExceeded { limit, .. } => {
+                error.with_details(json!({ "limit": limit }))
+            }
+            ApiError::SignupRateLimited { retry_after, .. } => {
+                error.with_retry_after(Some(retry_after))
+            }
+            _ => error,
         }
     }
 }
+
+impl From<ApiError> for actix_web::Error {
+    fn from(err: ApiError) -> Self {
+        JsonError::from(err).into()
+    }
+}
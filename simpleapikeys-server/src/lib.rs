@@ -0,0 +1,251 @@
+//! The simpleapikeys-server, which manages API keys and verifies them for the proxy-server
+//!
+//! The server is a library so it can also be started in-process, like the end-to-end tests do.
+use std::io;
+use std::net::TcpListener;
+
+use actix_web::dev::Server;
+use actix_web::{self, web, HttpServer};
+use lanther_common::handlers;
+use metrics::Metrics;
+use services::{
+    ApiKeyService, AuditService, IdempotencyService, LockdownService, Notifier, PlanService,
+    PrincipalService, SignupService,
+};
+use settings::Settings;
+
+mod error;
+mod metrics;
+mod middlewares;
+mod models;
+mod routes;
+pub mod services;
+pub mod settings;
+
+struct ServiceContainer {
+    apikey: ApiKeyService,
+    plan: PlanService,
+    principal: PrincipalService,
+    audit: AuditService,
+    lockdown: LockdownService,
+    notifier: Notifier,
+}
+
+impl ServiceContainer {
+    fn new(
+        apikey: ApiKeyService,
+        plan: PlanService,
+        principal: PrincipalService,
+        audit: AuditService,
+        lockdown: LockdownService,
+        notifier: Notifier,
+    ) -> Self {
+        ServiceContainer {
+            apikey,
+            plan,
+            principal,
+            audit,
+            lockdown,
+            notifier,
+        }
+    }
+}
+
+// Application state to be shared
+struct AppState {
+    service: ServiceContainer,
+}
+
+/// Start the server on a listener, serving API keys stored in a database
+///
+/// The settings must have been validated for the server, and the database migrated.
+pub fn serve(
+    settings: &Settings,
+    db: lanther_store::Database,
+    listener: TcpListener,
+) -> io::Result<Server> {
+    let keyring = settings
+        .encryption
+        .keyring()
+        .expect("encryption is validated");
+    let apikeys = ApiKeyService::new(
+        db.collection("apikeys"),
+        keyring.clone(),
+        settings.quotas.clone(),
+    );
+    let plans = db.collection("plans");
+    let principals = db.collection("principals");
+    let audit_log = db.collection("audit_log");
+    let lockdowns = db.collection("lockdowns");
+    let notifier = Notifier::new(settings.notifications.webhook_url.clone());
+    if settings.leaks.reporter_token.is_none() {
+        log::warn!("No leak reporter token configured, leak reports are rejected");
+    }
+    let reporter_token =
+        web::Data::new(routes::ReporterToken(settings.leaks.reporter_token.clone()));
+    if settings.admin.token.is_none() {
+        log::warn!(
+            "No admin token configured, lockdowns cannot be put in place or lifted, plans and \
+             principals cannot be written, nor the admin dashboard used"
+        );
+        if !settings.signup.anonymous_creation {
+            log::warn!(
+                "Anonymous key creation is disabled too, so no API key can be created or managed"
+            );
+        }
+    }
+    let admin_token = web::Data::new(routes::AdminToken(settings.admin.token.clone()));
+    let dashboard = web::Data::new(routes::Dashboard {
+        proxy_url: settings.admin.proxy_url.clone(),
+    });
+    let key_creation = web::Data::new(routes::KeyCreation {
+        anonymous: settings.signup.anonymous_creation,
+    });
+    let signups = settings
+        .smtp
+        .mailer()
+        .expect("smtp is validated")
+        .map(|mailer| {
+            web::Data::new(routes::Signups {
+                service: SignupService::new(db.collection("signups")),
+                mailer,
+                base_url: settings
+                    .signup
+                    .base_url
+                    .clone()
+                    .expect("signup is validated")
+                    .trim_end_matches('/')
+                    .to_string(),
+                token_ttl: chrono::Duration::hours(settings.signup.token_ttl),
+                window: chrono::Duration::seconds(settings.signup.window),
+                per_ip: settings.signup.per_ip,
+                per_domain: settings.signup.per_domain,
+            })
+        });
+    if signups.is_none() {
+        log::info!("No SMTP server configured, signups are disabled");
+    }
+    let idempotency_keys = db.collection("idempotency_keys");
+    let idempotency = IdempotencyService::new(
+        idempotency_keys,
+        keyring,
+        chrono::Duration::seconds(settings.idempotency.window),
+        chrono::Duration::seconds(settings.idempotency.lease),
+    );
+
+    log::info!(
+        "Starting SimpleAPI Keys Server on: {}",
+        listener.local_addr()?
+    );
+
+    let metrics = Metrics::new();
+    let server = HttpServer::new(move || {
+        let state = web::Data::new(AppState {
+            service: ServiceContainer::new(
+                apikeys.clone(),
+                PlanService::new(plans.clone()),
+                PrincipalService::new(principals.clone()),
+                AuditService::new(audit_log.clone()),
+                LockdownService::new(lockdowns.clone()),
+                notifier.clone(),
+            ),
+        });
+        let app = actix_web::App::new()
+            .wrap(middlewares::Traced)
+            .wrap(middlewares::Metered::new(
+                metrics.http_requests.clone(),
+                metrics.http_request_duration.clone(),
+                "unmatched",
+            ))
+            .app_data(web::JsonConfig::default().error_handler(handlers::json_error_handler))
+            .default_service(web::route().to(handlers::not_found))
+            .data(db.clone())
+            .app_data(admin_token.clone())
+            .service(routes::healthz)
+            .service(routes::readyz)
+            .data(metrics.clone())
+            .data(apikeys.clone())
+            .service(routes::get_metrics)
+            .service(
+                web::scope("/apikeys")
+                    .app_data(state.clone())
+                    .app_data(reporter_token.clone())
+                    .app_data(key_creation.clone())
+                    .wrap(middlewares::Idempotent::new(idempotency.clone()))
+                    .service(routes::report_leaks)
+                    .service(routes::get_apikeys)
+                    // Registered before /{key}, which would take "stats", "usage" or "search" for a
+                    // key
+                    .service(routes::get_stats)
+                    .service(routes::search_apikeys)
+                    .service(routes::get_usage)
+                    .service(routes::get_owner_usage)
+                    .service(routes::get_apikey)
+                    .service(routes::verify_apikey)
+                    .service(routes::get_children)
+                    .service(routes::create_child)
+                    .service(routes::delete_child)
+                    .service(routes::create_apikey)
+                    .service(routes::delete_apikey)
+                    .service(routes::update_apikey)
+                    .service(routes::patch_apikey),
+            )
+            .service(
+                web::scope("/plans")
+                    .app_data(state.clone())
+                    .wrap(middlewares::Idempotent::new(idempotency.clone()))
+                    .service(routes::get_plans)
+                    .service(routes::get_plan)
+                    .service(routes::create_plan)
+                    .service(routes::replace_plan)
+                    .service(routes::delete_plan),
+            )
+            .service(
+                web::scope("/principals")
+                    .app_data(state.clone())
+                    .wrap(middlewares::Idempotent::new(idempotency.clone()))
+                    .service(routes::get_principals)
+                    .service(routes::get_principal)
+                    .service(routes::create_principal)
+                    .service(routes::patch_principal)
+                    .service(routes::delete_principal),
+            )
+            .service(
+                web::scope("/lockdowns")
+                    .app_data(state.clone())
+                    .wrap(middlewares::Idempotent::new(idempotency.clone()))
+                    .service(routes::get_lockdowns)
+                    .service(routes::put_lockdown)
+                    .service(routes::lift_lockdown)
+                    .service(routes::put_owner_lockdown)
+                    .service(routes::lift_owner_lockdown),
+            )
+            .service(
+                web::scope("/admin")
+                    .app_data(state.clone())
+                    .app_data(dashboard.clone())
+                    .service(routes::dashboard_apikeys)
+                    .service(routes::dashboard_create)
+                    .service(routes::dashboard_apikey)
+                    .service(routes::dashboard_disable)
+                    .service(routes::dashboard_rotate),
+            );
+        match &signups {
+            Some(signups) => app.service(
+                web::scope("/signups")
+                    .app_data(state)
+                    .app_data(signups.clone())
+                    .wrap(middlewares::Idempotent::new(idempotency.clone()))
+                    .service(routes::create_signup)
+                    .service(routes::confirm_signup)
+                    .service(routes::verify_signup),
+            ),
+            None => app,
+        }
+    })
+    .listen(listener)?
+    // On SIGTERM or SIGINT, stop accepting connections and let in-flight requests finish
+    .shutdown_timeout(settings.server.shutdown_timeout)
+    .run();
+    Ok(server)
+}
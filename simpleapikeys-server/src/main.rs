@@ -1,26 +1,34 @@
-use actix_web::{self, web, HttpServer};
-use clap::{self, Arg};
-use mongodb::{options::ClientOptions, Client};
-use services::ApiKeyService;
+use std::net::TcpListener;
 
-mod error;
-mod models;
-mod routes;
-mod services;
+use clap::{self, Arg, SubCommand};
+use simpleapikeys_server::serve;
+use simpleapikeys_server::services::ApiKeyService;
+use simpleapikeys_server::settings::Settings;
 
-struct ServiceContainer {
-    apikey: ApiKeyService,
+/// Arguments shared by the server and the migrate subcommand
+///
+/// None of them are required, as they may also be set in a configuration file or the environment.
+fn common_args<'a, 'b>() -> [Arg<'a, 'b>; 3] {
+    [
+        Arg::with_name("config")
+            .long("config")
+            .help("A TOML or YAML configuration file, also read from LANTHER_CONFIG")
+            .takes_value(true),
+        Arg::with_name("mongo-db-address")
+            .long("mongo-db-address")
+            .help("The address of a Mongo DB instance")
+            .takes_value(true),
+        Arg::with_name("mongo-db")
+            .long("mongo-db")
+            .help("The name of a Mongo DB")
+            .takes_value(true),
+    ]
 }
 
-impl ServiceContainer {
-    fn new(apikey: ApiKeyService) -> Self {
-        ServiceContainer { apikey }
-    }
-}
-
-// Application state to be shared
-struct AppState {
-    service: ServiceContainer,
+/// Report an error that prevents the server from starting and exit
+fn exit_with(e: impl std::fmt::Display) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(1)
 }
 
 #[actix_web::main]
@@ -35,56 +43,105 @@ async fn main() -> std::io::Result<()> {
             Arg::with_name("ADDRESS")
                 .help("The address the SimpleAPI key server will be listening to")
                 .takes_value(true)
-                .index(1)
-                .required(true),
+                .index(1),
         )
+        .args(&common_args())
         .arg(
-            Arg::with_name("mongo-db-address")
-                .long("mongo-db-address")
-                .help("The address of a Mongo DB instance")
-                .takes_value(true)
-                .required(true),
+            Arg::with_name("skip-migrations")
+                .long("skip-migrations")
+                .help("Do not apply pending database migrations on startup"),
         )
         .arg(
-            Arg::with_name("mongo-db")
-                .long("mongo-db")
-                .help("The name of a Mongo DB")
-                .takes_value(true)
-                .required(true),
+            Arg::with_name("idempotency-window")
+                .long("idempotency-window")
+                .help("How long to replay responses for a repeated Idempotency-Key, in seconds")
+                .takes_value(true),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Apply pending database migrations and exit")
+                .args(&common_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("reencrypt")
+                .about("Re-encrypt every API key with the current master key and exit")
+                .args(&common_args()),
         )
         .get_matches();
+    let migrate = matches.subcommand_matches("migrate");
+    let reencrypt = matches.subcommand_matches("reencrypt");
+    let matches = migrate.or(reencrypt).unwrap_or(&matches);
 
-    let db_address = matches
-        .value_of("mongo-db-address")
-        .expect("mongo-db-address is a required argument");
-    let db_name = matches
-        .value_of("mongo-db")
-        .expect("mongo-db is a required argument");
+    let settings = Settings::load(matches).unwrap_or_else(|e| exit_with(e));
+    let validated = if migrate.is_some() {
+        settings.validate_migrate()
+    } else if reencrypt.is_some() {
+        settings.validate_reencrypt()
+    } else {
+        settings.validate_server()
+    };
+    validated.unwrap_or_else(|e| exit_with(e));
 
-    let client_options = ClientOptions::parse(db_address).await.unwrap();
-    let client = Client::with_options(client_options).unwrap();
-    let db = client.database(db_name);
-    let apikeys = db.collection("apikeys");
+    let db = lanther_store::connect(&settings.mongo)
+        .await
+        .unwrap_or_else(|e| exit_with(e));
 
-    let address = matches
-        .value_of("ADDRESS")
-        .expect("ADDRESS is a required argument");
-
-    log::info!("Starting SimpleAPI Keys Server on: {}", address);
+    if reencrypt.is_some() {
+        let keyring = settings
+            .encryption
+            .keyring()
+            .expect("encryption is validated");
+        let reencrypted = ApiKeyService::new(
+            lanther_store::Collection::Mongo(db.collection("apikeys")),
+            keyring,
+            settings.quotas.clone(),
+        )
+        .reencrypt()
+        .await
+        .unwrap_or_else(|e| exit_with(e));
+        log::info!(
+            "Rotated {}, encrypted {} and indexed {} API keys, skipped {} written to meanwhile",
+            reencrypted.rotated,
+            reencrypted.encrypted,
+            reencrypted.indexed,
+            reencrypted.skipped
+        );
+        if reencrypted.skipped > 0 {
+            exit_with("Some API keys were skipped, run reencrypt again to pick them up");
+        }
+        return Ok(());
+    }
 
-    HttpServer::new(move || {
-        let service = ServiceContainer::new(ApiKeyService::new(apikeys.clone()));
-        actix_web::App::new().service(
-            web::scope("/apikeys")
-                .data(AppState { service })
-                .service(routes::get_apikeys)
-                .service(routes::get_apikey)
-                .service(routes::create_apikey)
-                .service(routes::delete_apikey)
-                .service(routes::update_apikey),
+    if migrate.is_some() || settings.migrations.on_startup {
+        let applied = lanther_migrations::run(
+            &db,
+            &[
+                "apikeys",
+                "idempotency_keys",
+                "plans",
+                "audit_log",
+                "signups",
+                "principals",
+            ],
         )
-    })
-    .bind(address)?
-    .run()
-    .await
+        .await
+        .unwrap_or_else(|e| exit_with(e));
+        log::info!("Applied {} pending migrations", applied.len());
+    }
+    if migrate.is_some() {
+        return Ok(());
+    }
+
+    let address = settings
+        .server
+        .address
+        .as_deref()
+        .expect("server.address is validated");
+    let listener = TcpListener::bind(address)
+        .unwrap_or_else(|e| exit_with(format!("Could not listen on {}: {}", address, e)));
+    serve(&settings, lanther_store::Database::Mongo(db), listener)?.await?;
+
+    log::info!("SimpleAPI Keys Server stopped");
+    log::logger().flush();
+    Ok(())
 }
@@ -0,0 +1,230 @@
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::body::{Body, MessageBody, ResponseBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, HeaderName, HeaderValue, Method, StatusCode};
+use actix_web::{web, Error, HttpMessage, HttpResponse};
+use futures::future::{ok, Ready};
+use futures::{stream, Future, StreamExt};
+use sha2::{Digest, Sha256};
+
+use crate::error::ApiError;
+use crate::models::StoredResponse;
+use crate::services::{Claim, IdempotencyService};
+
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+pub const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+
+/// Maximum length of an Idempotency-Key
+const MAX_KEY_LENGTH: usize = 255;
+/// Maximum size of a request body that will be buffered to fingerprint it
+const MAX_BODY_SIZE: usize = 262_144;
+
+/// Middleware to honour the Idempotency-Key header on mutating requests
+///
+/// The first request with a given key is processed as usual and its response recorded. Retries
+/// with the same key and an identical request get the recorded response replayed, while a
+/// different request reusing the key is rejected. Keys are scoped to the credentials a request
+/// is made with, so a response is only ever replayed to whoever it was first sent to. Anonymous
+/// requests have nothing to scope them to, so they may not send a key at all.
+pub struct Idempotent(Rc<IdempotencyService>);
+
+impl Idempotent {
+    pub fn new(service: IdempotencyService) -> Idempotent {
+        Idempotent(Rc::new(service))
+    }
+}
+
+impl<S, B> Transform<S> for Idempotent
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = IdempotentMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(IdempotentMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            idempotency: self.0.clone(),
+        })
+    }
+}
+
+pub struct IdempotentMiddleware<S> {
+    idempotency: Rc<IdempotencyService>,
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for IdempotentMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let idempotency = self.idempotency.clone();
+
+        let is_mutating =
+            [Method::POST, Method::PUT, Method::PATCH, Method::DELETE].contains(req.method());
+        let key = req
+            .headers()
+            .get(IDEMPOTENCY_KEY)
+            .map(|h| h.to_str().map(|k| k.to_string()));
+
+        Box::pin(async move {
+            let key = match key {
+                Some(Ok(key)) if is_mutating => key,
+                Some(_) if is_mutating => return Err(ApiError::InvalidIdempotencyKey.into()),
+                _ => {
+                    let fut = service.borrow_mut().call(req);
+                    let res = fut.await?;
+                    return Ok(
+                        res.map_body(|_, body| ResponseBody::Other(Body::from_message(body)))
+                    );
+                }
+            };
+            if key.is_empty() || key.len() > MAX_KEY_LENGTH {
+                return Err(ApiError::InvalidIdempotencyKey.into());
+            }
+            let caller = caller(&req).ok_or(ApiError::AnonymousIdempotencyKey)?;
+            let record = format!("{}:{}", caller, key);
+
+            // The body has to be read to fingerprint the request, so put it back for the handler
+            let mut body = web::BytesMut::new();
+            let mut payload = req.take_payload();
+            while let Some(chunk) = payload.next().await {
+                let chunk = chunk?;
+                if (body.len() + chunk.len()) > MAX_BODY_SIZE {
+                    return Err(ApiError::PayloadTooLarge.into());
+                }
+                body.extend_from_slice(&chunk);
+            }
+            let body = body.freeze();
+            let fingerprint = fingerprint(&req, &body);
+            req.set_payload(Payload::Stream(Box::pin(stream::once(async { Ok(body) }))));
+
+            match idempotency.claim(&record, &fingerprint).await {
+                Ok(Claim::Claimed) => {}
+                Ok(Claim::Replay(stored)) => {
+                    log::debug!("Replaying response for Idempotency-Key {}", key);
+                    return Ok(req.into_response(replay(&stored)));
+                }
+                Ok(Claim::InProgress) => return Err(ApiError::IdempotencyKeyInProgress.into()),
+                Ok(Claim::Mismatch) => return Err(ApiError::IdempotencyKeyMismatch.into()),
+                Err(e) => return Err(e.into()),
+            }
+
+            let fut = service.borrow_mut().call(req);
+            let res = match fut.await {
+                Ok(res) => res,
+                Err(e) => {
+                    release(&idempotency, &record, &key).await;
+                    return Err(e);
+                }
+            };
+
+            // Server errors are not recorded, as retrying them may well succeed, and neither are
+            // refusals to authenticate, which say nothing of what the request would have done
+            let status = res.status();
+            if status.is_server_error()
+                || status == StatusCode::UNAUTHORIZED
+                || status == StatusCode::FORBIDDEN
+            {
+                release(&idempotency, &record, &key).await;
+                return Ok(res.map_body(|_, body| ResponseBody::Other(Body::from_message(body))));
+            }
+
+            let mut res = res;
+            let mut body = web::BytesMut::new();
+            let mut stream = res.take_body();
+            while let Some(chunk) = stream.next().await {
+                body.extend_from_slice(&chunk?);
+            }
+            let stored = StoredResponse {
+                status: res.status().as_u16(),
+                headers: res
+                    .headers()
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        value
+                            .to_str()
+                            .ok()
+                            .map(|v| (name.as_str().to_string(), v.to_string()))
+                    })
+                    .collect(),
+                body: body.to_vec(),
+            };
+            if let Err(e) = idempotency.complete(&record, &stored).await {
+                log::error!(
+                    "Error recording response for Idempotency-Key {}: {}",
+                    key,
+                    e
+                );
+            }
+
+            let body = body.freeze();
+            Ok(res.map_body(|_, _| ResponseBody::Other(Body::from(body))))
+        })
+    }
+}
+
+/// Identify who makes a request by a digest of its credentials, if it has any
+fn caller(req: &ServiceRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .map(|credentials| format!("{:x}", Sha256::digest(credentials.as_bytes())))
+}
+
+/// Identify a request by its method, path and body
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(req.uri().to_string().as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body);
+    format!("{:x}", hasher.finalize())
+}
+
+fn replay(stored: &StoredResponse) -> HttpResponse {
+    let mut builder = HttpResponse::build(
+        actix_web::http::StatusCode::from_u16(stored.status)
+            .unwrap_or(actix_web::http::StatusCode::OK),
+    );
+    for (name, value) in &stored.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            builder.header(name, value);
+        }
+    }
+    builder.header(IDEMPOTENT_REPLAYED, "true");
+    builder.body(stored.body.clone())
+}
+
+async fn release(idempotency: &IdempotencyService, record: &str, key: &str) {
+    if let Err(e) = idempotency.release(record).await {
+        log::error!("Error releasing Idempotency-Key {}: {}", key, e);
+    }
+}
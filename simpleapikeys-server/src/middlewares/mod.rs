@@ -0,0 +1,4 @@
+pub mod idempotency;
+
+pub use idempotency::Idempotent;
+pub use lanther_common::middlewares::{Metered, Traced};
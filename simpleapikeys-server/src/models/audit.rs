@@ -0,0 +1,31 @@
+use bson::Document;
+use chrono::Utc;
+use mongodb::bson::doc;
+
+/// An entry of the audit log, recording an action taken on an API key, or on many like a lockdown
+#[derive(Debug)]
+pub struct AuditRecord {
+    /// What was done, like `apikey.revoked`
+    pub action: &'static str,
+    /// The API key acted on, if the action was on a single one
+    pub key: Option<String>,
+    /// Who did it, like `leak_reporter`
+    pub actor: &'static str,
+    /// Anything else worth keeping about the action
+    pub details: Document,
+}
+
+impl AuditRecord {
+    pub fn to_bson_document(&self) -> Document {
+        let mut document = doc! {
+            "action": self.action,
+            "actor": self.actor,
+            "details": self.details.clone(),
+            "created_at": Utc::now(),
+        };
+        if let Some(key) = &self.key {
+            document.insert("key", key.clone());
+        }
+        document
+    }
+}
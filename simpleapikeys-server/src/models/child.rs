@@ -0,0 +1,138 @@
+use bson::Bson;
+use serde_json::Value;
+
+use super::{schedule, ApiKey, ApiKeyPatch, NewApiKey, Verification};
+use crate::error::FieldErrors;
+
+/// Fields that may be given to create a child key, all of them optional
+const CHILD_FIELDS: [&str; 6] = [
+    "name",
+    "labels",
+    "scopes",
+    "expires_at",
+    "schedule",
+    "limits",
+];
+
+impl NewApiKey {
+    /// Validate a child key to delegate from a parent key, collecting an error message for every
+    /// offending field
+    ///
+    /// Fields are validated as they would be when patching an API key. The scopes, expiry and
+    /// limits of the child must then be within those of its parent, which it inherits unless
+    /// given narrower ones. Children always belong to the owner of their parent.
+    pub fn child_of(
+        parent: &ApiKey,
+        resolved: &Verification,
+        body: &Value,
+    ) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        if let Some(fields) = body.as_object() {
+            for field in fields.keys() {
+                if !CHILD_FIELDS.contains(&field.as_str()) {
+                    errors.insert(field.clone(), "cannot be set on child keys".to_string());
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let patch = ApiKeyPatch::from_json(body)?;
+
+        let mut child = NewApiKey::new(parent.environment);
+        child.parent = Some(parent.key);
+        child.owner = parent.owner.clone();
+        for (path, value) in patch.set.iter() {
+            match (path.as_str(), value) {
+                ("name", Bson::String(name)) => child.name = Some(name.clone()),
+                ("scopes", Bson::Array(scopes)) => {
+                    child.scopes = scopes
+                        .iter()
+                        .filter_map(|scope| scope.as_str().map(|s| s.to_string()))
+                        .collect()
+                }
+                ("expires_at", Bson::DateTime(expires_at)) => child.expires_at = Some(*expires_at),
+                ("schedule", schedule) => child.schedule = schedule::from_bson(schedule),
+                (path, Bson::String(value)) if path.starts_with("labels.") => {
+                    child
+                        .labels
+                        .insert(path["labels.".len()..].to_string(), value.clone());
+                }
+                ("limits.requests_per_minute", Bson::Int64(v)) => {
+                    child.limits.requests_per_minute = Some(*v)
+                }
+                ("limits.monthly_quota", Bson::Int64(v)) => child.limits.monthly_quota = Some(*v),
+                ("limits.max_payload_size", Bson::Int64(v)) => {
+                    child.limits.max_payload_size = Some(*v)
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(scopes) = &resolved.scopes {
+            if child.scopes.is_empty() {
+                child.scopes = scopes.clone();
+            } else if child.scopes.iter().any(|scope| !scopes.contains(scope)) {
+                errors.insert(
+                    "scopes".to_string(),
+                    format!(
+                        "must be a subset of the parent key's scopes: {}",
+                        scopes.join(", ")
+                    ),
+                );
+            }
+        }
+
+        if let Some(parent_expires_at) = parent.expires_at {
+            match child.expires_at {
+                None => child.expires_at = Some(parent_expires_at),
+                Some(expires_at) if expires_at > parent_expires_at => {
+                    errors.insert(
+                        "expires_at".to_string(),
+                        format!(
+                            "must not be later than the parent key's, {}",
+                            parent_expires_at.to_rfc3339()
+                        ),
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+
+        let limits = [
+            (
+                "limits.requests_per_minute",
+                &mut child.limits.requests_per_minute,
+                resolved.limits.requests_per_minute,
+            ),
+            (
+                "limits.monthly_quota",
+                &mut child.limits.monthly_quota,
+                resolved.limits.monthly_quota,
+            ),
+            (
+                "limits.max_payload_size",
+                &mut child.limits.max_payload_size,
+                resolved.limits.max_payload_size,
+            ),
+        ];
+        for (path, limit, parent_limit) in limits {
+            match (*limit, parent_limit) {
+                (None, Some(parent_limit)) => *limit = Some(parent_limit),
+                (Some(value), Some(parent_limit)) if value > parent_limit => {
+                    errors.insert(
+                        path.to_string(),
+                        format!("must not exceed the parent key's, {}", parent_limit),
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(child)
+        } else {
+            Err(errors)
+        }
+    }
+}
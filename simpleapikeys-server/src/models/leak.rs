@@ -0,0 +1,75 @@
+use serde_json::Value;
+
+use crate::error::FieldErrors;
+
+/// The most candidate keys a single leak report may hold
+pub const MAX_CANDIDATES: usize = 1000;
+
+/// A batch of strings found in public, which may be API keys
+#[derive(Debug)]
+pub struct LeakReport {
+    pub keys: Vec<String>,
+    /// Where the candidates were found, like the URL of a public repository
+    pub source: Option<String>,
+}
+
+impl LeakReport {
+    /// Validate a leak report, collecting an error message for every offending field
+    pub fn from_json(report: &Value) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let fields = match report.as_object() {
+            Some(fields) => fields,
+            None => {
+                errors.insert("".to_string(), "must be a JSON object".to_string());
+                return Err(errors);
+            }
+        };
+
+        let mut result = LeakReport {
+            keys: Vec::new(),
+            source: None,
+        };
+        for (field, value) in fields {
+            match field.as_str() {
+                "keys" => match value.as_array() {
+                    Some(keys) if keys.len() > MAX_CANDIDATES => {
+                        errors.insert(
+                            field.clone(),
+                            format!("must hold at most {} keys", MAX_CANDIDATES),
+                        );
+                    }
+                    Some(keys) if keys.iter().all(Value::is_string) => {
+                        for key in keys.iter().filter_map(Value::as_str) {
+                            let key = key.trim().to_string();
+                            if !result.keys.contains(&key) {
+                                result.keys.push(key);
+                            }
+                        }
+                    }
+                    _ => {
+                        errors.insert(field.clone(), "must be an array of strings".to_string());
+                    }
+                },
+                "source" => match value {
+                    Value::Null => {}
+                    Value::String(source) => result.source = Some(source.clone()),
+                    _ => {
+                        errors.insert(field.clone(), "must be a string or null".to_string());
+                    }
+                },
+                _ => {
+                    errors.insert(field.clone(), "is not a known field".to_string());
+                }
+            }
+        }
+        if !fields.contains_key("keys") {
+            errors.insert("keys".to_string(), "is required".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(result)
+        } else {
+            Err(errors)
+        }
+    }
+}
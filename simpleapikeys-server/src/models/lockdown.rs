@@ -0,0 +1,123 @@
+use bson::{document::ValueAccessError, Bson, Document};
+use mongodb::bson::doc;
+use serde_json::Value;
+
+use super::{FromBsonDocument, Lockdown};
+use crate::error::FieldErrors;
+
+/// The `_id` of the global lockdown, as there is only ever one
+pub const GLOBAL: &str = "global";
+
+/// The `_id` of the lockdown of an owner, as there is at most one for each
+pub fn owner_id(owner: &str) -> String {
+    format!("owner:{}", owner)
+}
+
+impl FromBsonDocument for Lockdown {
+    fn from_bson_document(doc: &Document) -> Result<Self, ValueAccessError> {
+        Ok(Lockdown {
+            owner: doc.get_str("owner").ok().map(|owner| owner.to_string()),
+            allow: match doc.get_array("allow") {
+                Ok(allow) => allow
+                    .iter()
+                    .filter_map(|owner| owner.as_str().map(|s| s.to_string()))
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+            reason: doc.get_str("reason")?.to_string(),
+            created_at: *doc.get_datetime("created_at")?,
+        })
+    }
+}
+
+/// The fields of a lockdown, as given to put it in place
+#[derive(Debug)]
+pub struct LockdownInput {
+    pub reason: String,
+    pub allow: Vec<String>,
+}
+
+impl LockdownInput {
+    /// Validate a lockdown, collecting an error message for every offending field
+    ///
+    /// Only a global lockdown, which has no `owner`, may `allow` some owners through.
+    pub fn from_json(body: &Value, owner: Option<&str>) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let fields = match body.as_object() {
+            Some(fields) => fields,
+            None => {
+                errors.insert("".to_string(), "must be a JSON object".to_string());
+                return Err(errors);
+            }
+        };
+
+        let mut result = LockdownInput {
+            reason: String::new(),
+            allow: Vec::new(),
+        };
+        for (field, value) in fields {
+            match field.as_str() {
+                "reason" => match value.as_str() {
+                    Some(reason) if !reason.trim().is_empty() && reason.len() <= 500 => {
+                        result.reason = reason.trim().to_string()
+                    }
+                    _ => {
+                        errors.insert(
+                            field.clone(),
+                            "must be a non-empty string of at most 500 characters".to_string(),
+                        );
+                    }
+                },
+                "allow" if owner.is_some() => {
+                    errors.insert(
+                        field.clone(),
+                        "may only be given for a global lockdown".to_string(),
+                    );
+                }
+                "allow" => match value.as_array() {
+                    Some(allow)
+                        if allow.len() <= 100
+                            && allow
+                                .iter()
+                                .all(|owner| owner.as_str().is_some_and(|o| !o.is_empty())) =>
+                    {
+                        result.allow = allow
+                            .iter()
+                            .filter_map(|owner| owner.as_str().map(|o| o.to_string()))
+                            .collect()
+                    }
+                    _ => {
+                        errors.insert(
+                            field.clone(),
+                            "must be an array of at most 100 non-empty owners".to_string(),
+                        );
+                    }
+                },
+                _ => {
+                    errors.insert(field.clone(), "is not a known field".to_string());
+                }
+            }
+        }
+        if !fields.contains_key("reason") {
+            errors.insert("reason".to_string(), "is required".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(result)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn to_bson_document(&self) -> Document {
+        let allow: Vec<Bson> = self
+            .allow
+            .iter()
+            .map(|owner| Bson::String(owner.clone()))
+            .collect();
+        doc! {
+            "reason": self.reason.clone(),
+            "allow": allow,
+        }
+    }
+}
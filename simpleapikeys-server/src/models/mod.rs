@@ -1,47 +1,303 @@
-use bson::{document::ValueAccessError, Document};
+use std::collections::BTreeMap;
+
+use bson::{document::ValueAccessError, Bson, Document};
 use chrono::prelude::*;
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use uuid::Uuid;
 
-/// Represents an API key as defined by a UUID
-#[derive(Serialize, Deserialize, Debug)]
-pub struct ApiKey {
-    #[serde(rename = "_id")]
-    id: String,
-    key: Uuid,
-    disabled: bool,
-    created_at: DateTime<Utc>,
-    updated_at: DateTime<Utc>,
+use crate::error::FieldErrors;
+
+mod audit;
+mod child;
+mod leak;
+pub mod lockdown;
+mod patch;
+mod plan;
+mod principal;
+mod quota;
+pub mod schedule;
+pub mod search;
+mod signup;
+pub mod stats;
+
+pub use audit::AuditRecord;
+pub use lanther_common::models::{
+    ApiKey, ApiKeySearch, ApiKeyStats, Deleted, Environment, Group, LeakReportResult, LeakedKey,
+    Limits, Lockdown, OwnerUsage, Plan, Principal, PrincipalKind, Schedule, SearchHit, Signup,
+    UpdateApiKey, Verification, VerifiedPrincipal,
+};
+pub use leak::LeakReport;
+pub use lockdown::LockdownInput;
+pub use patch::ApiKeyPatch;
+pub use plan::PlanInput;
+pub use principal::{NewPrincipal, PrincipalPatch};
+pub use quota::QuotaState;
+pub use signup::NewSignup;
+
+/// Read a model shared with clients from the document it is stored as
+pub trait FromBsonDocument: Sized {
+    fn from_bson_document(doc: &Document) -> Result<Self, ValueAccessError>;
 }
 
-impl ApiKey {
-    pub fn from_bson_document(doc: &Document) -> Result<Self, ValueAccessError> {
+/// Write a model shared with clients as the document it is stored as
+pub trait ToBsonDocument {
+    fn to_bson_document(&self) -> Document;
+}
+
+/// The version of a stored API key, where keys stored before versions were introduced, and not
+/// yet backfilled by the migrations, are read as version 0
+pub fn version(doc: &Document) -> Result<i64, ValueAccessError> {
+    match doc.get("version") {
+        None | Some(Bson::Null) => Ok(0),
+        Some(Bson::Int64(version)) => Ok(*version),
+        Some(Bson::Int32(version)) => Ok(*version as i64),
+        Some(_) => Err(ValueAccessError::UnexpectedType),
+    }
+}
+
+/// A filter on the `version` of API keys matching any of some versions, where version 0 matches
+/// the keys stored without one
+pub fn versions_filter(versions: &[i64]) -> Document {
+    let mut allowed: Vec<Bson> = versions.iter().map(|v| Bson::Int64(*v)).collect();
+    if versions.contains(&0) {
+        allowed.push(Bson::Null);
+    }
+    doc! { "$in": allowed }
+}
+
+impl FromBsonDocument for ApiKey {
+    fn from_bson_document(doc: &Document) -> Result<Self, ValueAccessError> {
         Ok(ApiKey {
             id: doc.get_object_id("_id")?.to_hex(),
             key: Uuid::parse_str(doc.get_str("key")?).expect("Uuid parse failed"),
+            environment: doc
+                .get_str("environment")
+                .ok()
+                .and_then(Environment::parse)
+                .unwrap_or_default(),
+            parent: doc
+                .get_str("parent")
+                .ok()
+                .and_then(|parent| Uuid::parse_str(parent).ok()),
+            owner: doc.get_str("owner").ok().map(|owner| owner.to_string()),
+            name: doc.get_str("name").ok().map(|name| name.to_string()),
+            labels: match doc.get_document("labels") {
+                Ok(labels) => labels
+                    .iter()
+                    .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                    .collect(),
+                Err(_) => BTreeMap::new(),
+            },
+            scopes: match doc.get_array("scopes") {
+                Ok(scopes) => scopes
+                    .iter()
+                    .filter_map(|scope| scope.as_str().map(|s| s.to_string()))
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+            expires_at: doc.get_datetime("expires_at").ok().copied(),
+            schedule: doc.get("schedule").and_then(schedule::from_bson),
+            limits: match doc.get_document("limits") {
+                Ok(limits) => Limits::from_bson_document(limits)?,
+                Err(_) => Limits::default(),
+            },
+            plan: doc.get_str("plan").ok().map(|plan| plan.to_string()),
+            principal: doc
+                .get_str("principal")
+                .ok()
+                .map(|principal| principal.to_string()),
             disabled: doc.get_bool("disabled")?,
+            revoked_at: doc.get_datetime("revoked_at").ok().copied(),
+            revocation_reason: doc
+                .get_str("revocation_reason")
+                .ok()
+                .map(|reason| reason.to_string()),
+            version: version(doc)?,
             created_at: *doc.get_datetime("created_at")?,
             updated_at: *doc.get_datetime("updated_at")?,
         })
     }
 }
 
+impl FromBsonDocument for Limits {
+    /// Limits that are missing or are not integers are read as unlimited
+    fn from_bson_document(doc: &Document) -> Result<Self, ValueAccessError> {
+        let limit = |field| match doc.get(field) {
+            Some(Bson::Int64(v)) => Some(*v),
+            Some(Bson::Int32(v)) => Some(*v as i64),
+            _ => None,
+        };
+        Ok(Limits {
+            requests_per_minute: limit("requests_per_minute"),
+            monthly_quota: limit("monthly_quota"),
+            max_payload_size: limit("max_payload_size"),
+        })
+    }
+}
+
+impl ToBsonDocument for Limits {
+    fn to_bson_document(&self) -> Document {
+        let mut doc = Document::new();
+        let limits = [
+            ("requests_per_minute", self.requests_per_minute),
+            ("monthly_quota", self.monthly_quota),
+            ("max_payload_size", self.max_payload_size),
+        ];
+        for (field, limit) in limits.iter() {
+            if let Some(limit) = limit {
+                doc.insert(*field, *limit);
+            }
+        }
+        doc
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct NewApiKey {
     pub key: Uuid,
+    pub environment: Environment,
+    pub parent: Option<Uuid>,
+    pub owner: Option<String>,
+    pub plan: Option<String>,
+    pub principal: Option<String>,
+    pub name: Option<String>,
+    pub labels: BTreeMap<String, String>,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub schedule: Option<Schedule>,
+    pub limits: Limits,
 }
 
 impl NewApiKey {
-    pub fn new() -> Self {
+    pub fn new(environment: Environment) -> Self {
         NewApiKey {
             key: Uuid::new_v4(),
+            environment,
+            parent: None,
+            owner: None,
+            plan: None,
+            principal: None,
+            name: None,
+            labels: BTreeMap::new(),
+            scopes: Vec::new(),
+            expires_at: None,
+            schedule: None,
+            limits: Limits::default(),
+        }
+    }
+
+    /// Validate the optional body of a request to create an API key
+    ///
+    /// The environment cannot be changed once the API key is created, unlike the owner, the plan
+    /// and the principal, of which the last two must be checked to exist before creating the key.
+    pub fn from_json(body: &Value) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let fields = match body.as_object() {
+            Some(fields) => fields,
+            None => {
+                errors.insert("".to_string(), "must be a JSON object".to_string());
+                return Err(errors);
+            }
+        };
+
+        let mut environment = Environment::default();
+        let mut owner = None;
+        let mut plan = None;
+        let mut principal = None;
+        for (field, value) in fields {
+            match field.as_str() {
+                "owner" => match value.as_str() {
+                    Some(given) if !given.trim().is_empty() => owner = Some(given.to_string()),
+                    _ => {
+                        errors.insert(field.clone(), "must be a non-empty string".to_string());
+                    }
+                },
+                "plan" => match value {
+                    Value::Null => {}
+                    Value::String(given) if !given.is_empty() => plan = Some(given.clone()),
+                    _ => {
+                        errors.insert(
+                            field.clone(),
+                            "must be a non-empty string or null".to_string(),
+                        );
+                    }
+                },
+                "principal" => match value {
+                    Value::Null => {}
+                    Value::String(given) if !given.is_empty() => principal = Some(given.clone()),
+                    _ => {
+                        errors.insert(
+                            field.clone(),
+                            "must be a non-empty string or null".to_string(),
+                        );
+                    }
+                },
+                "environment" => match value.as_str().and_then(Environment::parse) {
+                    Some(parsed) => environment = parsed,
+                    None => {
+                        errors.insert(field.clone(), "must be either live or test".to_string());
+                    }
+                },
+                _ => {
+                    errors.insert(field.clone(), "is not a known field".to_string());
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(NewApiKey {
+                owner,
+                plan,
+                principal,
+                ..NewApiKey::new(environment)
+            })
+        } else {
+            Err(errors)
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
-pub struct UpdateApiKey {
-    pub key: Uuid,
-    pub disabled: bool,
+/// A response recorded for an Idempotency-Key, to be replayed when a request is retried
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl StoredResponse {
+    pub fn from_bson_document(doc: &Document) -> Result<Self, ValueAccessError> {
+        let mut headers = Vec::new();
+        for header in doc.get_array("headers")? {
+            if let Bson::Document(header) = header {
+                headers.push((
+                    header.get_str("name")?.to_string(),
+                    header.get_str("value")?.to_string(),
+                ));
+            }
+        }
+        Ok(StoredResponse {
+            status: doc.get_i32("status")? as u16,
+            headers,
+            body: doc.get_binary_generic("body")?.clone(),
+        })
+    }
+
+    pub fn to_bson_document(&self) -> Document {
+        let headers: Vec<Bson> = self
+            .headers
+            .iter()
+            .map(|(name, value)| Bson::Document(doc! { "name": name, "value": value }))
+            .collect();
+        doc! {
+            "status": self.status as i32,
+            "headers": headers,
+            "body": Bson::Binary(bson::Binary {
+                subtype: bson::spec::BinarySubtype::Generic,
+                bytes: self.body.clone(),
+            }),
+        }
+    }
 }
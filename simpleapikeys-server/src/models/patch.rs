@@ -0,0 +1,295 @@
+use bson::{Bson, Document};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+use super::{schedule, Limits};
+use crate::error::FieldErrors;
+
+/// Fields managed by the server, which can never be patched
+const IMMUTABLE_FIELDS: [&str; 9] = [
+    "_id",
+    "key",
+    "environment",
+    "parent",
+    "revoked_at",
+    "revocation_reason",
+    "version",
+    "created_at",
+    "updated_at",
+];
+
+/// A JSON merge patch (RFC 7396) over the mutable fields of an API key
+///
+/// The patch is translated into `$set` and `$unset` operators so it can be applied to the stored
+/// document atomically, without reading it first.
+#[derive(Debug, Default)]
+pub struct ApiKeyPatch {
+    pub set: Document,
+    pub unset: Document,
+}
+
+impl ApiKeyPatch {
+    /// Validate a merge patch, collecting an error message for every offending field
+    pub fn from_json(patch: &Value) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let fields = match patch.as_object() {
+            Some(fields) => fields,
+            None => {
+                errors.insert("".to_string(), "must be a JSON object".to_string());
+                return Err(errors);
+            }
+        };
+
+        let mut result = ApiKeyPatch::default();
+        for (field, value) in fields {
+            match field.as_str() {
+                "owner" => result.owner(value, &mut errors),
+                "name" => result.name(value, &mut errors),
+                "labels" => result.labels(value, &mut errors),
+                "scopes" => result.scopes(value, &mut errors),
+                "expires_at" => result.expires_at(value, &mut errors),
+                "schedule" => result.schedule(value, &mut errors),
+                "limits" => result.limits(value, &mut errors),
+                "plan" => result.plan(value, &mut errors),
+                "principal" => result.principal(value, &mut errors),
+                "disabled" => result.disabled(value, &mut errors),
+                f if IMMUTABLE_FIELDS.contains(&f) => {
+                    errors.insert(field.clone(), "is immutable".to_string());
+                }
+                _ => {
+                    errors.insert(field.clone(), "is not a known field".to_string());
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(result)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn set_or_unset(&mut self, path: String, value: Option<Bson>) {
+        match value {
+            Some(value) => self.set.insert(path, value),
+            None => self.unset.insert(path, ""),
+        };
+    }
+
+    fn owner(&mut self, value: &Value, errors: &mut FieldErrors) {
+        match value {
+            Value::Null => self.set_or_unset("owner".to_string(), None),
+            Value::String(owner) if !owner.trim().is_empty() => {
+                self.set_or_unset("owner".to_string(), Some(Bson::String(owner.clone())))
+            }
+            _ => {
+                errors.insert(
+                    "owner".to_string(),
+                    "must be a non-empty string or null".to_string(),
+                );
+            }
+        }
+    }
+
+    /// Whether the patch sets or removes the owner
+    pub fn changes_owner(&self) -> bool {
+        self.set.contains_key("owner") || self.unset.contains_key("owner")
+    }
+
+    fn name(&mut self, value: &Value, errors: &mut FieldErrors) {
+        match value {
+            Value::Null => self.set_or_unset("name".to_string(), None),
+            Value::String(name) if !name.trim().is_empty() => {
+                self.set_or_unset("name".to_string(), Some(Bson::String(name.clone())))
+            }
+            _ => {
+                errors.insert(
+                    "name".to_string(),
+                    "must be a non-empty string or null".to_string(),
+                );
+            }
+        }
+    }
+
+    fn labels(&mut self, value: &Value, errors: &mut FieldErrors) {
+        let labels = match value {
+            Value::Null => return self.set_or_unset("labels".to_string(), None),
+            Value::Object(labels) => labels,
+            _ => {
+                errors.insert(
+                    "labels".to_string(),
+                    "must be an object or null".to_string(),
+                );
+                return;
+            }
+        };
+
+        for (label, value) in labels {
+            let path = format!("labels.{}", label);
+            // Label names end up in a MongoDB field path, so they must be valid path segments
+            if label.is_empty() || label.contains('.') || label.starts_with('$') {
+                errors.insert(
+                    path,
+                    "label names must be non-empty and contain no '.' or leading '$'".to_string(),
+                );
+                continue;
+            }
+            match value {
+                Value::Null => self.set_or_unset(path, None),
+                Value::String(v) => self.set_or_unset(path, Some(Bson::String(v.clone()))),
+                _ => {
+                    errors.insert(path, "must be a string or null".to_string());
+                }
+            }
+        }
+    }
+
+    fn scopes(&mut self, value: &Value, errors: &mut FieldErrors) {
+        let scopes = match value {
+            Value::Null => return self.set_or_unset("scopes".to_string(), None),
+            Value::Array(scopes) => scopes,
+            _ => {
+                errors.insert("scopes".to_string(), "must be an array or null".to_string());
+                return;
+            }
+        };
+
+        let mut result: Vec<Bson> = Vec::new();
+        for scope in scopes {
+            match scope.as_str() {
+                Some(scope) if !scope.trim().is_empty() => {
+                    let scope = Bson::String(scope.to_string());
+                    if !result.contains(&scope) {
+                        result.push(scope);
+                    }
+                }
+                _ => {
+                    errors.insert(
+                        "scopes".to_string(),
+                        "must only contain non-empty strings".to_string(),
+                    );
+                    return;
+                }
+            }
+        }
+        self.set_or_unset("scopes".to_string(), Some(Bson::Array(result)));
+    }
+
+    fn expires_at(&mut self, value: &Value, errors: &mut FieldErrors) {
+        let expires_at = match value {
+            Value::Null => None,
+            Value::String(s) => match DateTime::parse_from_rfc3339(s) {
+                Ok(dt) => Some(Bson::DateTime(dt.with_timezone(&Utc))),
+                Err(_) => {
+                    errors.insert(
+                        "expires_at".to_string(),
+                        "must be an RFC 3339 timestamp or null".to_string(),
+                    );
+                    return;
+                }
+            },
+            _ => {
+                errors.insert(
+                    "expires_at".to_string(),
+                    "must be an RFC 3339 timestamp or null".to_string(),
+                );
+                return;
+            }
+        };
+        self.set_or_unset("expires_at".to_string(), expires_at);
+    }
+
+    fn schedule(&mut self, value: &Value, errors: &mut FieldErrors) {
+        match value {
+            Value::Null => self.set_or_unset("schedule".to_string(), None),
+            _ => {
+                if let Some(schedule) = schedule::from_json(value, "schedule", errors) {
+                    self.set_or_unset("schedule".to_string(), Some(schedule::to_bson(&schedule)));
+                }
+            }
+        }
+    }
+
+    fn limits(&mut self, value: &Value, errors: &mut FieldErrors) {
+        let limits = match value {
+            Value::Null => return self.set_or_unset("limits".to_string(), None),
+            Value::Object(limits) => limits,
+            _ => {
+                errors.insert(
+                    "limits".to_string(),
+                    "must be an object or null".to_string(),
+                );
+                return;
+            }
+        };
+
+        for (limit, value) in limits {
+            let path = format!("limits.{}", limit);
+            if !Limits::FIELDS.contains(&limit.as_str()) {
+                errors.insert(path, "is not a known field".to_string());
+                continue;
+            }
+            match value {
+                Value::Null => self.set_or_unset(path, None),
+                Value::Number(n) if n.as_i64().is_some_and(|n| n >= 0) => {
+                    self.set_or_unset(path, n.as_i64().map(Bson::Int64))
+                }
+                _ => {
+                    errors.insert(path, "must be a non-negative integer or null".to_string());
+                }
+            }
+        }
+    }
+
+    fn plan(&mut self, value: &Value, errors: &mut FieldErrors) {
+        match value {
+            Value::Null => self.set_or_unset("plan".to_string(), None),
+            Value::String(plan) if !plan.is_empty() => {
+                self.set_or_unset("plan".to_string(), Some(Bson::String(plan.clone())))
+            }
+            _ => {
+                errors.insert(
+                    "plan".to_string(),
+                    "must be a non-empty string or null".to_string(),
+                );
+            }
+        }
+    }
+
+    /// The plan the patch moves the key to, which must be checked to exist before applying it
+    pub fn plan_name(&self) -> Option<&str> {
+        self.set.get_str("plan").ok()
+    }
+
+    fn principal(&mut self, value: &Value, errors: &mut FieldErrors) {
+        match value {
+            Value::Null => self.set_or_unset("principal".to_string(), None),
+            Value::String(principal) if !principal.is_empty() => self.set_or_unset(
+                "principal".to_string(),
+                Some(Bson::String(principal.clone())),
+            ),
+            _ => {
+                errors.insert(
+                    "principal".to_string(),
+                    "must be a non-empty string or null".to_string(),
+                );
+            }
+        }
+    }
+
+    /// The principal the patch moves the key to, which must be checked to exist before applying it
+    pub fn principal_name(&self) -> Option<&str> {
+        self.set.get_str("principal").ok()
+    }
+
+    fn disabled(&mut self, value: &Value, errors: &mut FieldErrors) {
+        match value {
+            Value::Bool(disabled) => {
+                self.set_or_unset("disabled".to_string(), Some(Bson::Boolean(*disabled)))
+            }
+            _ => {
+                errors.insert("disabled".to_string(), "must be a boolean".to_string());
+            }
+        }
+    }
+}
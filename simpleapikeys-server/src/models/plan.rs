@@ -0,0 +1,196 @@
+use bson::{document::ValueAccessError, Bson, Document};
+use mongodb::bson::doc;
+use serde_json::Value;
+
+use super::{FromBsonDocument, Limits, Plan, ToBsonDocument};
+use crate::error::FieldErrors;
+
+impl FromBsonDocument for Plan {
+    fn from_bson_document(doc: &Document) -> Result<Self, ValueAccessError> {
+        Ok(Plan {
+            id: doc.get_object_id("_id")?.to_hex(),
+            name: doc.get_str("name")?.to_string(),
+            limits: match doc.get_document("limits") {
+                Ok(limits) => Limits::from_bson_document(limits)?,
+                Err(_) => Limits::default(),
+            },
+            scopes: match doc.get_array("scopes") {
+                Ok(scopes) => scopes
+                    .iter()
+                    .filter_map(|scope| scope.as_str().map(|s| s.to_string()))
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+            max_active_keys: doc.get_i64("max_active_keys").ok(),
+            created_at: *doc.get_datetime("created_at")?,
+            updated_at: *doc.get_datetime("updated_at")?,
+        })
+    }
+}
+
+/// The fields of a plan, as given to create or replace it
+#[derive(Debug)]
+pub struct PlanInput {
+    pub name: String,
+    pub limits: Limits,
+    pub scopes: Vec<String>,
+    pub max_active_keys: Option<i64>,
+}
+
+impl PlanInput {
+    /// Validate a plan, collecting an error message for every offending field
+    ///
+    /// When replacing a plan its name is taken from the URL, so `name` is given and the body may
+    /// only repeat it.
+    pub fn from_json(plan: &Value, name: Option<&str>) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let fields = match plan.as_object() {
+            Some(fields) => fields,
+            None => {
+                errors.insert("".to_string(), "must be a JSON object".to_string());
+                return Err(errors);
+            }
+        };
+
+        let mut result = PlanInput {
+            name: name.unwrap_or_default().to_string(),
+            limits: Limits::default(),
+            scopes: Vec::new(),
+            max_active_keys: None,
+        };
+        for (field, value) in fields {
+            match field.as_str() {
+                "name" => match (value.as_str(), name) {
+                    (Some(given), Some(name)) if given != name => {
+                        errors.insert(
+                            field.clone(),
+                            "must match the plan name in the URL".to_string(),
+                        );
+                    }
+                    (Some(given), _) => result.name = given.to_string(),
+                    (None, _) => {
+                        errors.insert(field.clone(), "must be a string".to_string());
+                    }
+                },
+                "limits" => result.limits = limits(value, &mut errors),
+                "scopes" => result.scopes = scopes(value, &mut errors),
+                "max_active_keys" => match value {
+                    Value::Null => result.max_active_keys = None,
+                    Value::Number(n) if n.as_i64().is_some_and(|n| n > 0) => {
+                        result.max_active_keys = n.as_i64()
+                    }
+                    _ => {
+                        errors.insert(
+                            field.clone(),
+                            "must be a positive integer or null".to_string(),
+                        );
+                    }
+                },
+                _ => {
+                    errors.insert(field.clone(), "is not a known field".to_string());
+                }
+            }
+        }
+
+        // Plans are addressed by name in URLs, so names are kept to URL-safe characters
+        let valid_name = !result.name.is_empty()
+            && result.name.len() <= 64
+            && result
+                .name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !valid_name && !errors.contains_key("name") {
+            errors.insert(
+                "name".to_string(),
+                "must be 1 to 64 letters, digits, '-' or '_'".to_string(),
+            );
+        }
+
+        if errors.is_empty() {
+            Ok(result)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn to_bson_document(&self) -> Document {
+        let scopes: Vec<Bson> = self
+            .scopes
+            .iter()
+            .map(|scope| Bson::String(scope.clone()))
+            .collect();
+        doc! {
+            "name": self.name.clone(),
+            "limits": self.limits.to_bson_document(),
+            "scopes": scopes,
+            // Written even when unlimited, so replacing a plan lifts a previous maximum
+            "max_active_keys": self.max_active_keys.map_or(Bson::Null, Bson::Int64),
+        }
+    }
+}
+
+fn limits(value: &Value, errors: &mut FieldErrors) -> Limits {
+    let mut result = Limits::default();
+    let limits = match value {
+        Value::Null => return result,
+        Value::Object(limits) => limits,
+        _ => {
+            errors.insert(
+                "limits".to_string(),
+                "must be an object or null".to_string(),
+            );
+            return result;
+        }
+    };
+
+    for (limit, value) in limits {
+        let path = format!("limits.{}", limit);
+        let parsed = match value {
+            Value::Null => None,
+            Value::Number(n) if n.as_i64().is_some_and(|n| n >= 0) => n.as_i64(),
+            _ => {
+                errors.insert(path, "must be a non-negative integer or null".to_string());
+                continue;
+            }
+        };
+        match limit.as_str() {
+            "requests_per_minute" => result.requests_per_minute = parsed,
+            "monthly_quota" => result.monthly_quota = parsed,
+            "max_payload_size" => result.max_payload_size = parsed,
+            _ => {
+                errors.insert(path, "is not a known field".to_string());
+            }
+        }
+    }
+    result
+}
+
+fn scopes(value: &Value, errors: &mut FieldErrors) -> Vec<String> {
+    let mut result = Vec::new();
+    let scopes = match value {
+        Value::Null => return result,
+        Value::Array(scopes) => scopes,
+        _ => {
+            errors.insert("scopes".to_string(), "must be an array or null".to_string());
+            return result;
+        }
+    };
+
+    for scope in scopes {
+        match scope.as_str() {
+            Some(scope) if !scope.trim().is_empty() => {
+                if !result.iter().any(|s| s == scope) {
+                    result.push(scope.to_string());
+                }
+            }
+            _ => {
+                errors.insert(
+                    "scopes".to_string(),
+                    "must only contain non-empty strings".to_string(),
+                );
+                return Vec::new();
+            }
+        }
+    }
+    result
+}
@@ -0,0 +1,232 @@
+use bson::{document::ValueAccessError, Bson, Document};
+use mongodb::bson::doc;
+use serde_json::{Map, Value};
+
+use super::{FromBsonDocument, Principal, PrincipalKind};
+use crate::error::FieldErrors;
+
+impl FromBsonDocument for Principal {
+    fn from_bson_document(doc: &Document) -> Result<Self, ValueAccessError> {
+        Ok(Principal {
+            id: doc.get_object_id("_id")?.to_hex(),
+            name: doc.get_str("name")?.to_string(),
+            kind: PrincipalKind::parse(doc.get_str("kind")?)
+                .ok_or(ValueAccessError::UnexpectedType)?,
+            description: doc
+                .get_str("description")
+                .ok()
+                .map(|description| description.to_string()),
+            metadata: match doc.get_document("metadata") {
+                Ok(metadata) => metadata
+                    .iter()
+                    .filter_map(|(k, v)| v.as_str().map(|v| (k.clone(), v.to_string())))
+                    .collect(),
+                Err(_) => Default::default(),
+            },
+            disabled: doc.get_bool("disabled")?,
+            created_at: *doc.get_datetime("created_at")?,
+            updated_at: *doc.get_datetime("updated_at")?,
+        })
+    }
+}
+
+/// A principal to create, enabled to begin with
+#[derive(Debug)]
+pub struct NewPrincipal {
+    pub name: String,
+    pub kind: PrincipalKind,
+    pub fields: PrincipalPatch,
+}
+
+impl NewPrincipal {
+    /// Validate a principal, collecting an error message for every offending field
+    pub fn from_json(body: &Value) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let fields = match body.as_object() {
+            Some(fields) => fields,
+            None => {
+                errors.insert("".to_string(), "must be a JSON object".to_string());
+                return Err(errors);
+            }
+        };
+
+        // Principals are addressed by name in URLs, so names are kept to URL-safe characters
+        let name = fields
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let valid_name = !name.is_empty()
+            && name.len() <= 64
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.');
+        if !valid_name {
+            errors.insert(
+                "name".to_string(),
+                "must be 1 to 64 letters, digits, '-', '_' or '.'".to_string(),
+            );
+        }
+        let kind = fields
+            .get("kind")
+            .and_then(Value::as_str)
+            .and_then(PrincipalKind::parse);
+        if kind.is_none() {
+            errors.insert(
+                "kind".to_string(),
+                "must be either user or service_account".to_string(),
+            );
+        }
+
+        let rest: Map<String, Value> = fields
+            .iter()
+            .filter(|(field, _)| *field != "name" && *field != "kind")
+            .map(|(field, value)| (field.clone(), value.clone()))
+            .collect();
+        let patch = PrincipalPatch::from_json(&Value::Object(rest));
+        if let Err(patch_errors) = &patch {
+            errors.extend(patch_errors.clone());
+        }
+        if let Ok(patch) = &patch {
+            if patch.set.contains_key("disabled") {
+                errors.insert(
+                    "disabled".to_string(),
+                    "cannot be given, as principals are created enabled".to_string(),
+                );
+            }
+        }
+
+        match (kind, patch) {
+            (Some(kind), Ok(fields)) if errors.is_empty() => Ok(NewPrincipal {
+                name: name.to_string(),
+                kind,
+                fields,
+            }),
+            _ => Err(errors),
+        }
+    }
+
+    pub fn to_bson_document(&self) -> Document {
+        let mut document = doc! {
+            "name": self.name.clone(),
+            "kind": self.kind.as_str(),
+            "disabled": false,
+        };
+        let mut metadata = Document::new();
+        for (path, value) in self.fields.set.iter() {
+            match path.strip_prefix("metadata.") {
+                Some(entry) => {
+                    metadata.insert(entry, value.clone());
+                }
+                None => {
+                    document.insert(path, value.clone());
+                }
+            }
+        }
+        if !metadata.is_empty() {
+            document.insert("metadata", metadata);
+        }
+        document
+    }
+}
+
+/// A JSON merge patch (RFC 7396) over the description, metadata and `disabled` state of a
+/// principal, translated into `$set` and `$unset` operators like that of an API key
+#[derive(Debug, Default)]
+pub struct PrincipalPatch {
+    pub set: Document,
+    pub unset: Document,
+}
+
+impl PrincipalPatch {
+    /// Validate a merge patch, collecting an error message for every offending field
+    pub fn from_json(patch: &Value) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let fields = match patch.as_object() {
+            Some(fields) => fields,
+            None => {
+                errors.insert("".to_string(), "must be a JSON object".to_string());
+                return Err(errors);
+            }
+        };
+
+        let mut result = PrincipalPatch::default();
+        for (field, value) in fields {
+            match (field.as_str(), value) {
+                ("description", Value::Null) => result.set_or_unset(field.clone(), None),
+                ("description", Value::String(description)) if description.len() <= 500 => {
+                    result.set_or_unset(field.clone(), Some(Bson::String(description.clone())))
+                }
+                ("description", _) => {
+                    errors.insert(
+                        field.clone(),
+                        "must be a string of at most 500 characters or null".to_string(),
+                    );
+                }
+                ("metadata", value) => result.metadata(value, &mut errors),
+                ("disabled", Value::Bool(disabled)) => {
+                    result.set_or_unset(field.clone(), Some(Bson::Boolean(*disabled)))
+                }
+                ("disabled", _) => {
+                    errors.insert(field.clone(), "must be a boolean".to_string());
+                }
+                ("name", _) | ("kind", _) => {
+                    errors.insert(field.clone(), "is immutable".to_string());
+                }
+                _ => {
+                    errors.insert(field.clone(), "is not a known field".to_string());
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(result)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Whether the patch disables or enables the principal, if it does either
+    pub fn disabled(&self) -> Option<bool> {
+        self.set.get_bool("disabled").ok()
+    }
+
+    fn set_or_unset(&mut self, path: String, value: Option<Bson>) {
+        match value {
+            Some(value) => self.set.insert(path, value),
+            None => self.unset.insert(path, ""),
+        };
+    }
+
+    fn metadata(&mut self, value: &Value, errors: &mut FieldErrors) {
+        let metadata = match value {
+            Value::Null => return self.set_or_unset("metadata".to_string(), None),
+            Value::Object(metadata) => metadata,
+            _ => {
+                errors.insert(
+                    "metadata".to_string(),
+                    "must be an object or null".to_string(),
+                );
+                return;
+            }
+        };
+
+        for (entry, value) in metadata {
+            let path = format!("metadata.{}", entry);
+            // Entry names end up in a MongoDB field path, so they must be valid path segments
+            if entry.is_empty() || entry.contains('.') || entry.starts_with('$') {
+                errors.insert(
+                    path,
+                    "entry names must be non-empty and contain no '.' or leading '$'".to_string(),
+                );
+                continue;
+            }
+            match value {
+                Value::Null => self.set_or_unset(path, None),
+                Value::String(v) => self.set_or_unset(path, Some(Bson::String(v.clone()))),
+                _ => {
+                    errors.insert(path, "must be a string or null".to_string());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use bson::{Bson, Document};
+use chrono::Utc;
+
+use super::ApiKey;
+
+/// What quotas an API key counts against: those of its owner and plan, only while it is active
+#[derive(Debug, PartialEq)]
+pub struct QuotaState {
+    pub owner: Option<String>,
+    pub plan: Option<String>,
+    pub active: bool,
+}
+
+impl QuotaState {
+    /// What an API key counts against as it is
+    pub fn of(apikey: &ApiKey) -> Self {
+        QuotaState {
+            owner: apikey.owner.clone(),
+            plan: apikey.plan.clone(),
+            active: !apikey.disabled && apikey.expires_at.is_none_or(|at| at > Utc::now()),
+        }
+    }
+
+    /// What an API key would count against once `$set` and `$unset` operators are applied to it
+    ///
+    /// Revoked keys stay disabled whatever is written to them, so they never count.
+    pub fn after(apikey: &ApiKey, set: &Document, unset: &Document) -> Self {
+        let text = |field: &str, current: &Option<String>| match set.get(field) {
+            Some(Bson::String(value)) => Some(value.clone()),
+            _ if unset.contains_key(field) => None,
+            _ => current.clone(),
+        };
+        let disabled = set.get_bool("disabled").unwrap_or(apikey.disabled);
+        let expires_at = match set.get_datetime("expires_at") {
+            Ok(expires_at) => Some(*expires_at),
+            Err(_) if unset.contains_key("expires_at") => None,
+            Err(_) => apikey.expires_at,
+        };
+        QuotaState {
+            owner: text("owner", &apikey.owner),
+            plan: text("plan", &apikey.plan),
+            active: apikey.revoked_at.is_none()
+                && !disabled
+                && expires_at.is_none_or(|at| at > Utc::now()),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use bson::Bson;
+use chrono_tz::Tz;
+use serde_json::Value;
+
+use super::Schedule;
+use crate::error::FieldErrors;
+use lanther_common::models::{minutes, Window, WEEKDAYS};
+
+/// The most windows a single schedule may have
+const MAX_WINDOWS: usize = 32;
+
+/// Validate a schedule, collecting an error message for every offending field under `path`
+pub fn from_json(schedule: &Value, path: &str, errors: &mut FieldErrors) -> Option<Schedule> {
+    let fields = match schedule.as_object() {
+        Some(fields) => fields,
+        None => {
+            errors.insert(path.to_string(), "must be an object or null".to_string());
+            return None;
+        }
+    };
+    let before = errors.len();
+    for field in fields.keys() {
+        if field != "timezone" && field != "windows" {
+            errors.insert(
+                format!("{}.{}", path, field),
+                "is not a known field".to_string(),
+            );
+        }
+    }
+
+    let timezone = match fields.get("timezone").and_then(Value::as_str) {
+        Some(timezone) if timezone.parse::<Tz>().is_ok() => timezone.to_string(),
+        _ => {
+            errors.insert(
+                format!("{}.timezone", path),
+                "must be an IANA timezone, like Europe/Madrid".to_string(),
+            );
+            String::new()
+        }
+    };
+
+    let mut windows = Vec::new();
+    match fields.get("windows").and_then(Value::as_array) {
+        Some(given) if !given.is_empty() && given.len() <= MAX_WINDOWS => {
+            for (i, window) in given.iter().enumerate() {
+                let path = format!("{}.windows.{}", path, i);
+                if let Some(window) = window_from_json(window, &path, errors) {
+                    windows.push(window);
+                }
+            }
+        }
+        _ => {
+            errors.insert(
+                format!("{}.windows", path),
+                format!("must be an array of 1 to {} windows", MAX_WINDOWS),
+            );
+        }
+    }
+
+    if errors.len() > before {
+        None
+    } else {
+        Some(Schedule { timezone, windows })
+    }
+}
+
+pub fn from_bson(schedule: &Bson) -> Option<Schedule> {
+    bson::from_bson(schedule.clone()).ok()
+}
+
+pub fn to_bson(schedule: &Schedule) -> Bson {
+    bson::to_bson(schedule).expect("schedules are serializable")
+}
+
+fn window_from_json(window: &Value, path: &str, errors: &mut FieldErrors) -> Option<Window> {
+    let fields = match window.as_object() {
+        Some(fields) => fields,
+        None => {
+            errors.insert(path.to_string(), "must be an object".to_string());
+            return None;
+        }
+    };
+    let before = errors.len();
+    for field in fields.keys() {
+        if !["days", "start", "end"].contains(&field.as_str()) {
+            errors.insert(
+                format!("{}.{}", path, field),
+                "is not a known field".to_string(),
+            );
+        }
+    }
+
+    let mut days: Vec<String> = Vec::new();
+    match fields.get("days").and_then(Value::as_array) {
+        Some(given)
+            if !given.is_empty()
+                && given
+                    .iter()
+                    .all(|day| day.as_str().is_some_and(|day| WEEKDAYS.contains(&day))) =>
+        {
+            for day in given.iter().filter_map(Value::as_str) {
+                if !days.iter().any(|d| d == day) {
+                    days.push(day.to_string());
+                }
+            }
+        }
+        _ => {
+            errors.insert(
+                format!("{}.days", path),
+                format!("must be a non-empty array of {}", WEEKDAYS.join(", ")),
+            );
+        }
+    }
+
+    let mut time = |field: &str| {
+        let given = fields.get(field).and_then(Value::as_str);
+        match given.and_then(minutes) {
+            Some(minutes) => Some((given.unwrap_or_default().to_string(), minutes)),
+            None => {
+                errors.insert(
+                    format!("{}.{}", path, field),
+                    "must be a time of day as HH:MM".to_string(),
+                );
+                None
+            }
+        }
+    };
+    let start = time("start");
+    let end = time("end");
+    if let (Some((_, start)), Some((_, end))) = (&start, &end) {
+        if start >= end {
+            errors.insert(
+                format!("{}.end", path),
+                "must be later than start, split windows going past midnight in two".to_string(),
+            );
+        }
+    }
+
+    match (start, end) {
+        (Some((start, _)), Some((end, _))) if errors.len() == before => {
+            Some(Window { days, start, end })
+        }
+        _ => None,
+    }
+}
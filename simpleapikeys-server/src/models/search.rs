@@ -0,0 +1,218 @@
+//! Terms API keys are searched by, and how well an API key matches a search
+//!
+//! The name and labels of API keys are encrypted, so they cannot be searched by the database.
+//! Instead, every API key stores hashes of the terms it can be found by: prefixes of the key, and
+//! prefixes of every word in its owner, name and label values. A search looks up the hashes of its
+//! own terms, then ranks the API keys found once decrypted.
+use std::collections::HashMap;
+
+use bson::Document;
+
+use super::ApiKey;
+use crate::error::FieldErrors;
+
+/// Shortest prefix of a key it can be found by, as shorter ones would match too many keys
+const MIN_KEY_PREFIX: usize = 4;
+/// Shortest prefix of a word it can be found by
+const MIN_WORD_PREFIX: usize = 2;
+/// Longest prefix of a word it can be found by, so long words do not add many terms
+const MAX_WORD_PREFIX: usize = 32;
+const MAX_QUERY_LEN: usize = 256;
+const MAX_PAGE: usize = 10_000;
+const DEFAULT_PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+
+/// Split text into lowercase words, on anything that is not a letter or digit
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= MIN_WORD_PREFIX)
+        .map(str::to_lowercase)
+}
+
+/// The prefixes of a string, from `min` up to `max` characters long
+fn prefixes(text: &str, min: usize, max: usize) -> impl Iterator<Item = String> + '_ {
+    text.char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .enumerate()
+        .filter(move |(count, _)| *count + 1 >= min && *count < max)
+        .map(move |(_, end)| text[..end].to_string())
+}
+
+/// The terms an API key can be found by, from the stored document it is written as once decrypted
+pub fn terms(doc: &Document) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    if let Ok(key) = doc.get_str("key") {
+        terms.extend(prefixes(key, MIN_KEY_PREFIX, usize::MAX));
+    }
+    let mut texts: Vec<&str> = Vec::new();
+    texts.extend(doc.get_str("owner").ok());
+    texts.extend(doc.get_str("name").ok());
+    if let Ok(labels) = doc.get_document("labels") {
+        texts.extend(labels.values().filter_map(|value| value.as_str()));
+    }
+    for text in texts {
+        for word in words(text) {
+            terms.extend(prefixes(&word, MIN_WORD_PREFIX, MAX_WORD_PREFIX));
+        }
+    }
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+/// A word of a search, or a whole token when it may be a prefix of a key
+struct Token {
+    text: String,
+    words: Vec<String>,
+}
+
+/// A search over API keys, as given to `GET /apikeys/search`
+#[derive(Debug)]
+pub struct Search {
+    pub query: String,
+    pub page: usize,
+    pub per_page: usize,
+}
+
+impl Search {
+    /// Read a search from the query string, where `q` is required and the page is 1-based
+    pub fn from_query(query: &HashMap<String, String>) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let q = query
+            .get("q")
+            .map(String::as_str)
+            .unwrap_or_default()
+            .trim();
+        if q.is_empty() || q.len() > MAX_QUERY_LEN {
+            errors.insert(
+                "q".to_string(),
+                format!("must be 1 to {} characters", MAX_QUERY_LEN),
+            );
+        } else if tokens(q).is_empty() {
+            errors.insert(
+                "q".to_string(),
+                format!(
+                    "must have a word of at least {} letters or digits, or {} characters of a key",
+                    MIN_WORD_PREFIX, MIN_KEY_PREFIX
+                ),
+            );
+        }
+        let mut number = |field: &str, default: usize, max: usize| {
+            let value = match query.get(field) {
+                Some(value) => value,
+                None => return default,
+            };
+            match value.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= max => n,
+                _ => {
+                    errors.insert(
+                        field.to_string(),
+                        format!("must be a number from 1 to {}", max),
+                    );
+                    default
+                }
+            }
+        };
+        let page = number("page", 1, MAX_PAGE);
+        let per_page = number("per_page", DEFAULT_PER_PAGE, MAX_PER_PAGE);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Search {
+            query: q.to_string(),
+            page,
+            per_page,
+        })
+    }
+
+    /// The terms to look API keys up by, truncated as the terms they are stored by are
+    pub fn terms(&self) -> Vec<String> {
+        let mut terms: Vec<String> = Vec::new();
+        for token in tokens(&self.query) {
+            if token.text.chars().count() >= MIN_KEY_PREFIX {
+                terms.push(token.text.clone());
+            }
+            for word in &token.words {
+                terms.push(word.chars().take(MAX_WORD_PREFIX).collect());
+            }
+        }
+        terms.sort();
+        terms.dedup();
+        terms
+    }
+
+    /// How well an API key matches the search, where 0 means it does not
+    ///
+    /// A token that is the start of the key is worth the most. Otherwise, each of its words is
+    /// worth as much as the best field it is found in, with whole words worth more than prefixes,
+    /// and the owner worth more than the name, which is worth more than labels.
+    pub fn score(&self, apikey: &ApiKey) -> i64 {
+        let key = apikey.key.to_hyphenated().to_string();
+        let owner = apikey.owner.as_deref().map(str::to_lowercase);
+        let owner_words: Vec<String> = apikey
+            .owner
+            .as_deref()
+            .map(words)
+            .into_iter()
+            .flatten()
+            .collect();
+        let name_words: Vec<String> = apikey
+            .name
+            .as_deref()
+            .map(words)
+            .into_iter()
+            .flatten()
+            .collect();
+        let label_words: Vec<String> = apikey
+            .labels
+            .values()
+            .flat_map(|value| words(value))
+            .collect();
+        let best = |word: &str, found: &[String], whole: i64, prefix: i64| {
+            if found.iter().any(|found| found == word) {
+                whole
+            } else if found.iter().any(|found| found.starts_with(word)) {
+                prefix
+            } else {
+                0
+            }
+        };
+
+        let mut score = 0;
+        for token in tokens(&self.query) {
+            if token.text.chars().count() >= MIN_KEY_PREFIX && key.starts_with(&token.text) {
+                score += if key == token.text { 20 } else { 10 };
+                continue;
+            }
+            for word in &token.words {
+                score += [
+                    if owner.as_deref() == Some(word.as_str()) {
+                        8
+                    } else {
+                        0
+                    },
+                    best(word, &owner_words, 6, 4),
+                    best(word, &name_words, 5, 3),
+                    best(word, &label_words, 3, 2),
+                ]
+                .iter()
+                .max()
+                .copied()
+                .unwrap_or_default();
+            }
+        }
+        score
+    }
+}
+
+/// Split a search into lowercase tokens on whitespace, leaving out those with nothing to look for
+fn tokens(query: &str) -> Vec<Token> {
+    query
+        .split_whitespace()
+        .map(|text| Token {
+            text: text.to_lowercase(),
+            words: words(text).collect(),
+        })
+        .filter(|token| !token.words.is_empty() || token.text.chars().count() >= MIN_KEY_PREFIX)
+        .collect()
+}
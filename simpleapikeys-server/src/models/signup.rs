@@ -0,0 +1,51 @@
+use lettre::Address;
+use serde_json::Value;
+
+use crate::error::FieldErrors;
+
+/// A request to sign up for an API key, activated once the email is verified
+#[derive(Debug)]
+pub struct NewSignup {
+    pub email: Address,
+}
+
+impl NewSignup {
+    /// Validate a signup, collecting an error message for every offending field
+    pub fn from_json(body: &Value) -> Result<Self, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let fields = match body.as_object() {
+            Some(fields) => fields,
+            None => {
+                errors.insert("".to_string(), "must be a JSON object".to_string());
+                return Err(errors);
+            }
+        };
+
+        let mut email = None;
+        for (field, value) in fields {
+            match field.as_str() {
+                "email" => match value.as_str().map(|v| v.trim().parse::<Address>()) {
+                    Some(Ok(address)) => {
+                        // Domains are case insensitive, so signups are limited by their lowercase
+                        let domain = address.domain().to_lowercase();
+                        email = Address::new(address.user(), domain).ok();
+                    }
+                    _ => {
+                        errors.insert(field.clone(), "must be an email address".to_string());
+                    }
+                },
+                _ => {
+                    errors.insert(field.clone(), "is not a known field".to_string());
+                }
+            }
+        }
+        if !fields.contains_key("email") {
+            errors.insert("email".to_string(), "is required".to_string());
+        }
+
+        match email {
+            Some(email) if errors.is_empty() => Ok(NewSignup { email }),
+            _ => Err(errors),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use bson::{document::ValueAccessError, Bson, Document};
+
+use super::{ApiKeyStats, FromBsonDocument, Group};
+use lanther_common::models::STATES;
+
+impl FromBsonDocument for ApiKeyStats {
+    /// Read the stats from the result of a `$facet` stage with one `$group` per breakdown
+    fn from_bson_document(doc: &Document) -> Result<Self, ValueAccessError> {
+        let by_state = by_state(doc.get_array("by_state")?)?;
+        Ok(ApiKeyStats {
+            total: by_state.iter().map(|group| group.count).sum(),
+            by_state,
+            by_owner: groups(doc.get_array("by_owner")?)?,
+            by_plan: groups(doc.get_array("by_plan")?)?,
+            by_month: groups(doc.get_array("by_month")?)?,
+        })
+    }
+}
+
+/// Read the counts of a `$group` by state, filling in the states no key is in
+pub fn by_state(results: &[Bson]) -> Result<Vec<Group>, ValueAccessError> {
+    let counted = groups(results)?;
+    Ok(STATES
+        .iter()
+        .map(|state| Group {
+            name: Some(state.to_string()),
+            count: counted
+                .iter()
+                .find(|group| group.name.as_deref() == Some(*state))
+                .map_or(0, |group| group.count),
+        })
+        .collect())
+}
+
+/// Read the counts of a `$group`, whose `_id` is the name each count is for
+pub fn groups(results: &[Bson]) -> Result<Vec<Group>, ValueAccessError> {
+    let mut groups = Vec::new();
+    for result in results {
+        if let Bson::Document(result) = result {
+            groups.push(Group {
+                name: result.get_str("_id").ok().map(|name| name.to_string()),
+                count: match result.get("count") {
+                    Some(Bson::Int64(count)) => *count,
+                    Some(Bson::Int32(count)) => *count as i64,
+                    _ => return Err(ValueAccessError::UnexpectedType),
+                },
+            });
+        }
+    }
+    Ok(groups)
+}
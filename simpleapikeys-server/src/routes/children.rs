@@ -0,0 +1,82 @@
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, post, web, HttpResponse};
+use lanther_common::Envelope;
+use serde_json::json;
+
+use super::{plan_of, resolve};
+use crate::error::{ApiError, ErrorCode, JsonError};
+use crate::models;
+
+/// Delegate a child key from an API key, with at most the scopes, expiry and limits of its parent
+///
+/// Child keys cannot have children of their own, and can only be created from a key that is
+/// neither disabled nor expired.
+#[post("/{key}/children")]
+pub async fn create_child(
+    key: web::Path<String>,
+    body: web::Bytes,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    let parent = app_data.service.apikey.get_by_key(&key).await?;
+    if parent.parent.is_some() {
+        return Err(ApiError::ChildKeyNotAllowed(
+            "child keys cannot have children of their own".to_string(),
+        )
+        .into());
+    }
+    let resolved = resolve(&app_data, &parent).await?;
+    if resolved.disabled || resolved.expired {
+        return Err(ApiError::ChildKeyNotAllowed(
+            "the parent key is disabled or expired".to_string(),
+        )
+        .into());
+    }
+
+    let body = if body.is_empty() {
+        json!({})
+    } else {
+        serde_json::from_slice(&body).map_err(ApiError::from)?
+    };
+    let child = models::NewApiKey::child_of(&parent, &resolved, &body)
+        .map_err(ApiError::ValidationError)?;
+    // Child keys take up a slot on the plan of their parent, which they are used on
+    let plan = plan_of(&app_data, &parent).await?;
+    let inserted = app_data.service.apikey.create(child, plan.as_ref()).await?;
+    let id = inserted.inserted_id.as_object_id().ok_or_else(|| {
+        JsonError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::InternalError,
+            "Insert failed",
+        )
+    })?;
+    let child = app_data.service.apikey.get_by_id(id).await?;
+    Ok(HttpResponse::Created().json(Envelope::created(child)))
+}
+
+#[get("/{key}/children")]
+pub async fn get_children(
+    key: web::Path<String>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    // Tell a key without children apart from a key that does not exist
+    app_data.service.apikey.get_by_key(&key).await?;
+    let children = app_data.service.apikey.get_children(&key).await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(children)))
+}
+
+#[delete("/{key}/children/{child}")]
+pub async fn delete_child(
+    path: web::Path<(String, String)>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    let (key, child) = path.into_inner();
+    let count = app_data.service.apikey.delete_child(&key, &child).await?;
+    if count == 0 {
+        return Err(JsonError::new(
+            StatusCode::NOT_FOUND,
+            ErrorCode::ApikeyNotFound,
+            format!("Child key not found: {}", child),
+        ));
+    }
+    Ok(HttpResponse::Ok().json(Envelope::ok(models::Deleted { count })))
+}
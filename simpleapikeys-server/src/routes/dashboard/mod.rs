@@ -0,0 +1,384 @@
+//! A server-rendered admin dashboard, to manage API keys from a browser rather than with curl
+//!
+//! Browsers cannot send the admin token as a bearer token, so the dashboard takes it as the
+//! password of HTTP Basic authentication instead. As browsers send those credentials along with
+//! any request, forms also carry a token derived from the admin token, which other sites cannot
+//! know, so they cannot submit them on the admin's behalf.
+use std::time::Duration;
+
+use actix_web::client::Client;
+use actix_web::http::{header, StatusCode};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use lanther_common::Envelope;
+use mongodb::bson::{doc, Document};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use super::{check_quotas, AdminToken};
+use crate::error::{ApiError, FieldErrors, JsonError};
+use crate::models;
+
+pub(super) mod pages;
+
+/// How many API keys a page lists at most
+const PAGE_SIZE: usize = 100;
+
+/// How many of the most recent requests made with a key are shown
+const RECENT_REQUESTS: usize = 20;
+
+/// What the dashboard needs besides the services of the server
+pub struct Dashboard {
+    /// The proxy-server whose request log the requests made with a key are read from
+    pub proxy_url: Option<String>,
+}
+
+/// A request made with an API key, as logged by the proxy-server
+#[derive(Deserialize, Debug)]
+pub struct LoggedRequest {
+    pub method: String,
+    pub path: String,
+    pub environment: models::Environment,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AdminToken {
+    /// Check the request carries the admin token as the password of HTTP Basic authentication,
+    /// whatever the user name, returning the token forms must be submitted with
+    fn authenticate_basic(&self, req: &HttpRequest) -> Result<String, HttpResponse> {
+        let token = match self.0.as_deref() {
+            Some(token) => token,
+            None => return Err(unauthorized()),
+        };
+        let password = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Basic "))
+            .and_then(|credentials| base64::decode(credentials).ok())
+            .and_then(|credentials| String::from_utf8(credentials).ok())
+            .and_then(|credentials| {
+                credentials
+                    .split_once(':')
+                    .map(|(_, password)| password.to_string())
+            });
+        // Comparing digests keeps the time taken from telling how much of the token is right
+        match password {
+            Some(password)
+                if Sha256::digest(password.as_bytes()) == Sha256::digest(token.as_bytes()) =>
+            {
+                let csrf = Sha256::new()
+                    .chain(b"lanther-dashboard-csrf:")
+                    .chain(token.as_bytes())
+                    .finalize();
+                Ok(base64::encode_config(csrf, base64::URL_SAFE_NO_PAD))
+            }
+            _ => Err(unauthorized()),
+        }
+    }
+}
+
+/// Check a form was submitted from the dashboard itself
+fn check_csrf(expected: &str, given: &str) -> Result<(), HttpResponse> {
+    if Sha256::digest(expected.as_bytes()) == Sha256::digest(given.as_bytes()) {
+        Ok(())
+    } else {
+        let message = "The form was not submitted from the dashboard, reload it and try again";
+        Err(html(
+            StatusCode::FORBIDDEN,
+            pages::error(message, &FieldErrors::new()),
+        ))
+    }
+}
+
+fn unauthorized() -> HttpResponse {
+    let mut response = html(
+        StatusCode::UNAUTHORIZED,
+        pages::error(
+            "Sign in with the admin token as the password",
+            &FieldErrors::new(),
+        ),
+    );
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        header::HeaderValue::from_static("Basic realm=\"Lanther admin\", charset=\"UTF-8\""),
+    );
+    response
+}
+
+/// Respond with a page, which is never cached, framed, nor allowed to run scripts
+fn html(status: StatusCode, page: String) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .header(header::CACHE_CONTROL, "no-store")
+        .header(
+            header::CONTENT_SECURITY_POLICY,
+            "default-src 'none'; style-src 'unsafe-inline'; form-action 'self'; \
+             frame-ancestors 'none'",
+        )
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .body(page)
+}
+
+/// Respond with the page of why an operation on API keys failed
+fn error(err: ApiError) -> HttpResponse {
+    let err = JsonError::from(err);
+    let status = StatusCode::from_u16(err.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let errors: FieldErrors = err
+        .error
+        .details
+        .as_ref()
+        .and_then(|details| details.get("fields"))
+        .and_then(|fields| serde_json::from_value(fields.clone()).ok())
+        .unwrap_or_default();
+    html(status, pages::error(&err.error.message, &errors))
+}
+
+fn see_other(location: String) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .header(header::LOCATION, location)
+        .finish()
+}
+
+/// List the most recently created API keys, or those matching a search, best matches first
+async fn list(
+    app_data: &crate::AppState,
+    query: &str,
+    csrf: &str,
+    errors: &FieldErrors,
+) -> Result<String, ApiError> {
+    let service = &app_data.service.apikey;
+    let (apikeys, total) = if query.is_empty() {
+        let apikeys = service.get_latest(PAGE_SIZE as i64).await?;
+        (apikeys, service.count().await? as usize)
+    } else {
+        let search = models::search::Search {
+            query: query.to_string(),
+            page: 1,
+            per_page: PAGE_SIZE,
+        };
+        let found = service.search(&search).await?;
+        let apikeys = found.results.into_iter().map(|hit| hit.apikey).collect();
+        (apikeys, found.total as usize)
+    };
+    Ok(pages::key_list(&apikeys, total, query, csrf, errors))
+}
+
+#[derive(Deserialize)]
+pub struct Search {
+    #[serde(default)]
+    q: String,
+}
+
+/// List the most recently created API keys, or those matching a search given `?q=`
+#[get("")]
+pub async fn dashboard_apikeys(
+    req: HttpRequest,
+    search: web::Query<Search>,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> HttpResponse {
+    let csrf = match admin_token.authenticate_basic(&req) {
+        Ok(csrf) => csrf,
+        Err(response) => return response,
+    };
+    let query = search.q.trim();
+    match list(&app_data, query, &csrf, &FieldErrors::new()).await {
+        Ok(page) => html(StatusCode::OK, page),
+        Err(e) => error(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct NewKeyForm {
+    csrf: String,
+    #[serde(default)]
+    environment: String,
+    #[serde(default)]
+    owner: String,
+    #[serde(default)]
+    plan: String,
+    #[serde(default)]
+    principal: String,
+}
+
+/// Create an API key, validated as the body of `POST /apikeys` is, where empty fields are not
+/// given
+#[post("/apikeys")]
+pub async fn dashboard_create(
+    req: HttpRequest,
+    form: web::Form<NewKeyForm>,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> HttpResponse {
+    let csrf = match admin_token.authenticate_basic(&req) {
+        Ok(csrf) => csrf,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_csrf(&csrf, &form.csrf) {
+        return response;
+    }
+
+    let mut body = Map::new();
+    let fields = [
+        ("environment", &form.environment),
+        ("owner", &form.owner),
+        ("plan", &form.plan),
+        ("principal", &form.principal),
+    ];
+    for (field, value) in fields.iter() {
+        if !value.trim().is_empty() {
+            body.insert(field.to_string(), Value::String(value.trim().to_string()));
+        }
+    }
+    let created = match models::NewApiKey::from_json(&Value::Object(body)) {
+        Ok(apikey) => super::create(&app_data, apikey).await,
+        Err(errors) => Err(ApiError::ValidationError(errors)),
+    };
+    match created {
+        Ok(apikey) => see_other(format!("/admin/apikeys/{}", apikey.key)),
+        // Invalid fields are shown along with the form, so they can be corrected
+        Err(ApiError::ValidationError(errors)) => match list(&app_data, "", &csrf, &errors).await {
+            Ok(page) => html(StatusCode::UNPROCESSABLE_ENTITY, page),
+            Err(e) => error(e),
+        },
+        Err(e) => error(e),
+    }
+}
+
+/// Read the most recent requests made with an API key from the request log of the proxy-server
+async fn recent_requests(proxy_url: Option<&str>, key: &str) -> Result<Vec<LoggedRequest>, String> {
+    let proxy_url = proxy_url.ok_or("no proxy-server is configured as admin.proxy_url")?;
+    let url = format!(
+        "{}/requests/{}?order=newest&limit={}",
+        proxy_url.trim_end_matches('/'),
+        key,
+        RECENT_REQUESTS
+    );
+    let mut res = Client::default()
+        .get(url.as_str())
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !res.status().is_success() {
+        return Err(format!("the proxy-server responded with {}", res.status()));
+    }
+    let envelope: Envelope<Vec<LoggedRequest>> = res.json().await.map_err(|e| e.to_string())?;
+    Ok(envelope.payload)
+}
+
+/// Show an API key, its child keys and the requests recently made with it
+#[get("/apikeys/{key}")]
+pub async fn dashboard_apikey(
+    req: HttpRequest,
+    key: web::Path<String>,
+    admin_token: web::Data<AdminToken>,
+    dashboard: web::Data<Dashboard>,
+    app_data: web::Data<crate::AppState>,
+) -> HttpResponse {
+    let csrf = match admin_token.authenticate_basic(&req) {
+        Ok(csrf) => csrf,
+        Err(response) => return response,
+    };
+    let apikey = match app_data.service.apikey.get_by_key(&key).await {
+        Ok(apikey) => apikey,
+        Err(e) => return error(e),
+    };
+    let children = match app_data.service.apikey.get_children(&key).await {
+        Ok(children) => children,
+        Err(e) => return error(e),
+    };
+    let requests = recent_requests(dashboard.proxy_url.as_deref(), &key).await;
+    if let Err(reason) = &requests {
+        log::warn!(
+            "Could not read the requests made with {}: {}",
+            apikey.key,
+            reason
+        );
+    }
+    let page = pages::key_detail(&apikey, &children, &requests, &csrf);
+    html(StatusCode::OK, page)
+}
+
+#[derive(Deserialize)]
+pub struct DisabledForm {
+    csrf: String,
+    disabled: bool,
+}
+
+/// Disable or enable an API key
+#[post("/apikeys/{key}/disabled")]
+pub async fn dashboard_disable(
+    req: HttpRequest,
+    key: web::Path<String>,
+    form: web::Form<DisabledForm>,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> HttpResponse {
+    let csrf = match admin_token.authenticate_basic(&req) {
+        Ok(csrf) => csrf,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_csrf(&csrf, &form.csrf) {
+        return response;
+    }
+    let apikey = match app_data.service.apikey.get_by_key(&key).await {
+        Ok(apikey) => apikey,
+        Err(e) => return error(e),
+    };
+    let set = doc! { "disabled": form.disabled };
+    if let Err(e) = check_quotas(&app_data, &key, &set, &Document::new()).await {
+        return error(e);
+    }
+    let update = models::UpdateApiKey {
+        key: apikey.key,
+        disabled: form.disabled,
+    };
+    match app_data.service.apikey.update(update, None).await {
+        Ok(apikey) => see_other(format!("/admin/apikeys/{}", apikey.key)),
+        Err(e) => error(e),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RotateForm {
+    csrf: String,
+}
+
+/// Rotate an API key, showing the key that replaces it
+#[post("/apikeys/{key}/rotate")]
+pub async fn dashboard_rotate(
+    req: HttpRequest,
+    key: web::Path<String>,
+    form: web::Form<RotateForm>,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> HttpResponse {
+    let csrf = match admin_token.authenticate_basic(&req) {
+        Ok(csrf) => csrf,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_csrf(&csrf, &form.csrf) {
+        return response;
+    }
+    let apikey = match app_data.service.apikey.get_by_key(&key).await {
+        Ok(apikey) => apikey,
+        Err(e) => return error(e),
+    };
+    let replacement = match app_data.service.apikey.rotate(&apikey).await {
+        Ok(replacement) => replacement,
+        Err(e) => return error(e),
+    };
+    let record = models::AuditRecord {
+        action: "apikey.rotated",
+        key: Some(apikey.key.to_hyphenated().to_string()),
+        actor: "admin",
+        details: doc! { "replaced_by": replacement.key.to_hyphenated().to_string() },
+    };
+    if let Err(e) = app_data.service.audit.record(record).await {
+        log::error!("Could not audit the rotation of {}: {}", apikey.key, e);
+    }
+    see_other(format!("/admin/apikeys/{}", replacement.key))
+}
@@ -0,0 +1,285 @@
+//! The HTML of the admin dashboard, written out by hand as there are only a few pages to it
+use chrono::{DateTime, Utc};
+
+use super::LoggedRequest;
+use crate::error::FieldErrors;
+use crate::models::ApiKey;
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 72em; color: #222; }
+table { border-collapse: collapse; width: 100%; margin-bottom: 1.5em; }
+th, td { text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #ddd; }
+th { background: #f4f4f4; }
+code { font-size: 0.9em; }
+form.inline { display: inline; }
+fieldset { margin-bottom: 1.5em; border: 1px solid #ddd; }
+.errors { color: #a00; }
+.state-active { color: #070; }
+.state-disabled, .state-expired, .state-revoked { color: #a00; }
+";
+
+/// Escape text to be written into HTML, whether as content or as an attribute value
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn layout(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title} - Lanther admin</title>\n<style>{style}</style>\n</head>\n<body>\n\
+         <p><a href=\"/admin\">API keys</a></p>\n<h1>{title}</h1>\n{body}</body>\n</html>\n",
+        title = escape(title),
+        style = STYLE,
+        body = body,
+    )
+}
+
+fn optional(value: Option<&str>) -> String {
+    value.map(escape).unwrap_or_default()
+}
+
+fn timestamp(at: &DateTime<Utc>) -> String {
+    at.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+/// The state a key is in, as counted by the stats of API keys
+fn state(apikey: &ApiKey) -> &'static str {
+    if apikey.revoked_at.is_some() {
+        "revoked"
+    } else if apikey.disabled {
+        "disabled"
+    } else if apikey.expires_at.is_some_and(|at| at <= Utc::now()) {
+        "expired"
+    } else {
+        "active"
+    }
+}
+
+fn key_link(apikey: &ApiKey) -> String {
+    format!(
+        "<a href=\"/admin/apikeys/{key}\"><code>{key}</code></a>",
+        key = apikey.key
+    )
+}
+
+fn key_rows(apikeys: &[ApiKey]) -> String {
+    let mut rows = String::new();
+    for apikey in apikeys {
+        rows.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>\
+             <td class=\"state-{state}\">{state}</td><td>{}</td></tr>\n",
+            key_link(apikey),
+            apikey.environment.as_str(),
+            optional(apikey.owner.as_deref()),
+            optional(apikey.name.as_deref()),
+            optional(apikey.plan.as_deref()),
+            optional(apikey.principal.as_deref()),
+            timestamp(&apikey.created_at),
+            state = state(apikey),
+        ));
+    }
+    format!(
+        "<table>\n<tr><th>Key</th><th>Environment</th><th>Owner</th><th>Name</th><th>Plan</th>\
+         <th>Principal</th><th>State</th><th>Created</th></tr>\n{}</table>\n",
+        rows
+    )
+}
+
+fn field_errors(errors: &FieldErrors) -> String {
+    if errors.is_empty() {
+        return String::new();
+    }
+    let mut items = String::new();
+    for (field, message) in errors {
+        items.push_str(&format!(
+            "<li><code>{}</code> {}</li>\n",
+            escape(field),
+            escape(message)
+        ));
+    }
+    format!("<ul class=\"errors\">\n{}</ul>\n", items)
+}
+
+/// The API keys matching a search, or every one of them, with a form to create another
+pub fn key_list(
+    apikeys: &[ApiKey],
+    total: usize,
+    query: &str,
+    csrf: &str,
+    errors: &FieldErrors,
+) -> String {
+    let search = format!(
+        "<form method=\"get\" action=\"/admin\">\n\
+         <input type=\"search\" name=\"q\" value=\"{}\" \
+         placeholder=\"Key, owner, name or label\" size=\"48\">\n\
+         <button type=\"submit\">Search</button>\n</form>\n\
+         <p>Showing {} of {} matching API keys.</p>\n",
+        escape(query),
+        apikeys.len(),
+        total,
+    );
+    let create = format!(
+        "<form method=\"post\" action=\"/admin/apikeys\">\n<fieldset>\n\
+         <legend>Create an API key</legend>\n{}\
+         <input type=\"hidden\" name=\"csrf\" value=\"{}\">\n\
+         <label>Environment <select name=\"environment\">\
+         <option value=\"live\">live</option><option value=\"test\">test</option>\
+         </select></label>\n\
+         <label>Owner <input name=\"owner\"></label>\n\
+         <label>Plan <input name=\"plan\"></label>\n\
+         <label>Principal <input name=\"principal\"></label>\n\
+         <button type=\"submit\">Create</button>\n</fieldset>\n</form>\n",
+        field_errors(errors),
+        escape(csrf),
+    );
+    layout("API keys", &(search + &create + &key_rows(apikeys)))
+}
+
+fn action(key: &ApiKey, path: &str, csrf: &str, fields: &str, label: &str) -> String {
+    format!(
+        "<form class=\"inline\" method=\"post\" action=\"/admin/apikeys/{}/{}\">\
+         <input type=\"hidden\" name=\"csrf\" value=\"{}\">{}\
+         <button type=\"submit\">{}</button></form>\n",
+        key.key,
+        path,
+        escape(csrf),
+        fields,
+        label,
+    )
+}
+
+/// An API key with its child keys and the requests recently made with it, and the actions that
+/// can be taken on it
+pub fn key_detail(
+    apikey: &ApiKey,
+    children: &[ApiKey],
+    requests: &Result<Vec<LoggedRequest>, String>,
+    csrf: &str,
+) -> String {
+    let mut body = String::new();
+
+    let labels: Vec<String> = apikey
+        .labels
+        .iter()
+        .map(|(label, value)| format!("<code>{}</code>={}", escape(label), escape(value)))
+        .collect();
+    let scopes: Vec<String> = apikey.scopes.iter().map(|scope| escape(scope)).collect();
+    let parent = match apikey.parent {
+        Some(parent) => format!(
+            "<a href=\"/admin/apikeys/{parent}\"><code>{parent}</code></a>",
+            parent = parent
+        ),
+        None => String::new(),
+    };
+    let revoked = match (&apikey.revoked_at, &apikey.revocation_reason) {
+        (Some(at), reason) => format!("{} ({})", timestamp(at), optional(reason.as_deref())),
+        (None, _) => String::new(),
+    };
+    let rows = [
+        ("Environment", apikey.environment.as_str().to_string()),
+        ("State", state(apikey).to_string()),
+        ("Owner", optional(apikey.owner.as_deref())),
+        ("Name", optional(apikey.name.as_deref())),
+        ("Plan", optional(apikey.plan.as_deref())),
+        ("Principal", optional(apikey.principal.as_deref())),
+        ("Parent", parent),
+        ("Labels", labels.join(", ")),
+        ("Scopes", scopes.join(", ")),
+        (
+            "Expires",
+            apikey
+                .expires_at
+                .as_ref()
+                .map(timestamp)
+                .unwrap_or_default(),
+        ),
+        ("Revoked", revoked),
+        ("Version", apikey.version.to_string()),
+        ("Created", timestamp(&apikey.created_at)),
+        ("Updated", timestamp(&apikey.updated_at)),
+    ];
+    body.push_str("<table>\n");
+    for (field, value) in rows.iter() {
+        body.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", field, value));
+    }
+    body.push_str("</table>\n");
+
+    if apikey.revoked_at.is_none() {
+        body.push_str("<p>\n");
+        body.push_str(&if apikey.disabled {
+            action(
+                apikey,
+                "disabled",
+                csrf,
+                "<input type=\"hidden\" name=\"disabled\" value=\"false\">",
+                "Enable",
+            )
+        } else {
+            action(
+                apikey,
+                "disabled",
+                csrf,
+                "<input type=\"hidden\" name=\"disabled\" value=\"true\">",
+                "Disable",
+            )
+        });
+        body.push_str(&action(
+            apikey,
+            "rotate",
+            csrf,
+            "",
+            "Rotate, revoking this key and its child keys",
+        ));
+        body.push_str("</p>\n");
+    }
+
+    body.push_str("<h2>Child keys</h2>\n");
+    if children.is_empty() {
+        body.push_str("<p>None.</p>\n");
+    } else {
+        body.push_str(&key_rows(children));
+    }
+
+    body.push_str("<h2>Recent requests</h2>\n");
+    match requests {
+        Err(reason) => body.push_str(&format!(
+            "<p class=\"errors\">The request log is unavailable: {}</p>\n",
+            escape(reason)
+        )),
+        Ok(requests) if requests.is_empty() => body.push_str("<p>None.</p>\n"),
+        Ok(requests) => {
+            body.push_str(
+                "<table>\n<tr><th>At</th><th>Method</th><th>Path</th><th>Environment</th></tr>\n",
+            );
+            for request in requests {
+                body.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td><td><code>{}</code></td><td>{}</td></tr>\n",
+                    timestamp(&request.created_at),
+                    escape(&request.method),
+                    escape(&request.path),
+                    request.environment.as_str(),
+                ));
+            }
+            body.push_str("</table>\n");
+        }
+    }
+
+    layout(&format!("API key {}", apikey.key), &body)
+}
+
+/// A page telling why a request failed
+pub fn error(message: &str, errors: &FieldErrors) -> String {
+    let body = format!("<p>{}</p>\n{}", escape(message), field_errors(errors));
+    layout("Something went wrong", &body)
+}
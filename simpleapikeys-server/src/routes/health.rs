@@ -0,0 +1,28 @@
+//! Liveness and readiness probes
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use actix_web::{get, web, HttpResponse};
+use lanther_common::{health, Envelope};
+use lanther_store::Database;
+use serde_json::json;
+
+/// How long a dependency may take to answer before it is considered down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The server is alive as long as it can answer, regardless of its dependencies
+#[get("/healthz")]
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(Envelope::ok(json!({ "status": "ok" })))
+}
+
+/// The server is ready to take requests when Mongo DB answers a ping
+#[get("/readyz")]
+pub async fn readyz(db: web::Data<Database>) -> HttpResponse {
+    let mut checks = BTreeMap::new();
+    checks.insert(
+        "mongo".to_string(),
+        health::check(CHECK_TIMEOUT, db.ping()).await,
+    );
+    health::respond(checks)
+}
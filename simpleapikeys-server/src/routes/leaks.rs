@@ -0,0 +1,84 @@
+use actix_web::{post, web, HttpRequest, HttpResponse};
+use lanther_common::Envelope;
+use mongodb::bson::doc;
+use serde_json::json;
+
+use crate::error::{ApiError, JsonError};
+use crate::models;
+
+/// The reason recorded for API keys revoked after being reported
+const LEAKED: &str = "leaked";
+
+/// The token leak reports must be authenticated with, if leak reporting is enabled
+pub struct ReporterToken(pub Option<String>);
+
+/// Check the request carries the reporter token as a bearer token
+fn authenticate(req: &HttpRequest, token: &ReporterToken) -> Result<(), ApiError> {
+    match token.0.as_deref() {
+        Some(token) if super::has_bearer_token(req, token) => Ok(()),
+        _ => Err(ApiError::ReporterUnauthorized),
+    }
+}
+
+/// Revoke every reported candidate that is an API key, and tell its owner
+///
+/// Candidates already revoked are reported as matched, but are not revoked, audited or notified
+/// about again, so a report can be safely retried.
+#[post("/leaks")]
+pub async fn report_leaks(
+    req: HttpRequest,
+    body: web::Bytes,
+    token: web::Data<ReporterToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    authenticate(&req, &token)?;
+    let report = serde_json::from_slice(&body).map_err(ApiError::from)?;
+    let report = models::LeakReport::from_json(&report).map_err(ApiError::ValidationError)?;
+
+    let mut result = models::LeakReportResult::default();
+    for candidate in report.keys {
+        match app_data.service.apikey.get_by_key(&candidate).await {
+            Ok(_) => {}
+            Err(ApiError::MongoDBEmptyResult) => {
+                result.unmatched.push(candidate);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        let revoked = app_data.service.apikey.revoke(&candidate, LEAKED).await?;
+        if let Some(apikey) = &revoked {
+            log::warn!("Revoked leaked API key {}", apikey.key);
+            let mut details = doc! {
+                "reason": LEAKED,
+            };
+            if let Some(source) = &report.source {
+                details.insert("source", source.clone());
+            }
+            let record = models::AuditRecord {
+                action: "apikey.revoked",
+                key: Some(candidate.clone()),
+                actor: "leak_reporter",
+                details,
+            };
+            // The key is revoked either way, and the rest of the batch must not wait on the log
+            if let Err(e) = app_data.service.audit.record(record).await {
+                log::error!("Could not audit the revocation of {}: {}", candidate, e);
+            }
+            app_data.service.notifier.notify(json!({
+                "event": "apikey.leaked",
+                "key": apikey.key,
+                "name": apikey.name,
+                "labels": apikey.labels,
+                "source": report.source,
+                "revoked_at": apikey.revoked_at,
+            }));
+        }
+        result.matched.push(models::LeakedKey {
+            key: candidate,
+            revoked: revoked.is_some(),
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(Envelope::ok(result)))
+}
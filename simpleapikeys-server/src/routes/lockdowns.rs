@@ -0,0 +1,125 @@
+use actix_web::{delete, get, put, web, HttpRequest, HttpResponse};
+use lanther_common::Envelope;
+use mongodb::bson::{doc, Bson, Document};
+
+use super::AdminToken;
+use crate::error::{ApiError, JsonError};
+use crate::models;
+
+/// What a lockdown locks down, as recorded in the audit log
+fn audit_details(lockdown: &models::Lockdown) -> Document {
+    let allow: Vec<Bson> = lockdown
+        .allow
+        .iter()
+        .map(|owner| Bson::String(owner.clone()))
+        .collect();
+    let mut details = doc! {
+        "reason": lockdown.reason.clone(),
+        "allow": allow,
+    };
+    if let Some(owner) = &lockdown.owner {
+        details.insert("owner", owner.clone());
+    }
+    details
+}
+
+/// Record a lockdown being put in place or lifted, which it is either way
+async fn audit(app_data: &crate::AppState, action: &'static str, lockdown: &models::Lockdown) {
+    let record = models::AuditRecord {
+        action,
+        key: None,
+        actor: "admin",
+        details: audit_details(lockdown),
+    };
+    if let Err(e) = app_data.service.audit.record(record).await {
+        log::error!("Could not audit {} of {:?}: {}", action, lockdown.owner, e);
+    }
+}
+
+async fn put(
+    req: &HttpRequest,
+    owner: Option<&str>,
+    body: &[u8],
+    admin_token: &AdminToken,
+    app_data: &crate::AppState,
+) -> Result<HttpResponse, JsonError> {
+    admin_token.authenticate(req)?;
+    let lockdown = serde_json::from_slice(body).map_err(ApiError::from)?;
+    let lockdown =
+        models::LockdownInput::from_json(&lockdown, owner).map_err(ApiError::ValidationError)?;
+    let lockdown = app_data.service.lockdown.put(owner, lockdown).await?;
+    log::warn!(
+        "Locked down {}: {}",
+        owner.unwrap_or("everyone"),
+        lockdown.reason
+    );
+    audit(app_data, "lockdown.started", &lockdown).await;
+    Ok(HttpResponse::Ok().json(Envelope::ok(lockdown)))
+}
+
+async fn lift(
+    req: &HttpRequest,
+    owner: Option<&str>,
+    admin_token: &AdminToken,
+    app_data: &crate::AppState,
+) -> Result<HttpResponse, JsonError> {
+    admin_token.authenticate(req)?;
+    let lockdown = app_data.service.lockdown.lift(owner).await?;
+    log::warn!("Lifted the lockdown of {}", owner.unwrap_or("everyone"));
+    audit(app_data, "lockdown.lifted", &lockdown).await;
+    Ok(HttpResponse::Ok().json(Envelope::ok(models::Deleted { count: 1 })))
+}
+
+/// List every lockdown in place
+#[get("")]
+pub async fn get_lockdowns(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    admin_token.authenticate(&req)?;
+    let lockdowns = app_data.service.lockdown.get_all().await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(lockdowns)))
+}
+
+/// Lock down every API key, except those of the owners it allows
+#[put("/global")]
+pub async fn put_lockdown(
+    req: HttpRequest,
+    body: web::Bytes,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    put(&req, None, &body, &admin_token, &app_data).await
+}
+
+#[delete("/global")]
+pub async fn lift_lockdown(
+    req: HttpRequest,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    lift(&req, None, &admin_token, &app_data).await
+}
+
+/// Lock down every API key of an owner
+#[put("/owners/{owner}")]
+pub async fn put_owner_lockdown(
+    req: HttpRequest,
+    owner: web::Path<String>,
+    body: web::Bytes,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    put(&req, Some(&owner), &body, &admin_token, &app_data).await
+}
+
+#[delete("/owners/{owner}")]
+pub async fn lift_owner_lockdown(
+    req: HttpRequest,
+    owner: web::Path<String>,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    lift(&req, Some(&owner), &admin_token, &app_data).await
+}
@@ -0,0 +1,37 @@
+use actix_web::http::StatusCode;
+use actix_web::{get, web, HttpResponse};
+
+use crate::error::{ErrorCode, JsonError};
+use crate::metrics::Metrics;
+use crate::services::ApiKeyService;
+
+/// Expose metrics in the Prometheus text format
+///
+/// API keys are counted on every scrape, so the counts are current without keeping track of
+/// every write. A failure to count them is logged, and the last counts are reported instead.
+#[get("/metrics")]
+pub async fn get_metrics(
+    metrics: web::Data<Metrics>,
+    apikeys: web::Data<ApiKeyService>,
+) -> Result<HttpResponse, JsonError> {
+    match apikeys.count_by_state().await {
+        Ok(counts) => {
+            for state in counts {
+                let name = state.name.unwrap_or_default();
+                metrics.apikeys.with_label_values(&[&name]).set(state.count);
+            }
+        }
+        Err(e) => log::warn!("Could not count API keys: {}", e),
+    }
+
+    let body = metrics.render().map_err(|e| {
+        JsonError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::InternalError,
+            e.to_string(),
+        )
+    })?;
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(body))
+}
@@ -1,111 +1,422 @@
-use super::error::JsonError;
+use super::error::{ApiError, ErrorCode, JsonError};
 use super::models;
-use actix_web::{delete, get, post, put, web, HttpResponse};
-use serde_json::json;
+use actix_web::http::header::{self, ETag, EntityTag, IfMatch};
+use actix_web::http::StatusCode;
+use actix_web::{delete, get, patch, post, put, web, HttpMessage, HttpRequest, HttpResponse};
+use lanther_common::Envelope;
+use mongodb::bson::{doc, Document};
+use sha2::{Digest, Sha256};
+
+mod children;
+mod dashboard;
+mod health;
+mod leaks;
+mod lockdowns;
+mod metrics;
+mod plans;
+mod principals;
+mod search;
+mod signups;
+mod stats;
+mod usage;
+pub use children::{create_child, delete_child, get_children};
+pub use dashboard::{
+    dashboard_apikey, dashboard_apikeys, dashboard_create, dashboard_disable, dashboard_rotate,
+    Dashboard,
+};
+pub use health::{healthz, readyz};
+pub use leaks::{report_leaks, ReporterToken};
+pub use lockdowns::{
+    get_lockdowns, lift_lockdown, lift_owner_lockdown, put_lockdown, put_owner_lockdown,
+};
+pub use metrics::get_metrics;
+pub use plans::{create_plan, delete_plan, get_plan, get_plans, replace_plan};
+pub use principals::{
+    create_principal, delete_principal, get_principal, get_principals, patch_principal,
+};
+pub use search::search_apikeys;
+pub use signups::{confirm_signup, create_signup, verify_signup, Signups};
+pub use stats::get_stats;
+pub use usage::{get_owner_usage, get_usage};
+
+/// The token the admin authenticates with, if there is an admin at all
+pub struct AdminToken(pub Option<String>);
+
+impl AdminToken {
+    /// Check the request carries the admin token as a bearer token
+    fn authenticate(&self, req: &HttpRequest) -> Result<(), ApiError> {
+        match self.0.as_deref() {
+            Some(token) if has_bearer_token(req, token) => Ok(()),
+            _ => Err(ApiError::AdminUnauthorized),
+        }
+    }
+}
+
+/// Who may create API keys with `POST /apikeys`, and manage them once created
+pub struct KeyCreation {
+    /// Whether anyone may, rather than only the admin
+    pub anonymous: bool,
+}
+
+impl KeyCreation {
+    /// Check the request may create or manage API keys, which takes the admin token unless
+    /// anyone may
+    ///
+    /// Keys are not tied to whoever created them, so once only the admin may create keys, only
+    /// the admin may read, search or change them either.
+    fn authorize(&self, req: &HttpRequest, admin_token: &AdminToken) -> Result<(), ApiError> {
+        if self.anonymous {
+            return Ok(());
+        }
+        admin_token.authenticate(req)
+    }
+
+    /// Check the request may give an API key an owner, or change it, which always takes the admin
+    /// token
+    ///
+    /// Lockdowns are put in place by owner, so whoever could pick the owner of a key could move it
+    /// out from under the lockdown of its owner, or into an owner allowed through a global one.
+    fn authorize_owner(&self, req: &HttpRequest, admin_token: &AdminToken) -> Result<(), ApiError> {
+        admin_token.authenticate(req)
+    }
+}
+
+/// Whether the request carries a token as a bearer token
+fn has_bearer_token(req: &HttpRequest, token: &str) -> bool {
+    let given = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    // Comparing digests keeps the time taken from telling how much of the token is right
+    given.is_some_and(|given| Sha256::digest(given.as_bytes()) == Sha256::digest(token.as_bytes()))
+}
+
+/// Build the ETag of an API key from its version
+fn etag(apikey: &models::ApiKey) -> ETag {
+    ETag(EntityTag::strong(apikey.version.to_string()))
+}
+
+/// Versions a write is conditioned on, as given by the If-Match header
+///
+/// Returns `None` when the write is unconditional. A malformed header yields no versions, so
+/// the write can never match.
+fn expected_versions(req: &HttpRequest) -> Option<Vec<i64>> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return None;
+    }
+    match req.get_header::<IfMatch>() {
+        Some(IfMatch::Any) => None,
+        Some(IfMatch::Items(tags)) => Some(
+            tags.iter()
+                .filter(|tag| !tag.weak)
+                .filter_map(|tag| tag.tag().parse().ok())
+                .collect(),
+        ),
+        None => Some(Vec::new()),
+    }
+}
 
 #[get("")]
-async fn get_apikeys(app_data: web::Data<crate::AppState>) -> Result<HttpResponse, JsonError> {
+async fn get_apikeys(
+    req: HttpRequest,
+    creation: web::Data<KeyCreation>,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    creation.authorize(&req, &admin_token)?;
     let result = app_data.service.apikey.get_all().await;
     match result {
-        Ok(keys) => Ok(HttpResponse::Ok().json(json!({
-            "status": 200,
-            "sucess": true,
-            "payload": keys,
-        }))),
+        Ok(keys) => Ok(HttpResponse::Ok().json(Envelope::ok(keys))),
         Err(e) => Err(e.into()),
     }
 }
 
 #[get("/{key}")]
 async fn get_apikey(
+    req: HttpRequest,
+    creation: web::Data<KeyCreation>,
+    admin_token: web::Data<AdminToken>,
     key: web::Path<String>,
     app_data: web::Data<crate::AppState>,
 ) -> Result<HttpResponse, JsonError> {
+    creation.authorize(&req, &admin_token)?;
     let result = app_data.service.apikey.get_by_key(&key).await;
     match result {
-        Ok(apikey) => Ok(HttpResponse::Ok().json(json!({
-            "status": 200,
-            "success": true,
-            "payload": apikey,
-        }))),
+        Ok(apikey) => Ok(HttpResponse::Ok()
+            .set(etag(&apikey))
+            .json(Envelope::ok(apikey))),
         Err(e) => Err(e.into()),
     }
 }
 
-#[post("")]
-async fn create_apikey(app_data: web::Data<crate::AppState>) -> Result<HttpResponse, JsonError> {
-    let apikey = models::NewApiKey::new();
-    let result = app_data.service.apikey.create(apikey).await;
-    match result {
-        Ok(inserted) => {
-            let id = inserted.inserted_id.as_object_id().ok_or(JsonError {
-                msg: format!("Insert failed"),
-                status: 500,
-                success: false,
-            })?;
-            let apikey = app_data.service.apikey.get_by_id(&id).await;
-            match apikey {
-                Ok(key) => Ok(HttpResponse::Ok().json(json!({
-                    "status": 200,
-                    "success": true,
-                    "payload": key,
-                }))),
-                Err(e) => Err(e.into()),
-            }
+/// Resolve the verification of an API key given its plan, its principal and, for child keys, its
+/// parent
+async fn resolve(
+    app_data: &crate::AppState,
+    apikey: &models::ApiKey,
+) -> Result<models::Verification, ApiError> {
+    let verification = verify(app_data, apikey).await?;
+    let parent = match apikey.parent {
+        Some(parent) => parent.to_hyphenated().to_string(),
+        None => return Ok(verification),
+    };
+    match app_data.service.apikey.get_by_key(&parent).await {
+        Ok(parent) => Ok(verification.bounded_by(&verify(app_data, &parent).await?)),
+        // Deleting a parent deletes its children, so this is only a transient state
+        Err(ApiError::MongoDBEmptyResult) => Ok(models::Verification {
+            disabled: true,
+            ..verification
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// Verify an API key on its own, which is disabled while the principal it acts for is, and locked
+/// down along with its owner
+async fn verify(
+    app_data: &crate::AppState,
+    apikey: &models::ApiKey,
+) -> Result<models::Verification, ApiError> {
+    let mut verification = apikey.verify(plan_of(app_data, apikey).await?.as_ref());
+    verification.locked_down = app_data
+        .service
+        .lockdown
+        .is_locked_down(apikey.owner.as_deref())
+        .await?;
+    let name = match apikey.principal.as_deref() {
+        Some(name) => name,
+        None => return Ok(verification),
+    };
+    match app_data.service.principal.get_by_name(name).await {
+        Ok(principal) => {
+            verification.disabled |= principal.disabled;
+            verification.principal = Some(models::VerifiedPrincipal {
+                name: principal.name,
+                kind: principal.kind,
+            });
         }
-        Err(e) => Err(e.into()),
+        // Principals cannot be deleted while keys act for them, so this is only a transient state
+        Err(ApiError::PrincipalNotFound(_)) => {
+            log::warn!("API key {} acts for missing principal {}", apikey.key, name);
+        }
+        Err(e) => return Err(e),
     }
+    Ok(verification)
+}
+
+async fn plan_of(
+    app_data: &crate::AppState,
+    apikey: &models::ApiKey,
+) -> Result<Option<models::Plan>, ApiError> {
+    let name = match apikey.plan.as_deref() {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    match app_data.service.plan.get_by_name(name).await {
+        Ok(plan) => Ok(Some(plan)),
+        // Keys are checked to be moved to existing plans, so this is only a transient state
+        Err(ApiError::PlanNotFound(_)) => {
+            log::warn!("API key {} is on missing plan {}", apikey.key, name);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Check a write leaves room for an API key under the quotas of its owner and plan
+///
+/// Only writes that make a key active, or move an active key to another owner or plan, take up a
+/// slot, so only those are checked. Child keys take theirs on the plan of their parent, and move
+/// to another plan along with it.
+async fn check_quotas(
+    app_data: &crate::AppState,
+    key: &str,
+    set: &Document,
+    unset: &Document,
+) -> Result<(), ApiError> {
+    let service = &app_data.service.apikey;
+    let apikey = service.get_by_key(key).await?;
+    let before = models::QuotaState::of(&apikey);
+    let after = models::QuotaState::after(&apikey, set, unset);
+    if !after.active {
+        return Ok(());
+    }
+
+    if let Some(owner) = after.owner.as_deref() {
+        if !before.active || before.owner != after.owner {
+            service.check_owner_quota(owner, Some(key)).await?;
+        }
+    }
+    let (plan, moved) = match apikey.parent {
+        Some(parent) => {
+            let parent = service
+                .get_by_key(&parent.to_hyphenated().to_string())
+                .await?;
+            (plan_of(app_data, &parent).await?, false)
+        }
+        None if before.plan == after.plan => (plan_of(app_data, &apikey).await?, false),
+        None => (
+            plans::check_plan_exists(app_data, after.plan.as_deref()).await?,
+            true,
+        ),
+    };
+    if before.active && !moved {
+        return Ok(());
+    }
+    if let Some(plan) = plan {
+        let children = if moved {
+            service.count_active_children(key).await?
+        } else {
+            0
+        };
+        service
+            .check_plan_quota(&plan, Some(key), 1 + children)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Resolve the plan, principal, scopes and limits of an API key, for the proxy to authorize
+/// requests with
+///
+/// Lockdowns are read on every verification, so they stop keys from being used as soon as they
+/// are put in place.
+#[get("/{key}/verify")]
+async fn verify_apikey(
+    key: web::Path<String>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    let apikey = app_data.service.apikey.get_by_key(&key).await?;
+    let verification = resolve(&app_data, &apikey).await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(verification)))
+}
+
+/// Create an API key, live unless the body asks for a test one with `{"environment":"test"}`
+///
+/// The body may also give the `owner` of the key and the `plan` it is on, both of which may limit
+/// how many active keys there are, and the `principal` it acts for. Only the admin may give the
+/// `owner`. When anonymous creation is disabled, only the admin may create API keys at all, and
+/// everyone else signs up for one instead.
+#[post("")]
+async fn create_apikey(
+    req: HttpRequest,
+    body: web::Bytes,
+    creation: web::Data<KeyCreation>,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    creation.authorize(&req, &admin_token)?;
+    let apikey = if body.is_empty() {
+        models::NewApiKey::new(models::Environment::default())
+    } else {
+        let body = serde_json::from_slice(&body).map_err(ApiError::from)?;
+        models::NewApiKey::from_json(&body).map_err(ApiError::ValidationError)?
+    };
+    if apikey.owner.is_some() {
+        creation.authorize_owner(&req, &admin_token)?;
+    }
+    let apikey = create(&app_data, apikey).await?;
+    Ok(HttpResponse::Ok()
+        .set(etag(&apikey))
+        .json(Envelope::ok(apikey)))
+}
+
+/// Create an API key once the plan and principal it names are checked to exist
+async fn create(
+    app_data: &crate::AppState,
+    apikey: models::NewApiKey,
+) -> Result<models::ApiKey, ApiError> {
+    let plan = plans::check_plan_exists(app_data, apikey.plan.as_deref()).await?;
+    principals::check_principal_exists(app_data, apikey.principal.as_deref()).await?;
+    let inserted = app_data
+        .service
+        .apikey
+        .create(apikey, plan.as_ref())
+        .await?;
+    app_data.service.apikey.get_inserted(&inserted).await
 }
 
 #[put("")]
 async fn update_apikey(
+    req: HttpRequest,
+    creation: web::Data<KeyCreation>,
+    admin_token: web::Data<AdminToken>,
     apikey: web::Json<models::UpdateApiKey>,
     app_data: web::Data<crate::AppState>,
 ) -> Result<HttpResponse, JsonError> {
-    let apikey = apikey.into_inner();
-    let result = app_data.service.apikey.update(apikey).await;
+    creation.authorize(&req, &admin_token)?;
+    let set = doc! { "disabled": apikey.disabled };
+    check_quotas(
+        &app_data,
+        &apikey.key.to_hyphenated().to_string(),
+        &set,
+        &Document::new(),
+    )
+    .await?;
+    let versions = expected_versions(&req);
+    let result = app_data
+        .service
+        .apikey
+        .update(apikey.into_inner(), versions.as_deref())
+        .await;
     log::debug!("Result: {:?}", result);
     match result {
-        Ok(_) => {
-            // Result does not return an upserted_id, so we play nice by fetching by key
-            // And returning the changed object
-            let key = apikey.key.to_hyphenated().to_string();
-            let apikey = app_data.service.apikey.get_by_key(&key).await;
-            match apikey {
-                Ok(key) => Ok(HttpResponse::Ok().json(json!({
-                    "status": 200,
-                    "success": true,
-                    "payload": key,
-                }))),
-                Err(e) => Err(e.into()),
-            }
-        }
+        Ok(key) => Ok(HttpResponse::Ok().set(etag(&key)).json(Envelope::ok(key))),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[patch("/{key}")]
+async fn patch_apikey(
+    req: HttpRequest,
+    creation: web::Data<KeyCreation>,
+    admin_token: web::Data<AdminToken>,
+    key: web::Path<String>,
+    body: web::Bytes,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    creation.authorize(&req, &admin_token)?;
+    let patch = serde_json::from_slice(&body).map_err(ApiError::from)?;
+    let patch = models::ApiKeyPatch::from_json(&patch).map_err(ApiError::ValidationError)?;
+    if patch.changes_owner() {
+        creation.authorize_owner(&req, &admin_token)?;
+    }
+    plans::check_plan_exists(&app_data, patch.plan_name()).await?;
+    principals::check_principal_exists(&app_data, patch.principal_name()).await?;
+    check_quotas(&app_data, &key, &patch.set, &patch.unset).await?;
+    let versions = expected_versions(&req);
+    let result = app_data
+        .service
+        .apikey
+        .patch(&key, patch, versions.as_deref())
+        .await;
+    match result {
+        Ok(key) => Ok(HttpResponse::Ok().set(etag(&key)).json(Envelope::ok(key))),
         Err(e) => Err(e.into()),
     }
 }
 
 #[delete("/{key}")]
 async fn delete_apikey(
+    req: HttpRequest,
+    creation: web::Data<KeyCreation>,
+    admin_token: web::Data<AdminToken>,
     key: web::Path<String>,
     app_data: web::Data<crate::AppState>,
 ) -> Result<HttpResponse, JsonError> {
+    creation.authorize(&req, &admin_token)?;
     let result = app_data.service.apikey.delete_by_key(&key).await;
     match result {
         Ok(res) => {
-            if res.deleted_count == 0 {
-                Err(JsonError {
-                    msg: format!("Key not found: {}", key),
-                    status: 404,
-                    success: false,
-                })
+            if res == 0 {
+                Err(JsonError::new(
+                    StatusCode::NOT_FOUND,
+                    ErrorCode::ApikeyNotFound,
+                    format!("Key not found: {}", key),
+                ))
             } else {
-                Ok(HttpResponse::Ok().json(json!({
-                    "status": 200,
-                    "success": true,
-                    "payload": {
-                        "count": res.deleted_count,
-                    },
-                })))
+                Ok(HttpResponse::Ok().json(Envelope::ok(models::Deleted { count: res })))
             }
         }
         Err(e) => Err(e.into()),
@@ -0,0 +1,93 @@
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
+use lanther_common::Envelope;
+
+use super::AdminToken;
+use crate::error::{ApiError, FieldErrors, JsonError};
+use crate::models;
+
+#[get("")]
+pub async fn get_plans(app_data: web::Data<crate::AppState>) -> Result<HttpResponse, JsonError> {
+    let plans = app_data.service.plan.get_all().await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(plans)))
+}
+
+#[get("/{name}")]
+pub async fn get_plan(
+    name: web::Path<String>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    let plan = app_data.service.plan.get_by_name(&name).await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(plan)))
+}
+
+/// Create a plan, which only the admin may do as plans set the limits of their keys
+#[post("")]
+pub async fn create_plan(
+    req: HttpRequest,
+    body: web::Bytes,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    admin_token.authenticate(&req)?;
+    let plan = serde_json::from_slice(&body).map_err(ApiError::from)?;
+    let plan = models::PlanInput::from_json(&plan, None).map_err(ApiError::ValidationError)?;
+    let plan = app_data.service.plan.create(plan).await?;
+    Ok(HttpResponse::Created().json(Envelope::created(plan)))
+}
+
+#[put("/{name}")]
+pub async fn replace_plan(
+    req: HttpRequest,
+    name: web::Path<String>,
+    body: web::Bytes,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    admin_token.authenticate(&req)?;
+    let plan = serde_json::from_slice(&body).map_err(ApiError::from)?;
+    let plan =
+        models::PlanInput::from_json(&plan, Some(&name)).map_err(ApiError::ValidationError)?;
+    let plan = app_data.service.plan.replace(plan).await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(plan)))
+}
+
+/// Delete a plan, as long as no API key is on it
+#[delete("/{name}")]
+pub async fn delete_plan(
+    req: HttpRequest,
+    name: web::Path<String>,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    admin_token.authenticate(&req)?;
+    let keys = app_data.service.apikey.count_by_plan(&name).await?;
+    if keys > 0 {
+        return Err(ApiError::PlanInUse {
+            name: name.into_inner(),
+            keys,
+        }
+        .into());
+    }
+    app_data.service.plan.delete_by_name(&name).await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(models::Deleted { count: 1 })))
+}
+
+/// Check a plan an API key is being put on exists, reporting it as an invalid field otherwise
+pub async fn check_plan_exists(
+    app_data: &crate::AppState,
+    name: Option<&str>,
+) -> Result<Option<models::Plan>, ApiError> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    match app_data.service.plan.get_by_name(name).await {
+        Ok(plan) => Ok(Some(plan)),
+        Err(ApiError::PlanNotFound(_)) => {
+            let mut errors = FieldErrors::new();
+            errors.insert("plan".to_string(), "is not a known plan".to_string());
+            Err(ApiError::ValidationError(errors))
+        }
+        Err(e) => Err(e),
+    }
+}
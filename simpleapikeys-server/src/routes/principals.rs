@@ -0,0 +1,107 @@
+use actix_web::{delete, get, patch, post, web, HttpRequest, HttpResponse};
+use lanther_common::Envelope;
+
+use super::AdminToken;
+use crate::error::{ApiError, FieldErrors, JsonError};
+use crate::models;
+
+#[get("")]
+pub async fn get_principals(
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    let principals = app_data.service.principal.get_all().await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(principals)))
+}
+
+#[get("/{name}")]
+pub async fn get_principal(
+    name: web::Path<String>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    let principal = app_data.service.principal.get_by_name(&name).await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(principal)))
+}
+
+/// Create a principal, which only the admin may do, as principals can be disabled with all of
+/// their keys
+#[post("")]
+pub async fn create_principal(
+    req: HttpRequest,
+    body: web::Bytes,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    admin_token.authenticate(&req)?;
+    let principal = serde_json::from_slice(&body).map_err(ApiError::from)?;
+    let principal =
+        models::NewPrincipal::from_json(&principal).map_err(ApiError::ValidationError)?;
+    let principal = app_data.service.principal.create(principal).await?;
+    Ok(HttpResponse::Created().json(Envelope::created(principal)))
+}
+
+/// Apply a merge patch to a principal, which disables or enables all of its keys along with it
+#[patch("/{name}")]
+pub async fn patch_principal(
+    req: HttpRequest,
+    name: web::Path<String>,
+    body: web::Bytes,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    admin_token.authenticate(&req)?;
+    let patch = serde_json::from_slice(&body).map_err(ApiError::from)?;
+    let patch = models::PrincipalPatch::from_json(&patch).map_err(ApiError::ValidationError)?;
+    let disabled = patch.disabled();
+    let principal = app_data.service.principal.patch(&name, patch).await?;
+    match disabled {
+        Some(true) => log::warn!("Disabled principal {} and its API keys", principal.name),
+        Some(false) => log::info!("Enabled principal {} and its API keys", principal.name),
+        None => {}
+    }
+    Ok(HttpResponse::Ok().json(Envelope::ok(principal)))
+}
+
+/// Delete a principal, as long as no API key acts for it
+#[delete("/{name}")]
+pub async fn delete_principal(
+    req: HttpRequest,
+    name: web::Path<String>,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    admin_token.authenticate(&req)?;
+    let keys = app_data.service.apikey.count_by_principal(&name).await?;
+    if keys > 0 {
+        return Err(ApiError::PrincipalInUse {
+            name: name.into_inner(),
+            keys,
+        }
+        .into());
+    }
+    app_data.service.principal.delete_by_name(&name).await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(models::Deleted { count: 1 })))
+}
+
+/// Check a principal an API key is being given to exists, reporting it as an invalid field
+/// otherwise
+pub async fn check_principal_exists(
+    app_data: &crate::AppState,
+    name: Option<&str>,
+) -> Result<(), ApiError> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(()),
+    };
+    match app_data.service.principal.get_by_name(name).await {
+        Ok(_) => Ok(()),
+        Err(ApiError::PrincipalNotFound(_)) => {
+            let mut errors = FieldErrors::new();
+            errors.insert(
+                "principal".to_string(),
+                "is not a known principal".to_string(),
+            );
+            Err(ApiError::ValidationError(errors))
+        }
+        Err(e) => Err(e),
+    }
+}
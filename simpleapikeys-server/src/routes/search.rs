@@ -0,0 +1,25 @@
+use std::collections::HashMap;
+
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use lanther_common::Envelope;
+
+use super::{AdminToken, KeyCreation};
+use crate::error::{ApiError, JsonError};
+use crate::models::search::Search;
+
+/// Search API keys by prefixes of their key, and by words in their owner, name and labels
+///
+/// Takes the search as `q`, along with `page` and `per_page`.
+#[get("/search")]
+pub async fn search_apikeys(
+    req: HttpRequest,
+    creation: web::Data<KeyCreation>,
+    admin_token: web::Data<AdminToken>,
+    query: web::Query<HashMap<String, String>>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    creation.authorize(&req, &admin_token)?;
+    let search = Search::from_query(&query).map_err(ApiError::ValidationError)?;
+    let results = app_data.service.apikey.search(&search).await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(results)))
+}
@@ -0,0 +1,209 @@
+use actix_web::http::{header, StatusCode};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::{Duration, Utc};
+use lanther_common::Envelope;
+use mongodb::bson::doc;
+
+use super::dashboard::pages::escape;
+use crate::error::{ApiError, ErrorCode, JsonError};
+use crate::models;
+use crate::services::{Mailer, SignupService};
+
+/// Signups, which are only enabled when there is an SMTP server to send verification emails
+pub struct Signups {
+    pub service: SignupService,
+    pub mailer: Mailer,
+    /// The URL the server is reached at, which verification links point to
+    pub base_url: String,
+    /// How long a verification link works for
+    pub token_ttl: Duration,
+    /// How long signups are counted against the limits for
+    pub window: Duration,
+    pub per_ip: i64,
+    pub per_domain: i64,
+}
+
+fn verification_email(link: &str, expires_at: &str) -> String {
+    format!(
+        "Someone, hopefully you, signed up for an API key with this email address.\n\n\
+         Follow this link to verify it and get your API key:\n\n{}\n\n\
+         The link works until {}. If you did not sign up, you can safely ignore this email.\n",
+        link, expires_at
+    )
+}
+
+/// A bare HTML page, for those who follow the link of a verification email in a browser
+fn page(title: &str, body: &str) -> HttpResponse {
+    let html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n{body}</body>\n</html>\n",
+        title = escape(title),
+        body = body,
+    );
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html)
+}
+
+/// Whether a request comes from a browser, which gets a page rather than JSON
+fn wants_html(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// Sign up for an API key, sending a link to verify the email given with
+///
+/// Signups are limited for each IP address and each email domain, as they send emails to
+/// whichever address they are given.
+#[post("")]
+pub async fn create_signup(
+    req: HttpRequest,
+    body: web::Bytes,
+    signups: web::Data<Signups>,
+) -> Result<HttpResponse, JsonError> {
+    let signup = serde_json::from_slice(&body).map_err(ApiError::from)?;
+    let signup = models::NewSignup::from_json(&signup).map_err(ApiError::ValidationError)?;
+    // Behind a load balancer, this is the balancer's address rather than the client's
+    let ip = req
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default();
+
+    let limits = [
+        ("IP address", "ip", ip.as_str(), signups.per_ip),
+        (
+            "email domain",
+            "domain",
+            signup.email.domain(),
+            signups.per_domain,
+        ),
+    ];
+    for (by, field, value, limit) in limits.iter() {
+        let retry_after = signups
+            .service
+            .retry_after(field, value, *limit, signups.window)
+            .await?;
+        if let Some(retry_after) = retry_after {
+            return Err(ApiError::SignupRateLimited { by, retry_after }.into());
+        }
+    }
+
+    let token = SignupService::generate_token();
+    let expires_at = Utc::now() + signups.token_ttl;
+    signups
+        .service
+        .create(&token, &signup, &ip, expires_at)
+        .await?;
+
+    let link = format!(
+        "{}/signups/{}/verify",
+        signups.base_url.trim_end_matches('/'),
+        token
+    );
+    let sent = signups
+        .mailer
+        .send(
+            signup.email.as_ref(),
+            "Verify your email to get your API key",
+            verification_email(&link, &expires_at.to_rfc3339()),
+        )
+        .await;
+    if let Err(e) = sent {
+        // Nobody could ever verify it, so it must not count against the limits either
+        if let Err(e) = signups.service.delete(&token).await {
+            log::error!("Could not delete unsent signup: {}", e);
+        }
+        return Err(e.into());
+    }
+
+    let signup = models::Signup {
+        email: signup.email.to_string(),
+        expires_at,
+    };
+    Ok(HttpResponse::Accepted().json(Envelope::new(StatusCode::ACCEPTED, signup)))
+}
+
+/// Ask whoever follows a verification link to confirm it, without verifying anything yet
+///
+/// This is where verification links point to. Email security scanners fetch the links of the
+/// emails they scan, so following a link only shows a form, which posts the token to verify it.
+#[get("/{token}/verify")]
+pub async fn confirm_signup(token: web::Path<String>, signups: web::Data<Signups>) -> HttpResponse {
+    let action = format!(
+        "{}/signups/{}/verify",
+        signups.base_url.trim_end_matches('/'),
+        token
+    );
+    let body = format!(
+        "<p>Get the API key of your signup. It is only shown once, so keep it somewhere \
+         safe.</p>\n<form method=\"post\" action=\"{}\">\
+         <button type=\"submit\">Get my API key</button></form>\n",
+        escape(&action)
+    );
+    page("Verify your email", &body)
+}
+
+/// Verify the email of a signup, creating its API key
+///
+/// This is the only time the API key is shown, as a page to browsers and as JSON otherwise.
+#[post("/{token}/verify")]
+pub async fn verify_signup(
+    req: HttpRequest,
+    token: web::Path<String>,
+    signups: web::Data<Signups>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    let email = signups
+        .service
+        .claim(&token)
+        .await?
+        .ok_or(ApiError::SignupTokenInvalid)?;
+
+    let mut apikey = models::NewApiKey::new(models::Environment::default());
+    apikey.owner = Some(email.clone());
+    let created = match app_data.service.apikey.create(apikey, None).await {
+        Ok(created) => created,
+        Err(e) => {
+            if let Err(e) = signups.service.release(&token).await {
+                log::error!("Could not release signup: {}", e);
+            }
+            return Err(e.into());
+        }
+    };
+    let id = created.inserted_id.as_object_id().ok_or_else(|| {
+        JsonError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::InternalError,
+            "Insert failed",
+        )
+    })?;
+    let apikey = app_data.service.apikey.get_by_id(id).await?;
+    signups
+        .service
+        .complete(&token, &apikey.key.to_string())
+        .await?;
+
+    let record = models::AuditRecord {
+        action: "apikey.created",
+        key: Some(apikey.key.to_string()),
+        actor: "signup",
+        details: doc! { "email": email },
+    };
+    if let Err(e) = app_data.service.audit.record(record).await {
+        log::error!("Could not audit the creation of {}: {}", apikey.key, e);
+    }
+
+    if wants_html(&req) {
+        let body = format!(
+            "<p>Your API key is:</p>\n<p><code>{}</code></p>\n\
+             <p>It will not be shown again.</p>\n",
+            apikey.key
+        );
+        return Ok(page("Your API key", &body));
+    }
+    Ok(HttpResponse::Ok()
+        .set(super::etag(&apikey))
+        .json(Envelope::ok(apikey)))
+}
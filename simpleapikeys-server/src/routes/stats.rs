@@ -0,0 +1,18 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use lanther_common::Envelope;
+
+use super::{AdminToken, KeyCreation};
+use crate::error::JsonError;
+
+/// Count API keys by state, owner, plan and month of creation
+#[get("/stats")]
+pub async fn get_stats(
+    req: HttpRequest,
+    creation: web::Data<KeyCreation>,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    creation.authorize(&req, &admin_token)?;
+    let stats = app_data.service.apikey.stats().await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(stats)))
+}
@@ -0,0 +1,32 @@
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use lanther_common::Envelope;
+
+use super::{AdminToken, KeyCreation};
+use crate::error::JsonError;
+
+/// Count the active API keys of every owner, against how many each may have
+#[get("/usage")]
+pub async fn get_usage(
+    req: HttpRequest,
+    creation: web::Data<KeyCreation>,
+    admin_token: web::Data<AdminToken>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    creation.authorize(&req, &admin_token)?;
+    let usage = app_data.service.apikey.usage().await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(usage)))
+}
+
+/// Count the active API keys of an owner, against how many it may have
+#[get("/usage/{owner}")]
+pub async fn get_owner_usage(
+    req: HttpRequest,
+    creation: web::Data<KeyCreation>,
+    admin_token: web::Data<AdminToken>,
+    owner: web::Path<String>,
+    app_data: web::Data<crate::AppState>,
+) -> Result<HttpResponse, JsonError> {
+    creation.authorize(&req, &admin_token)?;
+    let usage = app_data.service.apikey.usage_of(&owner).await?;
+    Ok(HttpResponse::Ok().json(Envelope::ok(usage)))
+}
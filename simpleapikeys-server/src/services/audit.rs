@@ -0,0 +1,24 @@
+use lanther_store::Collection;
+
+use crate::error::ApiError;
+use crate::models;
+
+/// Service to keep an audit log of actions taken on API keys in the database
+#[derive(Clone)]
+pub struct AuditService {
+    collection: Collection,
+}
+
+impl AuditService {
+    pub fn new(collection: Collection) -> Self {
+        AuditService { collection }
+    }
+
+    /// Append a record to the audit log
+    pub async fn record(&self, record: models::AuditRecord) -> Result<(), ApiError> {
+        self.collection
+            .insert_one(record.to_bson_document())
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use std::convert::TryInto;
+
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use bson::spec::BinarySubtype;
+use bson::{Binary, Bson, Document};
+use hmac::{Hmac, Mac, NewMac};
+use rand::RngCore;
+use sha2::Sha256;
+
+/// Length in bytes of master and data keys, as used by AES-256-GCM
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+/// Length in bytes search terms are truncated to once hashed, which is plenty to tell them apart
+const TERM_LEN: usize = 16;
+
+#[derive(thiserror::Error, Debug)]
+pub enum EncryptionError {
+    #[error("No master key with version {0} is configured")]
+    UnknownKeyVersion(i64),
+    #[error("Could not decrypt {0}: it is corrupted or was encrypted with another key")]
+    Decrypt(String),
+    #[error("Encrypted field {0} is malformed")]
+    Malformed(String),
+}
+
+/// A master key, which only ever encrypts the data keys of each API key
+///
+/// A search key is derived from it to hash the terms API keys are searched by, so they can be
+/// looked up without storing the encrypted fields they come from in the clear.
+#[derive(Clone)]
+pub struct MasterKey {
+    version: i64,
+    cipher: Aes256Gcm,
+    search: Hmac<Sha256>,
+}
+
+impl MasterKey {
+    pub fn new(version: i64, key: &[u8; KEY_LEN]) -> Self {
+        let mut derive = hmac(key);
+        derive.update(b"lanther-search-terms");
+        let search_key = derive.finalize().into_bytes();
+        MasterKey {
+            version,
+            cipher: cipher(key),
+            search: hmac(&search_key),
+        }
+    }
+
+    fn hash_term(&self, term: &str) -> String {
+        let mut mac = self.search.clone();
+        mac.update(term.as_bytes());
+        let hash = mac.finalize().into_bytes();
+        base64::encode_config(&hash[..TERM_LEN], base64::URL_SAFE_NO_PAD)
+    }
+}
+
+/// A key generated for a single API key to encrypt its sensitive fields with
+pub struct DataKey {
+    cipher: Aes256Gcm,
+}
+
+/// The master keys data keys may be encrypted with
+///
+/// New data keys are always encrypted with the current master key. While rotating, the previous
+/// master key is kept to read the data keys that have not been re-encrypted yet.
+#[derive(Clone)]
+pub struct Keyring {
+    current: MasterKey,
+    previous: Option<MasterKey>,
+}
+
+impl Keyring {
+    pub fn new(current: MasterKey, previous: Option<MasterKey>) -> Self {
+        Keyring { current, previous }
+    }
+
+    pub fn current_version(&self) -> i64 {
+        self.current.version
+    }
+
+    /// Generate a data key, along with the fields that store it encrypted with the current master
+    /// key
+    pub fn generate(&self) -> (DataKey, Document) {
+        let mut key = [0u8; KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut key);
+        let data_key = DataKey {
+            cipher: cipher(&key),
+        };
+        (data_key, self.wrap(&key))
+    }
+
+    /// Read the data key of a stored API key, if it has one yet
+    pub fn data_key(&self, doc: &Document) -> Result<Option<DataKey>, EncryptionError> {
+        match self.data_key_bytes(doc)? {
+            Some(key) => Ok(Some(DataKey {
+                cipher: cipher(&key),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Encrypt the data key of a stored API key with the current master key
+    ///
+    /// Returns `None` when the API key has no data key yet.
+    pub fn rewrap(&self, doc: &Document) -> Result<Option<Document>, EncryptionError> {
+        Ok(self.data_key_bytes(doc)?.map(|key| self.wrap(&key)))
+    }
+
+    /// Decrypt the sensitive fields of a stored API key in place
+    ///
+    /// API keys stored before encryption was introduced have no data key, and are read as is.
+    pub fn decrypt_document(&self, doc: &mut Document) -> Result<(), EncryptionError> {
+        match self.data_key(doc)? {
+            Some(data_key) => data_key.decrypt_fields(doc),
+            None => Ok(()),
+        }
+    }
+
+    /// Hash the terms an API key is searched by with the current search key, to be stored
+    pub fn search_terms(&self, terms: &[String]) -> Vec<String> {
+        terms
+            .iter()
+            .map(|term| self.current.hash_term(term))
+            .collect()
+    }
+
+    /// Hash the terms of a search with every search key, so API keys whose terms were hashed with
+    /// the previous one are still found until they are re-encrypted
+    pub fn query_terms(&self, terms: &[String]) -> Vec<String> {
+        let mut hashed = self.search_terms(terms);
+        if let Some(previous) = &self.previous {
+            hashed.extend(terms.iter().map(|term| previous.hash_term(term)));
+        }
+        hashed
+    }
+
+    fn wrap(&self, key: &[u8]) -> Document {
+        let mut doc = Document::new();
+        doc.insert("data_key", seal(&self.current.cipher, "data_key", key));
+        doc.insert("key_version", self.current.version);
+        doc
+    }
+
+    fn data_key_bytes(&self, doc: &Document) -> Result<Option<Vec<u8>>, EncryptionError> {
+        let wrapped = match doc.get("data_key") {
+            Some(wrapped) => wrapped,
+            None => return Ok(None),
+        };
+        let version = doc
+            .get_i64("key_version")
+            .map_err(|_| EncryptionError::Malformed("key_version".to_string()))?;
+        let master = if version == self.current.version {
+            &self.current
+        } else {
+            self.previous
+                .as_ref()
+                .filter(|previous| previous.version == version)
+                .ok_or(EncryptionError::UnknownKeyVersion(version))?
+        };
+
+        let key = open(&master.cipher, "data_key", wrapped)?;
+        if key.len() != KEY_LEN {
+            return Err(EncryptionError::Malformed("data_key".to_string()));
+        }
+        Ok(Some(key))
+    }
+}
+
+impl DataKey {
+    /// Encrypt the sensitive fields among those of a document or a `$set` operator
+    ///
+    /// The name and every label value are encrypted on their own, bound to their path, so labels
+    /// can still be patched one by one. Fields already encrypted are left alone.
+    pub fn encrypt_fields(&self, doc: &mut Document) {
+        let paths: Vec<String> = doc.keys().cloned().collect();
+        for path in paths {
+            let value = doc.get_mut(&path).expect("path is one of the keys");
+            if path == "name" || path.starts_with("labels.") {
+                if let Bson::String(plain) = value {
+                    *value = seal(&self.cipher, &path, plain.as_bytes());
+                }
+            } else if path == "labels" {
+                if let Bson::Document(labels) = value {
+                    let names: Vec<String> = labels.keys().cloned().collect();
+                    for name in names {
+                        let value = labels.get_mut(&name).expect("name is one of the keys");
+                        if let Bson::String(plain) = value {
+                            let path = format!("labels.{}", name);
+                            *value = seal(&self.cipher, &path, plain.as_bytes());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Encrypt an opaque value, like a recorded response body, bound to its path
+    pub fn encrypt_bytes(&self, path: &str, plain: &[u8]) -> Bson {
+        seal(&self.cipher, path, plain)
+    }
+
+    pub fn decrypt_bytes(&self, path: &str, value: &Bson) -> Result<Vec<u8>, EncryptionError> {
+        open(&self.cipher, path, value)
+    }
+
+    fn decrypt_fields(&self, doc: &mut Document) -> Result<(), EncryptionError> {
+        if let Some(value) = doc.get_mut("name") {
+            *value = self.decrypt(value, "name")?;
+        }
+        if let Ok(labels) = doc.get_document_mut("labels") {
+            let names: Vec<String> = labels.keys().cloned().collect();
+            for name in names {
+                let value = labels.get_mut(&name).expect("name is one of the keys");
+                *value = self.decrypt(value, &format!("labels.{}", name))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decrypt(&self, value: &Bson, path: &str) -> Result<Bson, EncryptionError> {
+        match value {
+            Bson::Binary(_) => {
+                let plain = open(&self.cipher, path, value)?;
+                String::from_utf8(plain)
+                    .map(Bson::String)
+                    .map_err(|_| EncryptionError::Malformed(path.to_string()))
+            }
+            // Written before the API key had a data key
+            _ => Ok(value.clone()),
+        }
+    }
+}
+
+/// Whether a `$set` operator writes any field that must be encrypted
+pub fn has_encrypted_fields(set: &Document) -> bool {
+    set.keys().any(|path| is_encrypted_field(path))
+}
+
+/// Whether a path is to a field that is encrypted, or a document of them
+pub fn is_encrypted_field(path: &str) -> bool {
+    path == "name" || path == "labels" || path.starts_with("labels.")
+}
+
+fn cipher(key: &[u8]) -> Aes256Gcm {
+    Aes256Gcm::new_from_slice(key).expect("keys are KEY_LEN bytes long")
+}
+
+fn hmac(key: &[u8]) -> Hmac<Sha256> {
+    Hmac::new_from_slice(key).expect("HMAC takes keys of any length")
+}
+
+/// Encrypt a value bound to its path, storing the random nonce in front of the ciphertext
+fn seal(cipher: &Aes256Gcm, path: &str, plain: &[u8]) -> Bson {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let payload = Payload {
+        msg: plain,
+        aad: path.as_bytes(),
+    };
+    let ciphertext = cipher
+        .encrypt(&Nonce::from(nonce), payload)
+        .expect("AES-GCM encryption cannot fail for values this size");
+
+    let mut bytes = nonce.to_vec();
+    bytes.extend(ciphertext);
+    Bson::Binary(Binary {
+        subtype: BinarySubtype::Generic,
+        bytes,
+    })
+}
+
+fn open(cipher: &Aes256Gcm, path: &str, value: &Bson) -> Result<Vec<u8>, EncryptionError> {
+    let bytes = match value {
+        Bson::Binary(binary) if binary.bytes.len() > NONCE_LEN => &binary.bytes,
+        _ => return Err(EncryptionError::Malformed(path.to_string())),
+    };
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let nonce: [u8; NONCE_LEN] = nonce.try_into().expect("split at NONCE_LEN");
+    let payload = Payload {
+        msg: ciphertext,
+        aad: path.as_bytes(),
+    };
+    cipher
+        .decrypt(&Nonce::from(nonce), payload)
+        .map_err(|_| EncryptionError::Decrypt(path.to_string()))
+}
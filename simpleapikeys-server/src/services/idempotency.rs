@@ -0,0 +1,159 @@
+use chrono::{Duration, Utc};
+use lanther_store::Collection;
+use mongodb::bson::{doc, spec::BinarySubtype, Binary, Bson, Document};
+
+use super::Keyring;
+use crate::error::ApiError;
+use crate::models;
+
+/// Outcome of claiming an Idempotency-Key for a request
+#[derive(Debug)]
+pub enum Claim {
+    /// The key was not in use, so the request should be processed
+    Claimed,
+    /// The key was used by an identical request which has already completed
+    Replay(models::StoredResponse),
+    /// The key was used by an identical request which is still being processed
+    InProgress,
+    /// The key was used by a different request
+    Mismatch,
+}
+
+/// Service to record responses by Idempotency-Key in the database
+///
+/// Records are stored under the key scoped to whoever made the request, and expire after a
+/// configurable window, after which a key may be reused. Until its response is recorded, a claim
+/// only holds the key for a short lease, so a request that never completes, like one whose client
+/// disconnected, does not keep its retries out for the whole window.
+///
+/// Response bodies may hold the decrypted fields of API keys, so they are stored encrypted with a
+/// data key of their own, like API keys are.
+#[derive(Clone)]
+pub struct IdempotencyService {
+    collection: Collection,
+    keyring: Keyring,
+    window: Duration,
+    lease: Duration,
+}
+
+impl IdempotencyService {
+    pub fn new(
+        collection: Collection,
+        keyring: Keyring,
+        window: Duration,
+        lease: Duration,
+    ) -> Self {
+        IdempotencyService {
+            collection,
+            keyring,
+            window,
+            lease,
+        }
+    }
+
+    /// Claim a key for a request identified by its fingerprint
+    pub async fn claim(&self, key: &str, fingerprint: &str) -> Result<Claim, ApiError> {
+        // A second attempt is only needed when an expired record has not been removed yet
+        for _ in 0..2 {
+            let now = Utc::now();
+            let document = doc! {
+                "_id": key.to_string(),
+                "fingerprint": fingerprint.to_string(),
+                "created_at": now,
+                "expires_at": now + self.lease,
+            };
+            match self.collection.insert_one(document).await {
+                Ok(_) => return Ok(Claim::Claimed),
+                Err(e) if e.is_duplicate_key() => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            let filter = doc! {
+                "_id": key.to_string(),
+            };
+            let existing = match self.collection.find_one(filter).await? {
+                Some(existing) => existing,
+                // Removed since we tried to insert it, so try again
+                None => continue,
+            };
+
+            if *existing.get_datetime("expires_at")? <= now {
+                let filter = doc! {
+                    "_id": key.to_string(),
+                    "expires_at": { "$lte": now },
+                };
+                self.collection.delete_one(filter).await?;
+                continue;
+            }
+            if existing.get_str("fingerprint")? != fingerprint {
+                return Ok(Claim::Mismatch);
+            }
+            return match existing.get_document("response") {
+                Ok(response) => Ok(Claim::Replay(self.open(response)?)),
+                Err(_) => Ok(Claim::InProgress),
+            };
+        }
+
+        Ok(Claim::InProgress)
+    }
+
+    /// Record the response of a request for which a key was claimed, keeping it for the window
+    ///
+    /// Should the lease have run out and a retry claimed the key again, the first response
+    /// recorded is the one kept.
+    pub async fn complete(
+        &self,
+        key: &str,
+        response: &models::StoredResponse,
+    ) -> Result<(), ApiError> {
+        let filter = doc! {
+            "_id": key.to_string(),
+            "response": { "$exists": false },
+        };
+        let update = doc! {
+            "$set": {
+                "response": self.seal(response),
+                "expires_at": Utc::now() + self.window,
+            },
+        };
+        self.collection.update_one(filter, update).await?;
+        Ok(())
+    }
+
+    /// Release a claimed key without recording a response, so the request can be retried
+    pub async fn release(&self, key: &str) -> Result<(), ApiError> {
+        let filter = doc! {
+            "_id": key.to_string(),
+        };
+        self.collection.delete_one(filter).await?;
+        Ok(())
+    }
+
+    /// The document to record a response as, with its body encrypted
+    fn seal(&self, response: &models::StoredResponse) -> Document {
+        let (data_key, wrapped) = self.keyring.generate();
+        let mut document = response.to_bson_document();
+        document.insert("body", data_key.encrypt_bytes("body", &response.body));
+        for (field, value) in wrapped {
+            document.insert(field, value);
+        }
+        document
+    }
+
+    /// Read a recorded response, decrypting its body unless it was recorded before they were
+    fn open(&self, document: &Document) -> Result<models::StoredResponse, ApiError> {
+        let mut document = document.clone();
+        if let Some(data_key) = self.keyring.data_key(&document)? {
+            let body = document.get("body").cloned().unwrap_or(Bson::Null);
+            let bytes = data_key.decrypt_bytes("body", &body)?;
+            document.insert(
+                "body",
+                Bson::Binary(Binary {
+                    subtype: BinarySubtype::Generic,
+                    bytes,
+                }),
+            );
+        }
+        Ok(models::StoredResponse::from_bson_document(&document)?)
+    }
+}
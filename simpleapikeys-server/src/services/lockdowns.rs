@@ -0,0 +1,105 @@
+use chrono::Utc;
+use futures::StreamExt;
+use lanther_store::Collection;
+use mongodb::bson::doc;
+
+use crate::error::ApiError;
+use crate::models::lockdown::{owner_id, GLOBAL};
+use crate::models::{self, FromBsonDocument};
+
+/// Service to put lockdowns in place and lift them in the database
+///
+/// There is at most one global lockdown and one lockdown per owner, each stored under an `_id`
+/// naming what it locks down, so putting one in place again replaces it.
+#[derive(Clone)]
+pub struct LockdownService {
+    collection: Collection,
+}
+
+impl LockdownService {
+    pub fn new(collection: Collection) -> Self {
+        LockdownService { collection }
+    }
+
+    /// Lock down an owner, or everyone when none is given, replacing any lockdown of theirs
+    pub async fn put(
+        &self,
+        owner: Option<&str>,
+        lockdown: models::LockdownInput,
+    ) -> Result<models::Lockdown, ApiError> {
+        let id = match owner {
+            Some(owner) => owner_id(owner),
+            None => GLOBAL.to_string(),
+        };
+        let mut set = lockdown.to_bson_document();
+        set.insert("created_at", Utc::now());
+        if let Some(owner) = owner {
+            set.insert("owner", owner);
+        }
+
+        // Replacing in place, rather than deleting first, never leaves the owner unlocked
+        let filter = doc! { "_id": id.clone() };
+        let update = doc! { "$set": set.clone() };
+        let mut updated = self
+            .collection
+            .find_one_and_update(filter.clone(), update.clone())
+            .await?;
+        if updated.is_none() {
+            let mut document = set;
+            document.insert("_id", id);
+            updated = match self.collection.insert_one(document.clone()).await {
+                Ok(_) => Some(document),
+                // Someone else put it in place first, so theirs is replaced instead
+                Err(e) if e.is_duplicate_key() => {
+                    self.collection.find_one_and_update(filter, update).await?
+                }
+                Err(e) => return Err(e.into()),
+            };
+        }
+        let updated = updated.ok_or(ApiError::LockdownNotFound)?;
+        Ok(models::Lockdown::from_bson_document(&updated)?)
+    }
+
+    /// Lift the lockdown of an owner, or the global one when none is given
+    pub async fn lift(&self, owner: Option<&str>) -> Result<models::Lockdown, ApiError> {
+        let filter = match owner {
+            Some(owner) => doc! { "_id": owner_id(owner) },
+            None => doc! { "_id": GLOBAL },
+        };
+        let doc = self.collection.find_one(filter.clone()).await?;
+        let doc = doc.ok_or(ApiError::LockdownNotFound)?;
+        self.collection.delete_one(filter).await?;
+        Ok(models::Lockdown::from_bson_document(&doc)?)
+    }
+
+    /// Get every lockdown in place
+    pub async fn get_all(&self) -> Result<Vec<models::Lockdown>, ApiError> {
+        let mut cursor = self.collection.find(None).await?;
+        let mut result: Vec<models::Lockdown> = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            result.push(models::Lockdown::from_bson_document(&doc?)?);
+        }
+        Ok(result)
+    }
+
+    /// Whether the API keys of an owner, or with no owner, are locked down
+    ///
+    /// Both the global lockdown and that of the owner are read in a single query, as this is
+    /// checked on every verification.
+    pub async fn is_locked_down(&self, owner: Option<&str>) -> Result<bool, ApiError> {
+        let mut ids = vec![GLOBAL.to_string()];
+        if let Some(owner) = owner {
+            ids.push(owner_id(owner));
+        }
+        let mut cursor = self.collection.find(doc! { "_id": { "$in": ids } }).await?;
+        while let Some(doc) = cursor.next().await {
+            let lockdown = models::Lockdown::from_bson_document(&doc?)?;
+            let allowed = lockdown.owner.is_none()
+                && owner.is_some_and(|owner| lockdown.allow.iter().any(|a| a == owner));
+            if !allowed {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+use actix_web::web;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+
+use crate::error::ApiError;
+
+/// Sends emails through an SMTP server
+#[derive(Clone)]
+pub struct Mailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl Mailer {
+    /// A mailer for the SMTP server at `host`, securing the connection with `starttls`, `tls`, or
+    /// not at all with `none`
+    pub fn new(
+        host: &str,
+        port: u16,
+        security: &str,
+        credentials: Option<(String, String)>,
+        timeout: Duration,
+        from: Mailbox,
+    ) -> Result<Self, String> {
+        let builder = match security {
+            "starttls" => SmtpTransport::starttls_relay(host),
+            "tls" => SmtpTransport::relay(host),
+            "none" => Ok(SmtpTransport::builder_dangerous(host)),
+            _ => return Err("smtp.security must be one of starttls, tls or none".to_string()),
+        }
+        .map_err(|e| format!("smtp.host `{}` cannot be used: {}", host, e))?;
+        let mut builder = builder.port(port).timeout(Some(timeout));
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(Mailer {
+            transport: builder.build(),
+            from,
+        })
+    }
+
+    /// Send a plain text email, waiting for the SMTP server to accept it
+    pub async fn send(&self, to: &str, subject: &str, body: String) -> Result<(), ApiError> {
+        let to: Mailbox = to
+            .parse()
+            .map_err(|e| ApiError::EmailUnavailable(format!("invalid recipient: {}", e)))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(|e| ApiError::EmailUnavailable(e.to_string()))?;
+        let transport = self.transport.clone();
+        // The transport blocks while talking to the SMTP server, which must not stall the server
+        web::block(move || transport.send(&message))
+            .await
+            .map(|_| ())
+            .map_err(|e| ApiError::EmailUnavailable(e.to_string()))
+    }
+}
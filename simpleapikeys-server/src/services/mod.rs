@@ -1,80 +1,743 @@
+use bson::document::ValueAccessError;
 use bson::oid::ObjectId;
 use chrono::Utc;
 use futures::StreamExt;
-use mongodb::results::{DeleteResult, InsertOneResult, UpdateResult};
-use mongodb::{bson::doc, Collection};
+use lanther_store::{Collection, InsertOneResult};
+use mongodb::{bson::doc, bson::Bson, bson::Document};
 
 use super::error::ApiError;
-use super::models;
+use super::models::{self, FromBsonDocument, ToBsonDocument};
+use super::settings::QuotaSettings;
 
-/// Service to operate on API keys in MongoDB
+mod audit;
+mod encryption;
+mod idempotency;
+mod lockdowns;
+mod mailer;
+mod notifications;
+mod plans;
+mod principals;
+mod signups;
+
+pub use audit::AuditService;
+pub use encryption::{EncryptionError, Keyring, MasterKey, KEY_LEN};
+pub use idempotency::{Claim, IdempotencyService};
+pub use lockdowns::LockdownService;
+pub use mailer::Mailer;
+pub use notifications::Notifier;
+pub use plans::PlanService;
+pub use principals::PrincipalService;
+pub use signups::SignupService;
+
+/// Service to operate on API keys in the database
+///
+/// The name and labels of API keys are encrypted at rest with a data key generated for each API
+/// key, which is in turn stored encrypted with a master key from the keyring.
 #[derive(Clone)]
 pub struct ApiKeyService {
     collection: Collection,
+    keyring: Keyring,
+    quotas: QuotaSettings,
+}
+
+/// A filter matching the API keys that are active as of now: neither disabled, which revoked keys
+/// also are, nor expired
+fn active() -> Document {
+    doc! {
+        "disabled": false,
+        "$or": [
+            { "expires_at": { "$exists": false } },
+            { "expires_at": { "$gt": Utc::now() } },
+        ],
+    }
+}
+
+/// Check `needed` more active API keys fit under a limit, besides those already `active`
+fn check_quota(
+    active: i64,
+    needed: i64,
+    limit: i64,
+    holder: impl FnOnce() -> String,
+) -> Result<(), ApiError> {
+    if active + needed > limit {
+        return Err(ApiError::KeyQuotaExceeded {
+            holder: holder(),
+            limit,
+        });
+    }
+    Ok(())
+}
+
+/// A `$group` stage counting API keys by the state they are in, as of now
+///
+/// States are checked in order, so a revoked key is never also counted as disabled, nor a
+/// disabled key as expired.
+fn by_state() -> Document {
+    doc! {
+        "$group": {
+            "_id": {
+                "$switch": {
+                    "branches": [
+                        {
+                            "case": { "$eq": [{ "$type": "$revoked_at" }, "date"] },
+                            "then": "revoked",
+                        },
+                        { "case": { "$eq": ["$disabled", true] }, "then": "disabled" },
+                        {
+                            "case": {
+                                "$and": [
+                                    { "$eq": [{ "$type": "$expires_at" }, "date"] },
+                                    { "$lte": ["$expires_at", Utc::now()] },
+                                ],
+                            },
+                            "then": "expired",
+                        },
+                    ],
+                    "default": "active",
+                },
+            },
+            "count": { "$sum": 1 },
+        },
+    }
+}
+
+/// What re-encrypting every API key with the current master key amounted to
+#[derive(Debug, Default)]
+pub struct Reencrypted {
+    /// API keys whose data key was encrypted with a previous master key
+    pub rotated: u64,
+    /// API keys stored before encryption was introduced, which had no data key yet
+    pub encrypted: u64,
+    /// API keys already encrypted with the current master key, which were not searchable yet
+    pub indexed: u64,
+    /// API keys written to while being re-encrypted, to be picked up by a later run
+    pub skipped: u64,
 }
 
 impl ApiKeyService {
-    pub fn new(collection: Collection) -> Self {
-        ApiKeyService { collection }
+    pub fn new(collection: Collection, keyring: Keyring, quotas: QuotaSettings) -> Self {
+        ApiKeyService {
+            collection,
+            keyring,
+            quotas,
+        }
     }
 
-    /// Create an API key
-    pub async fn create(&self, apikey: models::NewApiKey) -> Result<InsertOneResult, ApiError> {
-        let document = doc! {
+    /// Create an API key on the plan it names, if any, which must be the one given
+    ///
+    /// Child keys are counted on the plan of their parent, which is the one to give for them.
+    ///
+    /// The key is refused with `ApiError::KeyQuotaExceeded` when its owner, or its plan, already
+    /// has as many active keys as it may have. Keys created at the same time may both be let in
+    /// under the last slot, as the check is not atomic with the insert.
+    pub async fn create(
+        &self,
+        apikey: models::NewApiKey,
+        plan: Option<&models::Plan>,
+    ) -> Result<InsertOneResult, ApiError> {
+        if let Some(owner) = apikey.owner.as_deref() {
+            self.check_owner_quota(owner, None).await?;
+        }
+        if let Some(plan) = plan {
+            self.check_plan_quota(plan, None, 1).await?;
+        }
+        self.insert(apikey).await
+    }
+
+    /// Replace an API key with a new one that has everything but the key itself in common with it,
+    /// then revoke it, along with its child keys
+    ///
+    /// The replacement takes the place of a key that was active, so it is not checked against
+    /// quotas. Revoked keys cannot be rotated, as there is nothing left to replace.
+    pub async fn rotate(&self, apikey: &models::ApiKey) -> Result<models::ApiKey, ApiError> {
+        if apikey.revoked_at.is_some() {
+            return Err(ApiError::ApiKeyRevoked);
+        }
+        let replacement = models::NewApiKey {
+            parent: apikey.parent,
+            owner: apikey.owner.clone(),
+            plan: apikey.plan.clone(),
+            principal: apikey.principal.clone(),
+            name: apikey.name.clone(),
+            labels: apikey.labels.clone(),
+            scopes: apikey.scopes.clone(),
+            expires_at: apikey.expires_at,
+            schedule: apikey.schedule.clone(),
+            limits: apikey.limits,
+            ..models::NewApiKey::new(apikey.environment)
+        };
+        let inserted = self.insert(replacement).await?;
+        let replacement = self.get_inserted(&inserted).await?;
+        self.revoke(&apikey.key.to_hyphenated().to_string(), "rotated")
+            .await?;
+        Ok(replacement)
+    }
+
+    /// Write a new API key, encrypting its sensitive fields
+    async fn insert(&self, apikey: models::NewApiKey) -> Result<InsertOneResult, ApiError> {
+        let (data_key, wrapped) = self.keyring.generate();
+        let mut document = doc! {
             "key": apikey.key.to_hyphenated().to_string(),
+            "environment": apikey.environment.as_str(),
             "updated_at": Utc::now(),
             "created_at": Utc::now(),
             "disabled": false,
+            "version": 1i64,
         };
-        let result = self.collection.insert_one(document, None).await?;
+        if let Some(parent) = apikey.parent {
+            document.insert("parent", parent.to_hyphenated().to_string());
+        }
+        if let Some(owner) = apikey.owner {
+            document.insert("owner", owner);
+        }
+        if let Some(plan) = apikey.plan {
+            document.insert("plan", plan);
+        }
+        if let Some(principal) = apikey.principal {
+            document.insert("principal", principal);
+        }
+        if let Some(name) = apikey.name {
+            document.insert("name", name);
+        }
+        if !apikey.labels.is_empty() {
+            let labels: Document = apikey
+                .labels
+                .into_iter()
+                .map(|(label, value)| (label, Bson::String(value)))
+                .collect();
+            document.insert("labels", labels);
+        }
+        if !apikey.scopes.is_empty() {
+            document.insert("scopes", apikey.scopes);
+        }
+        if let Some(expires_at) = apikey.expires_at {
+            document.insert("expires_at", expires_at);
+        }
+        if let Some(schedule) = apikey.schedule {
+            document.insert("schedule", models::schedule::to_bson(&schedule));
+        }
+        let limits = apikey.limits.to_bson_document();
+        if !limits.is_empty() {
+            document.insert("limits", limits);
+        }
+        let terms = self.keyring.search_terms(&models::search::terms(&document));
+        document.insert("search_terms", terms);
+        data_key.encrypt_fields(&mut document);
+        document.extend(wrapped);
+        let result = self.collection.insert_one(document).await?;
         Ok(result)
     }
 
-    /// Update an API key given the key itself
-    pub async fn update(&self, apikey: models::UpdateApiKey) -> Result<UpdateResult, ApiError> {
+    /// Update an API key given the key itself, returning the key as written
+    ///
+    /// When `versions` is given, the update is only applied if the stored version is one of them,
+    /// otherwise `ApiError::VersionMismatch` is returned.
+    pub async fn update(
+        &self,
+        apikey: models::UpdateApiKey,
+        versions: Option<&[i64]>,
+    ) -> Result<models::ApiKey, ApiError> {
+        let key = apikey.key.to_hyphenated().to_string();
+        let set = doc! {
+            "disabled": apikey.disabled,
+        };
+        self.apply(&key, set, Document::new(), versions).await
+    }
+
+    /// Apply a merge patch to an API key given the key itself, returning the key as written
+    ///
+    /// `versions` behaves as in `update`.
+    pub async fn patch(
+        &self,
+        key: &str,
+        patch: models::ApiKeyPatch,
+        versions: Option<&[i64]>,
+    ) -> Result<models::ApiKey, ApiError> {
+        self.apply(key, patch.set, patch.unset, versions).await
+    }
+
+    /// Revoke an API key for good, disabling it and recording why
+    ///
+    /// Returns `None` when the key was already revoked, so each key is only ever revoked once.
+    pub async fn revoke(
+        &self,
+        key: &str,
+        reason: &str,
+    ) -> Result<Option<models::ApiKey>, ApiError> {
         let filter = doc! {
-            "key": apikey.key.to_hyphenated().to_string(),
+            "key": key.to_string(),
+            "revoked_at": { "$exists": false },
         };
         let document = doc! {
             "$set": {
+                "disabled": true,
+                "revoked_at": Utc::now(),
+                "revocation_reason": reason.to_string(),
+                "updated_at": Utc::now(),
+            },
+            "$inc": {
+                "version": 1i64,
+            },
+        };
+        let doc = self
+            .collection
+            .find_one_and_update(filter, document)
+            .await?;
+        let apikey = match doc {
+            Some(doc) => self.decrypt(doc)?,
+            None => return Ok(None),
+        };
+
+        // Child keys may have been minted by whoever got hold of their parent
+        let children = doc! {
+            "parent": key.to_string(),
+            "revoked_at": { "$exists": false },
+        };
+        let revoke_children = doc! {
+            "$set": {
+                "disabled": true,
+                "revoked_at": Utc::now(),
+                "revocation_reason": "parent_revoked",
                 "updated_at": Utc::now(),
-                "disabled": apikey.disabled,
+            },
+            "$inc": {
+                "version": 1i64,
+            },
+        };
+        self.collection
+            .update_many(children, revoke_children)
+            .await?;
+        Ok(Some(apikey))
+    }
+
+    /// Atomically set and unset fields of an API key, bumping its version
+    ///
+    /// Revoked API keys can never be enabled again, and attempting to is rejected with
+    /// `ApiError::ApiKeyRevoked`.
+    async fn apply(
+        &self,
+        key: &str,
+        mut set: Document,
+        unset: Document,
+        versions: Option<&[i64]>,
+    ) -> Result<models::ApiKey, ApiError> {
+        let mut filter = doc! {
+            "key": key.to_string(),
+        };
+        if let Some(versions) = versions {
+            filter.insert("version", models::versions_filter(versions));
+        }
+        let enables = set.get_bool("disabled") == Ok(false);
+        let disables = set.get_bool("disabled") == Ok(true);
+        if enables {
+            filter.insert("revoked_at", doc! { "$exists": false });
+        }
+
+        let searched = set
+            .keys()
+            .chain(unset.keys())
+            .any(|path| path == "owner" || encryption::is_encrypted_field(path));
+        if encryption::has_encrypted_fields(&set) {
+            self.data_key(key).await?.encrypt_fields(&mut set);
+        }
+        set.insert("updated_at", Utc::now());
+        let mut document = doc! {
+            "$set": set,
+            "$inc": {
+                "version": 1i64,
+            },
+        };
+        // MongoDB rejects empty update operators
+        if !unset.is_empty() {
+            document.insert("$unset", unset);
+        }
+
+        let doc = self
+            .collection
+            .find_one_and_update(filter, document)
+            .await?;
+
+        match doc {
+            Some(doc) => {
+                if disables {
+                    self.disable_children(key).await?;
+                }
+                let mut doc = doc;
+                self.keyring.decrypt_document(&mut doc)?;
+                if searched {
+                    self.index(&doc).await?;
+                }
+                Ok(models::ApiKey::from_bson_document(&doc)?)
+            }
+            None => {
+                // Nothing matched, so the key does not exist, is revoked, or its version moved on
+                let apikey = self.get_by_key(key).await?;
+                if enables && apikey.revoked_at.is_some() {
+                    return Err(ApiError::ApiKeyRevoked);
+                }
+                Err(ApiError::VersionMismatch)
             }
+        }
+    }
+
+    /// Write the terms an API key is searched by, from its decrypted document
+    ///
+    /// Terms are only written for the version they were read from, so when another write got in
+    /// first, they are read again from the key as it now is.
+    async fn index(&self, doc: &Document) -> Result<(), ApiError> {
+        let key = doc.get_str("key")?.to_string();
+        let mut doc = doc.clone();
+        loop {
+            let filter = doc! {
+                "key": key.clone(),
+                "version": models::versions_filter(&[models::version(&doc)?]),
+            };
+            let terms = self.keyring.search_terms(&models::search::terms(&doc));
+            let result = self
+                .collection
+                .update_one(filter, doc! { "$set": { "search_terms": terms } })
+                .await?;
+            if result.matched_count > 0 {
+                return Ok(());
+            }
+            doc = match self
+                .collection
+                .find_one(doc! { "key": key.clone() })
+                .await?
+            {
+                Some(doc) => doc,
+                // Deleted meanwhile, so there is nothing left to search for
+                None => return Ok(()),
+            };
+            self.keyring.decrypt_document(&mut doc)?;
+        }
+    }
+
+    /// Disable the child keys of an API key, which are not enabled again along with it
+    async fn disable_children(&self, key: &str) -> Result<(), ApiError> {
+        let filter = doc! {
+            "parent": key.to_string(),
+            "disabled": false,
         };
-        let result = self.collection.update_one(filter, document, None).await?;
-        Ok(result)
+        let update = doc! {
+            "$set": {
+                "disabled": true,
+                "updated_at": Utc::now(),
+            },
+            "$inc": {
+                "version": 1i64,
+            },
+        };
+        self.collection.update_many(filter, update).await?;
+        Ok(())
+    }
+
+    /// Delete an API key given the key itself, along with its child keys
+    ///
+    /// Returns how many API keys were deleted, which is 0 when the key did not exist.
+    pub async fn delete_by_key(&self, key: &str) -> Result<i64, ApiError> {
+        let filter = doc! {
+            "key": key.to_string(),
+        };
+        let result = self.collection.delete_one(filter).await?;
+        if result.deleted_count == 0 {
+            return Ok(0);
+        }
+        let children = doc! {
+            "parent": key.to_string(),
+        };
+        let deleted = self.collection.delete_many(children).await?;
+        Ok(result.deleted_count + deleted.deleted_count)
     }
 
-    /// Delete an API key given the key itself
-    pub async fn delete_by_key(&self, key: &str) -> Result<DeleteResult, ApiError> {
+    /// Delete a child key, as long as it was delegated from the given parent
+    pub async fn delete_child(&self, parent: &str, key: &str) -> Result<i64, ApiError> {
         let filter = doc! {
             "key": key.to_string(),
+            "parent": parent.to_string(),
+        };
+        let result = self.collection.delete_one(filter).await?;
+        Ok(result.deleted_count)
+    }
+
+    /// Get the child keys delegated from an API key
+    pub async fn get_children(&self, parent: &str) -> Result<Vec<models::ApiKey>, ApiError> {
+        let filter = doc! {
+            "parent": parent.to_string(),
         };
-        let result = self.collection.delete_one(filter, None).await?;
+        let mut cursor = self.collection.find(filter).await?;
+        let mut result: Vec<models::ApiKey> = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            result.push(self.decrypt(doc?)?);
+        }
         Ok(result)
     }
 
     /// Get all existing API keys
     pub async fn get_all(&self) -> Result<Vec<models::ApiKey>, ApiError> {
-        let mut cursor = self.collection.find(None, None).await?;
+        let mut cursor = self.collection.find(None).await?;
         let mut result: Vec<models::ApiKey> = Vec::new();
         while let Some(doc) = cursor.next().await {
             log::debug!("doc: {:?}", doc);
-            result.push(models::ApiKey::from_bson_document(&doc?)?);
+            result.push(self.decrypt(doc?)?);
+        }
+        Ok(result)
+    }
+
+    /// Get the most recently created API keys, newest first, up to `limit` of them
+    pub async fn get_latest(&self, limit: i64) -> Result<Vec<models::ApiKey>, ApiError> {
+        let sort = doc! { "created_at": -1 };
+        let mut cursor = self
+            .collection
+            .find_sorted(Document::new(), sort, Some(limit))
+            .await?;
+        let mut result: Vec<models::ApiKey> = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            result.push(self.decrypt(doc?)?);
         }
         Ok(result)
     }
 
+    /// Count every API key
+    pub async fn count(&self) -> Result<i64, ApiError> {
+        Ok(self.collection.count_documents(Document::new()).await?)
+    }
+
+    /// Count API keys by state, as listed in `models::stats::STATES`
+    pub async fn count_by_state(&self) -> Result<Vec<models::Group>, ApiError> {
+        let pipeline = vec![by_state()];
+        let mut cursor = self.collection.aggregate(pipeline).await?;
+        let mut results: Vec<Bson> = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            results.push(Bson::Document(doc?));
+        }
+        Ok(models::stats::by_state(&results)?)
+    }
+
+    /// Count API keys by state, owner, plan and month of creation, in a single aggregation
+    pub async fn stats(&self) -> Result<models::ApiKeyStats, ApiError> {
+        let count_by = |field: Bson| {
+            vec![
+                doc! { "$group": { "_id": field, "count": { "$sum": 1 } } },
+                doc! { "$sort": { "count": -1, "_id": 1 } },
+            ]
+        };
+        let pipeline = vec![doc! {
+            "$facet": {
+                "by_state": [by_state()],
+                "by_owner": count_by(Bson::String("$owner".to_string())),
+                "by_plan": count_by(Bson::String("$plan".to_string())),
+                "by_month": [
+                    {
+                        "$group": {
+                            "_id": {
+                                "$dateToString": { "format": "%Y-%m", "date": "$created_at" },
+                            },
+                            "count": { "$sum": 1 },
+                        },
+                    },
+                    { "$sort": { "_id": 1 } },
+                ],
+            },
+        }];
+
+        let mut cursor = self.collection.aggregate(pipeline).await?;
+        match cursor.next().await {
+            Some(doc) => Ok(models::ApiKeyStats::from_bson_document(&doc?)?),
+            None => Ok(models::ApiKeyStats::default()),
+        }
+    }
+
+    /// Check an owner has room for one more active API key, besides `key` if it already has it
+    pub async fn check_owner_quota(&self, owner: &str, key: Option<&str>) -> Result<(), ApiError> {
+        let limit = match self.quotas.limit_for(owner) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let mut filter = doc! { "owner": owner };
+        if let Some(key) = key {
+            filter.insert("key", doc! { "$ne": key });
+        }
+        let active = self.count_active(filter).await?;
+        check_quota(active, 1, limit, || format!("Owner {}", owner))
+    }
+
+    /// Check a plan has room for `needed` more active API keys, besides `key` and its child keys
+    /// if they are already on it
+    ///
+    /// Child keys are counted on the plan of their parent, as that is the plan they are used on.
+    pub async fn check_plan_quota(
+        &self,
+        plan: &models::Plan,
+        key: Option<&str>,
+        needed: i64,
+    ) -> Result<(), ApiError> {
+        let limit = match plan.max_active_keys {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let mut filter = doc! {
+            "plan": plan.name.clone(),
+            "parent": { "$exists": false },
+        };
+        if let Some(key) = key {
+            filter.insert("key", doc! { "$ne": key });
+        }
+        let mut cursor = self.collection.find(filter.clone()).await?;
+        let mut parents: Vec<Bson> = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            parents.push(Bson::String(doc?.get_str("key")?.to_string()));
+        }
+        let mut children = doc! { "parent": { "$in": parents } };
+        if let Some(key) = key {
+            children.insert("key", doc! { "$ne": key });
+        }
+        let active = self.count_active(filter).await? + self.count_active(children).await?;
+        check_quota(active, needed, limit, || format!("Plan {}", plan.name))
+    }
+
+    /// Count the active child keys of an API key
+    pub async fn count_active_children(&self, key: &str) -> Result<i64, ApiError> {
+        self.count_active(doc! { "parent": key }).await
+    }
+
+    async fn count_active(&self, mut filter: Document) -> Result<i64, ApiError> {
+        filter.extend(active());
+        Ok(self.collection.count_documents(filter).await?)
+    }
+
+    /// How many active API keys an owner has, against how many it may have
+    pub async fn usage_of(&self, owner: &str) -> Result<models::OwnerUsage, ApiError> {
+        let mut filter = doc! { "owner": owner };
+        filter.extend(active());
+        Ok(models::OwnerUsage {
+            owner: owner.to_string(),
+            active: self.collection.count_documents(filter).await?,
+            limit: self.quotas.limit_for(owner),
+        })
+    }
+
+    /// How many active API keys every owner has, including owners given a limit of their own
+    /// but no keys yet, counted in a single aggregation
+    pub async fn usage(&self) -> Result<Vec<models::OwnerUsage>, ApiError> {
+        let mut filter = doc! { "owner": { "$exists": true } };
+        filter.extend(active());
+        let pipeline = vec![
+            doc! { "$match": filter },
+            doc! { "$group": { "_id": "$owner", "count": { "$sum": 1 } } },
+        ];
+        let mut cursor = self.collection.aggregate(pipeline).await?;
+        let mut results = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            results.push(Bson::Document(doc?));
+        }
+        let mut counts: std::collections::BTreeMap<String, i64> = self
+            .quotas
+            .owners
+            .keys()
+            .map(|owner| (owner.clone(), 0))
+            .collect();
+        for group in models::stats::groups(&results)? {
+            if let Some(owner) = group.name {
+                counts.insert(owner, group.count);
+            }
+        }
+        Ok(counts
+            .into_iter()
+            .map(|(owner, active)| models::OwnerUsage {
+                limit: self.quotas.limit_for(&owner),
+                owner,
+                active,
+            })
+            .collect())
+    }
+
+    /// Count the API keys on a plan
+    pub async fn count_by_plan(&self, plan: &str) -> Result<i64, ApiError> {
+        let filter = doc! {
+            "plan": plan.to_string(),
+        };
+        Ok(self.collection.count_documents(filter).await?)
+    }
+
+    /// Count the API keys acting for a principal
+    pub async fn count_by_principal(&self, principal: &str) -> Result<i64, ApiError> {
+        let filter = doc! {
+            "principal": principal.to_string(),
+        };
+        Ok(self.collection.count_documents(filter).await?)
+    }
+
+    /// Search API keys by prefixes of their key, and by words in their owner, name and labels
+    ///
+    /// Every API key sharing a term with the search is decrypted and scored, then a page of those
+    /// that match is returned, best matches first and newest first among equals.
+    pub async fn search(
+        &self,
+        search: &models::search::Search,
+    ) -> Result<models::ApiKeySearch, ApiError> {
+        let terms: Vec<Bson> = self
+            .keyring
+            .query_terms(&search.terms())
+            .into_iter()
+            .map(Bson::String)
+            .collect();
+        let filter = doc! {
+            "search_terms": { "$in": terms },
+        };
+        let mut cursor = self.collection.find(filter).await?;
+        let mut hits: Vec<models::SearchHit> = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            let apikey = self.decrypt(doc?)?;
+            let score = search.score(&apikey);
+            if score > 0 {
+                hits.push(models::SearchHit { score, apikey });
+            }
+        }
+        hits.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then(b.apikey.created_at.cmp(&a.apikey.created_at))
+        });
+
+        let total = hits.len();
+        let results = hits
+            .into_iter()
+            .skip((search.page - 1).saturating_mul(search.per_page))
+            .take(search.per_page)
+            .collect();
+        Ok(models::ApiKeySearch {
+            query: search.query.clone(),
+            total: total as i64,
+            page: search.page as i64,
+            per_page: search.per_page as i64,
+            results,
+        })
+    }
+
     /// Find an existing API key by the key itself
     pub async fn get_by_key(&self, key: &str) -> Result<models::ApiKey, ApiError> {
         let filter = doc! {
             "key": key.to_string(),
         };
-        let doc = self.collection.find_one(filter, None).await?;
+        let doc = self.collection.find_one(filter).await?;
         let result = doc.ok_or(ApiError::MongoDBEmptyResult)?;
-        let apikey = models::ApiKey::from_bson_document(&result)?;
+        self.decrypt(result)
+    }
 
-        Ok(apikey)
+    /// Get the API key an insert wrote, as it was written
+    pub async fn get_inserted(
+        &self,
+        inserted: &InsertOneResult,
+    ) -> Result<models::ApiKey, ApiError> {
+        // Inserts always generate an ObjectID, as API keys are never given an _id of their own
+        let id = inserted
+            .inserted_id
+            .as_object_id()
+            .ok_or(ApiError::InvalidFieldError(
+                ValueAccessError::UnexpectedType,
+            ))?;
+        self.get_by_id(id).await
     }
 
     /// Find an existing API key by the ObjectID
@@ -84,10 +747,114 @@ impl ApiKeyService {
         let filter = doc! {
             "_id": id,
         };
-        let doc = self.collection.find_one(filter, None).await?;
+        let doc = self.collection.find_one(filter).await?;
         let result = doc.ok_or(ApiError::MongoDBEmptyResult)?;
-        let apikey = models::ApiKey::from_bson_document(&result)?;
+        self.decrypt(result)
+    }
+
+    /// Re-encrypt the data keys of every API key with the current master key
+    ///
+    /// Only data keys are re-encrypted when rotating the master key, as the fields they encrypt
+    /// are left untouched. API keys stored before encryption was introduced get a data key, and
+    /// have their sensitive fields encrypted with it. Either way, the terms API keys are searched
+    /// by are hashed again with the current search key, and written for API keys stored before
+    /// they could be searched.
+    pub async fn reencrypt(&self) -> Result<Reencrypted, ApiError> {
+        let mut summary = Reencrypted::default();
+        let filter = doc! {
+            "$or": [
+                { "key_version": { "$ne": self.keyring.current_version() } },
+                { "search_terms": { "$exists": false } },
+            ],
+        };
+        let mut cursor = self.collection.find(filter).await?;
+        while let Some(doc) = cursor.next().await {
+            let doc = doc?;
+            let id = doc.get_object_id("_id")?.clone();
+            // Fields changed since they were read would be overwritten with stale values or terms
+            let version = models::versions_filter(&[models::version(&doc)?]);
+            let mut plain = doc.clone();
+            self.keyring.decrypt_document(&mut plain)?;
+            let terms = self.keyring.search_terms(&models::search::terms(&plain));
+
+            let (filter, mut set) = match self.keyring.rewrap(&doc)? {
+                Some(_) if doc.get_i64("key_version")? == self.keyring.current_version() => {
+                    summary.indexed += 1;
+                    let filter = doc! {
+                        "_id": id,
+                        "version": version.clone(),
+                    };
+                    (filter, Document::new())
+                }
+                Some(set) => {
+                    summary.rotated += 1;
+                    let filter = doc! {
+                        "_id": id,
+                        "key_version": doc.get_i64("key_version")?,
+                        "version": version.clone(),
+                    };
+                    (filter, set)
+                }
+                None => {
+                    summary.encrypted += 1;
+                    let (data_key, mut set) = self.keyring.generate();
+                    for field in ["name", "labels"] {
+                        if let Some(value) = doc.get(field) {
+                            set.insert(field, value.clone());
+                        }
+                    }
+                    data_key.encrypt_fields(&mut set);
+                    let filter = doc! {
+                        "_id": id,
+                        "data_key": { "$exists": false },
+                        "version": version.clone(),
+                    };
+                    (filter, set)
+                }
+            };
+            set.insert("search_terms", terms);
+
+            let result = self
+                .collection
+                .update_one(filter, doc! { "$set": set })
+                .await?;
+            if result.modified_count == 0 {
+                summary.skipped += 1;
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Get the data key of an API key, generating one for keys stored before encryption
+    async fn data_key(&self, key: &str) -> Result<encryption::DataKey, ApiError> {
+        let filter = doc! {
+            "key": key.to_string(),
+        };
+        let doc = self.collection.find_one(filter.clone()).await?;
+        let doc = doc.ok_or(ApiError::MongoDBEmptyResult)?;
+        if let Some(data_key) = self.keyring.data_key(&doc)? {
+            return Ok(data_key);
+        }
+
+        // Another write may generate one first, in which case theirs is kept
+        let (_, set) = self.keyring.generate();
+        let missing = doc! {
+            "key": key.to_string(),
+            "data_key": { "$exists": false },
+        };
+        self.collection
+            .update_one(missing, doc! { "$set": set })
+            .await?;
+        let doc = self.collection.find_one(filter).await?;
+        let doc = doc.ok_or(ApiError::MongoDBEmptyResult)?;
+        self.keyring
+            .data_key(&doc)?
+            .ok_or(ApiError::MongoDBEmptyResult)
+    }
 
-        Ok(apikey)
+    /// Read an API key from its stored document, decrypting its sensitive fields
+    fn decrypt(&self, mut doc: Document) -> Result<models::ApiKey, ApiError> {
+        self.keyring.decrypt_document(&mut doc)?;
+        Ok(models::ApiKey::from_bson_document(&doc)?)
     }
 }
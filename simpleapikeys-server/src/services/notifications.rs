@@ -0,0 +1,36 @@
+use actix_web::client::Client;
+use serde_json::Value;
+
+/// Sends notifications about API keys to a webhook, so their owners can be told
+#[derive(Clone)]
+pub struct Notifier {
+    webhook_url: Option<String>,
+}
+
+impl Notifier {
+    /// A notifier posting to a webhook, or dropping every notification when there is none
+    pub fn new(webhook_url: Option<String>) -> Self {
+        Notifier { webhook_url }
+    }
+
+    /// Post an event to the webhook in the background
+    ///
+    /// Notifications are best effort: the caller does not wait for them, and failures are only
+    /// logged.
+    pub fn notify(&self, event: Value) {
+        let url = match &self.webhook_url {
+            Some(url) => url.clone(),
+            None => {
+                log::debug!("No webhook configured, dropping notification: {}", event);
+                return;
+            }
+        };
+        actix_web::rt::spawn(async move {
+            match Client::default().post(url.as_str()).send_json(&event).await {
+                Ok(res) if res.status().is_success() => {}
+                Ok(res) => log::error!("Webhook {} responded with {}", url, res.status()),
+                Err(e) => log::error!("Could not notify webhook {}: {}", url, e),
+            }
+        });
+    }
+}
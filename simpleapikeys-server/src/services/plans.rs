@@ -0,0 +1,78 @@
+use chrono::Utc;
+use futures::StreamExt;
+use lanther_store::Collection;
+use mongodb::bson::doc;
+
+use crate::error::ApiError;
+use crate::models::{self, FromBsonDocument};
+
+/// Service to operate on usage plans in the database
+#[derive(Clone)]
+pub struct PlanService {
+    collection: Collection,
+}
+
+impl PlanService {
+    pub fn new(collection: Collection) -> Self {
+        PlanService { collection }
+    }
+
+    /// Create a plan, which must have a name no other plan has
+    pub async fn create(&self, plan: models::PlanInput) -> Result<models::Plan, ApiError> {
+        let mut document = plan.to_bson_document();
+        document.insert("created_at", Utc::now());
+        document.insert("updated_at", Utc::now());
+        match self.collection.insert_one(document).await {
+            Ok(_) => self.get_by_name(&plan.name).await,
+            Err(e) if e.is_duplicate_key() => Err(ApiError::PlanAlreadyExists(plan.name)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replace the limits and scopes of an existing plan
+    pub async fn replace(&self, plan: models::PlanInput) -> Result<models::Plan, ApiError> {
+        let filter = doc! {
+            "name": plan.name.clone(),
+        };
+        let mut set = plan.to_bson_document();
+        set.insert("updated_at", Utc::now());
+        let doc = self
+            .collection
+            .find_one_and_update(filter, doc! { "$set": set })
+            .await?;
+        let doc = doc.ok_or(ApiError::PlanNotFound(plan.name))?;
+        Ok(models::Plan::from_bson_document(&doc)?)
+    }
+
+    /// Delete a plan given its name
+    pub async fn delete_by_name(&self, name: &str) -> Result<(), ApiError> {
+        let filter = doc! {
+            "name": name.to_string(),
+        };
+        let result = self.collection.delete_one(filter).await?;
+        if result.deleted_count == 0 {
+            return Err(ApiError::PlanNotFound(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Get all existing plans
+    pub async fn get_all(&self) -> Result<Vec<models::Plan>, ApiError> {
+        let mut cursor = self.collection.find(None).await?;
+        let mut result: Vec<models::Plan> = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            result.push(models::Plan::from_bson_document(&doc?)?);
+        }
+        Ok(result)
+    }
+
+    /// Find an existing plan by its name
+    pub async fn get_by_name(&self, name: &str) -> Result<models::Plan, ApiError> {
+        let filter = doc! {
+            "name": name.to_string(),
+        };
+        let doc = self.collection.find_one(filter).await?;
+        let doc = doc.ok_or_else(|| ApiError::PlanNotFound(name.to_string()))?;
+        Ok(models::Plan::from_bson_document(&doc)?)
+    }
+}
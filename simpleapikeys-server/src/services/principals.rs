@@ -0,0 +1,90 @@
+use chrono::Utc;
+use futures::StreamExt;
+use lanther_store::Collection;
+use mongodb::bson::doc;
+
+use crate::error::ApiError;
+use crate::models::{self, FromBsonDocument};
+
+/// Service to operate on principals in the database
+#[derive(Clone)]
+pub struct PrincipalService {
+    collection: Collection,
+}
+
+impl PrincipalService {
+    pub fn new(collection: Collection) -> Self {
+        PrincipalService { collection }
+    }
+
+    /// Create a principal, which must have a name no other principal has
+    pub async fn create(
+        &self,
+        principal: models::NewPrincipal,
+    ) -> Result<models::Principal, ApiError> {
+        let mut document = principal.to_bson_document();
+        document.insert("created_at", Utc::now());
+        document.insert("updated_at", Utc::now());
+        match self.collection.insert_one(document).await {
+            Ok(_) => self.get_by_name(&principal.name).await,
+            Err(e) if e.is_duplicate_key() => Err(ApiError::PrincipalAlreadyExists(principal.name)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Apply a merge patch to a principal given its name, returning the principal as written
+    pub async fn patch(
+        &self,
+        name: &str,
+        patch: models::PrincipalPatch,
+    ) -> Result<models::Principal, ApiError> {
+        let filter = doc! {
+            "name": name.to_string(),
+        };
+        let mut set = patch.set;
+        set.insert("updated_at", Utc::now());
+        let mut document = doc! { "$set": set };
+        // MongoDB rejects empty update operators
+        if !patch.unset.is_empty() {
+            document.insert("$unset", patch.unset);
+        }
+        let doc = self
+            .collection
+            .find_one_and_update(filter, document)
+            .await?;
+        let doc = doc.ok_or_else(|| ApiError::PrincipalNotFound(name.to_string()))?;
+        Ok(models::Principal::from_bson_document(&doc)?)
+    }
+
+    /// Delete a principal given its name
+    pub async fn delete_by_name(&self, name: &str) -> Result<(), ApiError> {
+        let filter = doc! {
+            "name": name.to_string(),
+        };
+        let result = self.collection.delete_one(filter).await?;
+        if result.deleted_count == 0 {
+            return Err(ApiError::PrincipalNotFound(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Get all existing principals
+    pub async fn get_all(&self) -> Result<Vec<models::Principal>, ApiError> {
+        let mut cursor = self.collection.find(None).await?;
+        let mut result: Vec<models::Principal> = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            result.push(models::Principal::from_bson_document(&doc?)?);
+        }
+        Ok(result)
+    }
+
+    /// Find an existing principal by its name
+    pub async fn get_by_name(&self, name: &str) -> Result<models::Principal, ApiError> {
+        let filter = doc! {
+            "name": name.to_string(),
+        };
+        let doc = self.collection.find_one(filter).await?;
+        let doc = doc.ok_or_else(|| ApiError::PrincipalNotFound(name.to_string()))?;
+        Ok(models::Principal::from_bson_document(&doc)?)
+    }
+}
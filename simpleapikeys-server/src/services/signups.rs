@@ -0,0 +1,138 @@
+use chrono::{DateTime, Duration, Utc};
+use futures::StreamExt;
+use lanther_store::Collection;
+use mongodb::bson::{doc, Document};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::error::ApiError;
+use crate::models;
+
+/// Length in bytes of the tokens verification links carry
+const TOKEN_LEN: usize = 32;
+
+/// Service to keep the signups waiting for their email to be verified in the database
+///
+/// Only a digest of each verification token is stored, so a token cannot be read back from the
+/// database, only checked.
+#[derive(Clone)]
+pub struct SignupService {
+    collection: Collection,
+}
+
+/// The digest a verification token is stored as
+fn digest(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+impl SignupService {
+    pub fn new(collection: Collection) -> Self {
+        SignupService { collection }
+    }
+
+    /// Generate a token for a verification link, which cannot be guessed
+    pub fn generate_token() -> String {
+        let mut token = [0u8; TOKEN_LEN];
+        rand::thread_rng().fill_bytes(&mut token);
+        base64::encode_config(token, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Seconds until another signup is allowed for `value` of `field`, when `limit` signups were
+    /// already made for it within the last `window`
+    pub async fn retry_after(
+        &self,
+        field: &str,
+        value: &str,
+        limit: i64,
+        window: Duration,
+    ) -> Result<Option<u64>, ApiError> {
+        let now = Utc::now();
+        let mut filter = Document::new();
+        filter.insert(field, value);
+        filter.insert("created_at", doc! { "$gte": now - window });
+        let mut cursor = self.collection.find(filter).await?;
+        let mut created: Vec<DateTime<Utc>> = Vec::new();
+        while let Some(doc) = cursor.next().await {
+            created.push(*doc?.get_datetime("created_at")?);
+        }
+        if (created.len() as i64) < limit {
+            return Ok(None);
+        }
+
+        // Another signup is allowed once enough of them fall out of the window
+        created.sort();
+        let freed = created[created.len() - limit as usize] + window;
+        Ok(Some((freed - now).num_seconds().max(1) as u64))
+    }
+
+    /// Record a signup made from `ip`, to be verified with `token` until `expires_at`
+    pub async fn create(
+        &self,
+        token: &str,
+        signup: &models::NewSignup,
+        ip: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), ApiError> {
+        let document = doc! {
+            "_id": digest(token),
+            "email": signup.email.to_string(),
+            "domain": signup.email.domain(),
+            "ip": ip,
+            "created_at": Utc::now(),
+            "expires_at": expires_at,
+        };
+        self.collection.insert_one(document).await?;
+        Ok(())
+    }
+
+    /// Forget a signup, like one whose verification email could not be sent
+    pub async fn delete(&self, token: &str) -> Result<(), ApiError> {
+        let filter = doc! {
+            "_id": digest(token),
+        };
+        self.collection.delete_one(filter).await?;
+        Ok(())
+    }
+
+    /// Claim a signup that is neither verified nor expired, returning its email
+    ///
+    /// A signup can only be claimed once, so only one API key is ever created for it.
+    pub async fn claim(&self, token: &str) -> Result<Option<String>, ApiError> {
+        let filter = doc! {
+            "_id": digest(token),
+            "verified_at": { "$exists": false },
+            "expires_at": { "$gt": Utc::now() },
+        };
+        let update = doc! {
+            "$set": { "verified_at": Utc::now() },
+        };
+        match self.collection.find_one_and_update(filter, update).await? {
+            Some(doc) => Ok(Some(doc.get_str("email")?.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// Release a claimed signup whose API key could not be created, so it can be verified again
+    pub async fn release(&self, token: &str) -> Result<(), ApiError> {
+        let filter = doc! {
+            "_id": digest(token),
+        };
+        let update = doc! {
+            "$unset": { "verified_at": "" },
+        };
+        self.collection.update_one(filter, update).await?;
+        Ok(())
+    }
+
+    /// Record the API key created for a verified signup
+    pub async fn complete(&self, token: &str, key: &str) -> Result<(), ApiError> {
+        let filter = doc! {
+            "_id": digest(token),
+        };
+        let update = doc! {
+            "$set": { "key": key },
+        };
+        self.collection.update_one(filter, update).await?;
+        Ok(())
+    }
+}
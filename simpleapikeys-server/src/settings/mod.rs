@@ -0,0 +1,537 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::ArgMatches;
+use config::{Config, Environment, File};
+use serde::Deserialize;
+
+use crate::services::{Keyring, Mailer, MasterKey, KEY_LEN};
+
+pub use lanther_store::MongoSettings;
+
+/// Prefix of the environment variables that override the configuration file
+pub const ENV_PREFIX: &str = "LANTHER";
+
+#[derive(thiserror::Error, Debug)]
+pub enum SettingsError {
+    #[error("Could not load configuration: {0}")]
+    Load(#[from] config::ConfigError),
+    #[error("Could not read {key} from {}: {source}", path.display())]
+    SecretFile {
+        key: &'static str,
+        path: PathBuf,
+        source: io::Error,
+    },
+    #[error("Invalid configuration:\n  - {}", .0.join("\n  - "))]
+    Invalid(Vec<String>),
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ServerSettings {
+    pub address: Option<String>,
+    /// Seconds to let in-flight requests finish after a shutdown signal
+    pub shutdown_timeout: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MigrationSettings {
+    pub on_startup: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IdempotencySettings {
+    /// How long responses are replayed for, in seconds
+    pub window: i64,
+    /// How long a request holds its key while being processed, in seconds, after which a retry
+    /// may claim it again
+    pub lease: i64,
+}
+
+/// Master keys encrypting the data keys of API keys, as base64-encoded 32 byte keys
+///
+/// Rotating the master key bumps `key_version` by one, and keeps the old key as the previous one
+/// until every API key has been re-encrypted.
+#[derive(Debug, Default, Deserialize)]
+pub struct EncryptionSettings {
+    pub master_key: Option<String>,
+    pub master_key_file: Option<PathBuf>,
+    pub key_version: i64,
+    pub previous_master_key: Option<String>,
+    pub previous_master_key_file: Option<PathBuf>,
+}
+
+impl EncryptionSettings {
+    /// Decode the master keys, listing every one that is missing or invalid
+    pub fn keyring(&self) -> Result<Keyring, Vec<String>> {
+        let mut errors = Vec::new();
+        if self.key_version < 1 {
+            errors.push("encryption.key_version must be at least 1".to_string());
+        }
+        let current = match &self.master_key {
+            Some(key) => decode_key("encryption.master_key", key)
+                .map_err(|e| errors.push(e))
+                .ok(),
+            None => {
+                errors.push(missing(
+                    "encryption.master_key",
+                    "encryption.master_key_file",
+                ));
+                None
+            }
+        };
+        let previous = self.previous_master_key.as_ref().and_then(|key| {
+            decode_key("encryption.previous_master_key", key)
+                .map_err(|e| errors.push(e))
+                .ok()
+        });
+
+        match current {
+            Some(current) if errors.is_empty() => Ok(Keyring::new(
+                MasterKey::new(self.key_version, &current),
+                previous.map(|previous| MasterKey::new(self.key_version - 1, &previous)),
+            )),
+            _ => Err(errors),
+        }
+    }
+}
+
+/// Leak reports are only accepted from a reporter authenticating with this token
+#[derive(Debug, Default, Deserialize)]
+pub struct LeakSettings {
+    pub reporter_token: Option<String>,
+    pub reporter_token_file: Option<PathBuf>,
+}
+
+/// How many active API keys each owner may have, where no limit means unlimited
+#[derive(Debug, Default, Deserialize, Clone)]
+pub struct QuotaSettings {
+    /// The limit of every owner not given one of its own in `owners`
+    pub max_active_keys_per_owner: Option<i64>,
+    /// Limits of specific owners, by owner
+    #[serde(default)]
+    pub owners: BTreeMap<String, i64>,
+}
+
+impl QuotaSettings {
+    /// How many active API keys an owner may have, if there is a limit at all
+    pub fn limit_for(&self, owner: &str) -> Option<i64> {
+        self.owners
+            .get(owner)
+            .copied()
+            .or(self.max_active_keys_per_owner)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct NotificationSettings {
+    /// Where events about API keys, like their being leaked, are posted to
+    pub webhook_url: Option<String>,
+}
+
+/// Requests only ever allowed to the admin, which authenticates with this token
+#[derive(Debug, Default, Deserialize)]
+pub struct AdminSettings {
+    pub token: Option<String>,
+    pub token_file: Option<PathBuf>,
+    /// The proxy-server whose request log the admin dashboard shows the requests of a key from
+    pub proxy_url: Option<String>,
+}
+
+/// The SMTP server emails, like the verification emails of signups, are sent through
+///
+/// Signups are only enabled when a `host` is set.
+#[derive(Debug, Default, Deserialize)]
+pub struct SmtpSettings {
+    pub host: Option<String>,
+    pub port: u16,
+    /// How the connection is secured: `starttls`, `tls`, or `none` for a local test server
+    pub security: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub password_file: Option<PathBuf>,
+    /// The mailbox emails are sent from, like `Lanther <noreply@example.com>`
+    pub from: Option<String>,
+    /// Seconds to wait for the SMTP server before giving up on an email
+    pub timeout: u64,
+}
+
+impl SmtpSettings {
+    /// Build a mailer for the SMTP server, if any, listing every setting that is missing or invalid
+    pub fn mailer(&self) -> Result<Option<Mailer>, Vec<String>> {
+        let host = match &self.host {
+            Some(host) => host,
+            None => return Ok(None),
+        };
+        let mut errors = Vec::new();
+        let from = match self.from.as_deref().map(str::parse) {
+            Some(Ok(from)) => Some(from),
+            Some(Err(_)) => {
+                errors.push(
+                    "smtp.from must be a mailbox, like `Lanther <noreply@example.com>`".to_string(),
+                );
+                None
+            }
+            None => {
+                errors.push("smtp.from is required when smtp.host is set".to_string());
+                None
+            }
+        };
+        if self.password.is_some() && self.username.is_none() {
+            errors.push("smtp.password is set, but smtp.username is not".to_string());
+        }
+        let credentials = self
+            .username
+            .clone()
+            .map(|username| (username, self.password.clone().unwrap_or_default()));
+
+        match from {
+            Some(from) if errors.is_empty() => Mailer::new(
+                host,
+                self.port,
+                &self.security,
+                credentials,
+                Duration::from_secs(self.timeout),
+                from,
+            )
+            .map(Some)
+            .map_err(|e| vec![e]),
+            _ => Err(errors),
+        }
+    }
+}
+
+/// Self-service signups, which activate an API key once the email given is verified
+#[derive(Debug, Deserialize)]
+pub struct SignupSettings {
+    /// Whether anyone may create API keys with `POST /apikeys` and manage them, or only the admin
+    pub anonymous_creation: bool,
+    /// The URL the server is reached at, which verification links point to
+    pub base_url: Option<String>,
+    /// Hours a verification link works for
+    pub token_ttl: i64,
+    /// Seconds over which signups are counted against the limits below
+    pub window: i64,
+    /// Signups allowed from the same IP address in each window
+    pub per_ip: i64,
+    /// Signups allowed for emails of the same domain in each window
+    pub per_domain: i64,
+}
+
+/// Configuration of the simpleapikeys-server
+///
+/// Settings are layered, each layer overriding the ones before it: defaults, a TOML or YAML
+/// configuration file, environment variables prefixed with `LANTHER_`, and command line arguments.
+/// Nested settings are separated by a double underscore in environment variables, so
+/// `mongo.password_file` is set with `LANTHER_MONGO__PASSWORD_FILE`.
+#[derive(Debug, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub server: ServerSettings,
+    #[serde(default)]
+    pub mongo: MongoSettings,
+    pub migrations: MigrationSettings,
+    pub idempotency: IdempotencySettings,
+    #[serde(default)]
+    pub encryption: EncryptionSettings,
+    #[serde(default)]
+    pub leaks: LeakSettings,
+    #[serde(default)]
+    pub notifications: NotificationSettings,
+    #[serde(default)]
+    pub quotas: QuotaSettings,
+    #[serde(default)]
+    pub admin: AdminSettings,
+    #[serde(default)]
+    pub smtp: SmtpSettings,
+    pub signup: SignupSettings,
+}
+
+impl Settings {
+    pub fn load(matches: &ArgMatches) -> Result<Self, SettingsError> {
+        let mut builder = Config::builder()
+            .set_default("server.shutdown_timeout", 30)?
+            .set_default("mongo.connect_attempts", 5)?
+            .set_default("mongo.connect_backoff", 1)?
+            .set_default("migrations.on_startup", true)?
+            .set_default("idempotency.window", 86400)?
+            .set_default("idempotency.lease", 60)?
+            .set_default("encryption.key_version", 1)?
+            .set_default("smtp.port", 587)?
+            .set_default("smtp.security", "starttls")?
+            .set_default("smtp.timeout", 10)?
+            .set_default("signup.anonymous_creation", true)?
+            .set_default("signup.token_ttl", 24)?
+            .set_default("signup.window", 3600)?
+            .set_default("signup.per_ip", 5)?
+            .set_default("signup.per_domain", 20)?;
+
+        let path = matches
+            .value_of("config")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os(format!("{}_CONFIG", ENV_PREFIX)).map(PathBuf::from));
+        if let Some(path) = path {
+            builder = builder.add_source(File::from(path.as_path()).required(true));
+        }
+
+        builder = builder.add_source(
+            Environment::with_prefix(ENV_PREFIX)
+                .prefix_separator("_")
+                .separator("__"),
+        );
+
+        let overrides = [
+            ("ADDRESS", "server.address"),
+            ("mongo-db-address", "mongo.address"),
+            ("mongo-db", "mongo.database"),
+            ("idempotency-window", "idempotency.window"),
+        ];
+        for (arg, key) in overrides.iter() {
+            if let Some(value) = matches.value_of(arg) {
+                builder = builder.set_override(*key, value)?;
+            }
+        }
+        if matches.is_present("skip-migrations") {
+            builder = builder.set_override("migrations.on_startup", false)?;
+        }
+
+        let mut settings: Settings = builder.build()?.try_deserialize()?;
+        settings.mongo.password = secret(
+            "mongo.password",
+            settings.mongo.password.take(),
+            settings.mongo.password_file.take(),
+        )?;
+        settings.encryption.master_key = secret(
+            "encryption.master_key",
+            settings.encryption.master_key.take(),
+            settings.encryption.master_key_file.take(),
+        )?;
+        settings.encryption.previous_master_key = secret(
+            "encryption.previous_master_key",
+            settings.encryption.previous_master_key.take(),
+            settings.encryption.previous_master_key_file.take(),
+        )?;
+        settings.leaks.reporter_token = secret(
+            "leaks.reporter_token",
+            settings.leaks.reporter_token.take(),
+            settings.leaks.reporter_token_file.take(),
+        )?;
+        settings.admin.token = secret(
+            "admin.token",
+            settings.admin.token.take(),
+            settings.admin.token_file.take(),
+        )?;
+        settings.smtp.password = secret(
+            "smtp.password",
+            settings.smtp.password.take(),
+            settings.smtp.password_file.take(),
+        )?;
+        Ok(settings)
+    }
+
+    /// Check the settings the server needs to listen for requests are valid
+    pub fn validate_server(&self) -> Result<(), SettingsError> {
+        let mut errors = self.mongo_errors();
+        match &self.server.address {
+            None => errors.push(missing("server.address", "the ADDRESS argument")),
+            Some(address) if address.to_socket_addrs().is_err() => errors.push(format!(
+                "server.address `{}` is not a valid address to listen on, like 127.0.0.1:8083",
+                address
+            )),
+            Some(_) => {}
+        }
+        if self.idempotency.window <= 0 {
+            errors.push("idempotency.window must be a positive number of seconds".to_string());
+        }
+        if self.idempotency.lease <= 0 {
+            errors.push("idempotency.lease must be a positive number of seconds".to_string());
+        }
+        errors.extend(self.encryption.keyring().err().unwrap_or_default());
+        if self
+            .leaks
+            .reporter_token
+            .as_ref()
+            .is_some_and(|token| token.len() < 32)
+        {
+            errors.push("leaks.reporter_token must be at least 32 characters long".to_string());
+        }
+        match self.notifications.webhook_url.as_deref() {
+            Some(url) if !url.starts_with("http://") && !url.starts_with("https://") => errors
+                .push("notifications.webhook_url must be an http:// or https:// URL".to_string()),
+            _ => {}
+        }
+        if self
+            .quotas
+            .max_active_keys_per_owner
+            .is_some_and(|max| max <= 0)
+        {
+            errors.push(
+                "quotas.max_active_keys_per_owner must be a positive number of API keys"
+                    .to_string(),
+            );
+        }
+        for (owner, max) in &self.quotas.owners {
+            if *max <= 0 {
+                errors.push(format!(
+                    "quotas.owners.{} must be a positive number of API keys",
+                    owner
+                ));
+            }
+        }
+        if self
+            .admin
+            .token
+            .as_ref()
+            .is_some_and(|token| token.len() < 32)
+        {
+            errors.push("admin.token must be at least 32 characters long".to_string());
+        }
+        match self.admin.proxy_url.as_deref() {
+            Some(url) if !url.starts_with("http://") && !url.starts_with("https://") => {
+                errors.push("admin.proxy_url must be an http:// or https:// URL".to_string())
+            }
+            _ => {}
+        }
+        errors.extend(self.signup_errors());
+        invalid_if_any(errors)
+    }
+
+    /// Check the settings needed to apply migrations are valid
+    pub fn validate_migrate(&self) -> Result<(), SettingsError> {
+        invalid_if_any(self.mongo_errors())
+    }
+
+    /// Check the settings needed to re-encrypt API keys are valid
+    pub fn validate_reencrypt(&self) -> Result<(), SettingsError> {
+        let mut errors = self.mongo_errors();
+        errors.extend(self.encryption.keyring().err().unwrap_or_default());
+        invalid_if_any(errors)
+    }
+
+    /// Check the signup settings, which only matter when an SMTP server is set to send emails
+    fn signup_errors(&self) -> Vec<String> {
+        let mut errors = self.smtp.mailer().err().unwrap_or_default();
+        if self.smtp.host.is_none() {
+            return errors;
+        }
+        match self.signup.base_url.as_deref() {
+            None => errors.push("signup.base_url is required when smtp.host is set".to_string()),
+            Some(url) if !url.starts_with("http://") && !url.starts_with("https://") => {
+                errors.push("signup.base_url must be an http:// or https:// URL".to_string())
+            }
+            Some(_) => {}
+        }
+        let positive = [
+            ("signup.token_ttl", self.signup.token_ttl),
+            ("signup.window", self.signup.window),
+            ("signup.per_ip", self.signup.per_ip),
+            ("signup.per_domain", self.signup.per_domain),
+        ];
+        for (key, value) in positive.iter() {
+            if *value <= 0 {
+                errors.push(format!("{} must be a positive number", key));
+            }
+        }
+        // Signups are forgotten once their link expires, so they cannot be counted for longer
+        if self.signup.window > self.signup.token_ttl * 3600 {
+            errors.push("signup.window must not be longer than signup.token_ttl".to_string());
+        }
+        errors
+    }
+
+    fn mongo_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        match &self.mongo.address {
+            None => errors.push(missing("mongo.address", "--mongo-db-address")),
+            Some(address)
+                if !address.starts_with("mongodb://") && !address.starts_with("mongodb+srv://") =>
+            {
+                errors.push(format!(
+                    "mongo.address `{}` must be a mongodb:// or mongodb+srv:// connection string",
+                    address
+                ))
+            }
+            Some(_) => {}
+        }
+        if self
+            .mongo
+            .database
+            .as_deref()
+            .unwrap_or_default()
+            .is_empty()
+        {
+            errors.push(missing("mongo.database", "--mongo-db"));
+        }
+        if self.mongo.connect_attempts == 0 {
+            errors.push("mongo.connect_attempts must be at least 1".to_string());
+        }
+        if self.mongo.password.is_some() && self.mongo.username.is_none() {
+            errors.push("mongo.password is set, but mongo.username is not".to_string());
+        }
+        errors
+    }
+}
+
+/// Resolve a secret given either directly or as the path to a file holding it
+fn secret(
+    key: &'static str,
+    value: Option<String>,
+    file: Option<PathBuf>,
+) -> Result<Option<String>, SettingsError> {
+    match (value, file) {
+        (Some(_), Some(_)) => Err(SettingsError::Invalid(vec![format!(
+            "only one of {} and {}_file may be set",
+            key, key
+        )])),
+        (None, Some(path)) => read_secret(key, &path).map(Some),
+        (value, None) => Ok(value),
+    }
+}
+
+fn read_secret(key: &'static str, path: &Path) -> Result<String, SettingsError> {
+    let secret = fs::read_to_string(path).map_err(|source| SettingsError::SecretFile {
+        key,
+        path: path.to_path_buf(),
+        source,
+    })?;
+    // Files written by editors or `echo` usually end in a newline that is not part of the secret
+    Ok(secret.trim_end_matches(&['\r', '\n'][..]).to_string())
+}
+
+fn decode_key(key: &str, value: &str) -> Result<[u8; KEY_LEN], String> {
+    let invalid = || {
+        format!(
+            "{} must be {} random bytes encoded in base64, like the output of `openssl rand -base64 {}`",
+            key, KEY_LEN, KEY_LEN
+        )
+    };
+    let bytes = base64::decode(value.trim()).map_err(|_| invalid())?;
+    let mut result = [0u8; KEY_LEN];
+    if bytes.len() != KEY_LEN {
+        return Err(invalid());
+    }
+    result.copy_from_slice(&bytes);
+    Ok(result)
+}
+
+fn missing(key: &str, arg: &str) -> String {
+    format!(
+        "{} is required: set it in the configuration file, with {}_{}, or with {}",
+        key,
+        ENV_PREFIX,
+        key.to_uppercase().replace('.', "__"),
+        arg
+    )
+}
+
+fn invalid_if_any(errors: Vec<String>) -> Result<(), SettingsError> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(SettingsError::Invalid(errors))
+    }
+}